@@ -4,6 +4,7 @@ import (
 	"context"
 	"fmt"
 	"log"
+	"math"
 	"net"
 	"time"
 
@@ -112,31 +113,29 @@ func (s *server) GetTablePartitions(ctx context.Context, req *metav1.GetTablePar
 	}
 
 	defer conn.Release()
-	var rows pgx.Rows
+	// A partition may hold rows in the requested range unless it ends before it or starts after it. Partitions
+	// record their range to the second, so the start of the request is rounded down to match, and a side of the
+	// request left open is math.MinInt64 or math.MaxInt64.
+	query := `SELECT partition_name, size FROM meta.table_partitions
+		WHERE tenant_id = $1 AND table_name = $2`
+	args := []any{req.TenantId, req.TableName}
 	if req.TimeRange != nil {
-		rows, err = conn.Query(ctx,
-			`SELECT partition_name FROM meta.table_partitions
-                WHERE table_name = $1
-                    AND (
-                        start_time <= $2 AND end_time <= $3
-                        OR start_time <= $2 AND end_time >= $3
-                        OR start_time >= $2 AND end_time <= $3
-                        OR start_time >= $2 AND end_time >= $3
-                    )`,
-			req.TableName, req.TimeRange.StartTime, req.TimeRange.EndTime)
-		if err != nil {
-			return nil, status.New(codes.Internal, err.Error()).Err()
+		if req.TimeRange.StartTime != math.MinInt64 {
+			args = append(args, time.UnixMilli(req.TimeRange.StartTime).Truncate(time.Second))
+			query += fmt.Sprintf(" AND (end_time IS NULL OR end_time >= $%d)", len(args))
 		}
-	} else {
-		rows, err = conn.Query(ctx,
-			`SELECT partition_name, size FROM meta.table_partitions
-  		 WHERE table_name = $1`,
-			req.TableName)
-		if err != nil {
-			return nil, status.New(codes.Internal, err.Error()).Err()
+		if req.TimeRange.EndTime != math.MaxInt64 {
+			args = append(args, time.UnixMilli(req.TimeRange.EndTime))
+			query += fmt.Sprintf(" AND (start_time IS NULL OR start_time <= $%d)", len(args))
 		}
 	}
 
+	rows, err := conn.Query(ctx, query, args...)
+	if err != nil {
+		return nil, status.New(codes.Internal, err.Error()).Err()
+	}
+
+	defer rows.Close()
 	var partitions []*commonv1.Partition
 	for rows.Next() {
 		var name string
@@ -151,11 +150,13 @@ func (s *server) GetTablePartitions(ctx context.Context, req *metav1.GetTablePar
 		})
 	}
 
-	if len(partitions) == 0 {
+	// A time range may leave out every partition of a table that exists.
+	if len(partitions) == 0 && req.TimeRange == nil {
 		return nil, status.New(codes.NotFound, "table not found").Err()
 	}
 
 	return &metav1.GetTablePartitionsResponse{
+		TenantId:   req.TenantId,
 		TableName:  req.TableName,
 		Partitions: partitions,
 	}, nil
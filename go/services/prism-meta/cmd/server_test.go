@@ -0,0 +1,66 @@
+package cmd
+
+import (
+	"context"
+	"fmt"
+	"os"
+	"testing"
+	"time"
+
+	"github.com/jackc/pgx/v5/pgxpool"
+	"github.com/stretchr/testify/assert"
+	"github.com/stretchr/testify/require"
+
+	commonv1 "code.prism.io/proto/common/gen/go/prism/common/v1"
+	metav1 "code.prism.io/proto/rpc/gen/go/prism/meta/v1"
+)
+
+// newTestServer connects to the database given by PRISM_META_TEST_DATABASE_URL, which must have had its migrations
+// applied with `prism-meta migrate up`, and skips the test if it is not set.
+func newTestServer(t *testing.T) *server {
+	url := os.Getenv("PRISM_META_TEST_DATABASE_URL")
+	if url == "" {
+		t.Skip("PRISM_META_TEST_DATABASE_URL is not set")
+	}
+
+	pool, err := pgxpool.New(context.Background(), url)
+	require.NoError(t, err)
+	t.Cleanup(pool.Close)
+	return newServer(pool)
+}
+
+func TestGetTablePartitionsOfTenant(t *testing.T) {
+	s := newTestServer(t)
+	ctx := context.Background()
+
+	// Tenants unique to this run keep the test from reading partitions that an earlier run recorded.
+	run := time.Now().UnixNano()
+	acme := fmt.Sprintf("acme-%d", run)
+	globex := fmt.Sprintf("globex-%d", run)
+	start := time.Date(2023, 11, 1, 0, 0, 0, 0, time.UTC).UnixMilli()
+	for _, tenant := range []string{acme, globex} {
+		_, err := s.RecordNewPartition(ctx, &metav1.RecordNewPartitionRequest{
+			TenantId:  tenant,
+			TableName: "http_requests",
+			Partition: &commonv1.Partition{
+				Name: fmt.Sprintf("%s/http_requests/0.parquet", tenant),
+				Size: 1024,
+				TimeRange: &commonv1.TimeRange{
+					StartTime: start,
+					EndTime:   start + time.Hour.Milliseconds(),
+				},
+			},
+		})
+		require.NoError(t, err)
+	}
+
+	for _, tenant := range []string{acme, globex} {
+		res, err := s.GetTablePartitions(ctx, &metav1.GetTablePartitionsRequest{
+			TenantId:  tenant,
+			TableName: "http_requests",
+		})
+		require.NoError(t, err)
+		require.Len(t, res.Partitions, 1)
+		assert.Equal(t, fmt.Sprintf("%s/http_requests/0.parquet", tenant), res.Partitions[0].Name)
+	}
+}
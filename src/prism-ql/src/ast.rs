@@ -1,15 +1,132 @@
+use std::str::FromStr;
+
 use codespan::Span;
 
 #[derive(Clone, Debug)]
 pub struct Query {
     pub span: Span,
+    /// Whether the query asks for its plans to be explained rather than for its results.
+    pub explain: bool,
+    pub parameters: Vec<ParameterDeclaration>,
+    pub lets: Vec<LetStatement>,
     pub table: Identifier,
     pub pipelines: Vec<Pipeline>,
+    pub facet: Option<Facet>,
+    pub render: Option<Render>,
+}
+
+/// Splits the results of a query into one result set per column, each holding the most common values of that column.
+/// It can only be followed by `render`.
+#[derive(Clone, Debug)]
+pub struct Facet {
+    pub span: Span,
+    pub columns: Vec<Identifier>,
+}
+
+/// How the results of a query should be presented. It can only end a query.
+#[derive(Clone, Debug)]
+pub struct Render {
+    pub span: Span,
+    pub visualization: Identifier,
+    pub properties: Vec<Property>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ParameterDeclaration {
+    pub span: Span,
+    pub name: Identifier,
+    pub ty: Identifier,
+    pub default: Option<LiteralExpression>,
+}
+
+#[derive(Clone, Debug)]
+pub struct LetStatement {
+    pub span: Span,
+    pub name: Identifier,
+    pub table: TableExpression,
+}
+
+#[derive(Clone, Debug)]
+pub enum TableExpression {
+    Datatable(Datatable),
+    ExternalData(ExternalData),
+}
+
+#[derive(Clone, Debug)]
+pub struct Datatable {
+    pub span: Span,
+    pub columns: Vec<ColumnDeclaration>,
+    /// The values of every row, one after another.
+    pub values: Vec<LiteralExpression>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ExternalData {
+    pub span: Span,
+    pub columns: Vec<ColumnDeclaration>,
+    pub path: StringLiteral,
+    pub properties: Vec<Property>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ColumnDeclaration {
+    pub span: Span,
+    pub name: Identifier,
+    pub ty: Identifier,
+}
+
+#[derive(Clone, Debug)]
+pub struct Property {
+    pub span: Span,
+    pub name: Identifier,
+    pub value: StringLiteral,
 }
 
 #[derive(Clone, Debug)]
 pub enum Pipeline {
     Count(Count),
+    Where(Where),
+    Take(Take),
+    Summarize(Summarize),
+    Distinct(Distinct),
+    MakeSeries(MakeSeries),
+    Sort(Sort),
+    Extend(Extend),
+    Serialize(Serialize),
+    MvExpand(MvExpand),
+    Invoke(Invoke),
+    Lookup(Lookup),
+    Sample(Sample),
+    SampleDistinct(SampleDistinct),
+    GetSchema(GetSchema),
+    Search(Search),
+    Evaluate(Evaluate),
+    Reduce(Reduce),
+}
+
+impl Pipeline {
+    pub fn span(&self) -> Span {
+        match self {
+            Pipeline::Count(count) => count.span,
+            Pipeline::Where(where_) => where_.span,
+            Pipeline::Take(take) => take.span,
+            Pipeline::Summarize(summarize) => summarize.span,
+            Pipeline::Distinct(distinct) => distinct.span,
+            Pipeline::MakeSeries(series) => series.span,
+            Pipeline::Sort(sort) => sort.span,
+            Pipeline::Extend(extend) => extend.span,
+            Pipeline::Serialize(serialize) => serialize.span,
+            Pipeline::MvExpand(expand) => expand.span,
+            Pipeline::Invoke(invoke) => invoke.span,
+            Pipeline::Lookup(lookup) => lookup.span,
+            Pipeline::Sample(sample) => sample.span,
+            Pipeline::SampleDistinct(sample) => sample.span,
+            Pipeline::GetSchema(getschema) => getschema.span,
+            Pipeline::Search(search) => search.span,
+            Pipeline::Evaluate(evaluate) => evaluate.span,
+            Pipeline::Reduce(reduce) => reduce.span,
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -18,6 +135,157 @@ pub struct Count {
     pub by: Option<Expression>,
 }
 
+#[derive(Clone, Debug)]
+pub struct Where {
+    pub span: Span,
+    pub predicate: Expression,
+}
+
+#[derive(Clone, Debug)]
+pub struct Take {
+    pub span: Span,
+    pub count: IntegerLiteral,
+}
+
+#[derive(Clone, Debug)]
+pub struct Summarize {
+    pub span: Span,
+    pub aggregations: Vec<Aggregation>,
+    pub by: Vec<Expression>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Aggregation {
+    pub span: Span,
+    pub name: Option<Identifier>,
+    pub expr: Expression,
+}
+
+#[derive(Clone, Debug)]
+pub struct Distinct {
+    pub span: Span,
+    pub columns: Vec<Expression>,
+}
+
+#[derive(Clone, Debug)]
+pub struct MakeSeries {
+    pub span: Span,
+    pub aggregations: Vec<SeriesAggregation>,
+    pub on: Identifier,
+    pub from: Expression,
+    pub to: Expression,
+    pub step: TimespanLiteral,
+    pub by: Vec<Expression>,
+}
+
+#[derive(Clone, Debug)]
+pub struct SeriesAggregation {
+    pub span: Span,
+    pub aggregation: Aggregation,
+    pub default: Option<Expression>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Sort {
+    pub span: Span,
+    pub by: Vec<SortExpression>,
+}
+
+#[derive(Clone, Debug)]
+pub struct SortExpression {
+    pub span: Span,
+    pub expr: Expression,
+    pub descending: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct Extend {
+    pub span: Span,
+    pub columns: Vec<NamedExpression>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Serialize {
+    pub span: Span,
+    pub columns: Vec<NamedExpression>,
+}
+
+#[derive(Clone, Debug)]
+pub struct MvExpand {
+    pub span: Span,
+    pub column: Identifier,
+    pub ty: Option<Identifier>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Invoke {
+    pub span: Span,
+    pub call: CallExpression,
+}
+
+#[derive(Clone, Debug)]
+pub struct Lookup {
+    pub span: Span,
+    pub table: Identifier,
+    pub on: Vec<Identifier>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Sample {
+    pub span: Span,
+    pub count: IntegerLiteral,
+    /// Whether `count` is a percentage of rows rather than a number of rows.
+    pub percent: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct SampleDistinct {
+    pub span: Span,
+    pub count: IntegerLiteral,
+    pub column: Expression,
+}
+
+#[derive(Clone, Debug)]
+pub struct GetSchema {
+    pub span: Span,
+}
+
+#[derive(Clone, Debug)]
+pub struct Search {
+    pub span: Span,
+    pub term: StringLiteral,
+    /// The columns to search in; every string column when empty.
+    pub columns: Vec<Identifier>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Evaluate {
+    pub span: Span,
+    pub plugin: Identifier,
+    pub args: Vec<PluginArgument>,
+}
+
+#[derive(Clone, Debug)]
+pub struct PluginArgument {
+    pub span: Span,
+    /// The parameter the argument is given for, if it is named rather than positional.
+    pub name: Option<Identifier>,
+    pub value: Expression,
+}
+
+#[derive(Clone, Debug)]
+pub struct Reduce {
+    pub span: Span,
+    pub by: Expression,
+}
+
+#[derive(Clone, Debug)]
+pub struct NamedExpression {
+    pub span: Span,
+    pub name: Option<Identifier>,
+    pub expr: Expression,
+}
+
 #[derive(Clone, Debug)]
 pub struct Identifier {
     pub span: Span,
@@ -27,6 +295,22 @@ pub struct Identifier {
 #[derive(Clone, Debug)]
 pub enum Expression {
     Column(ColumnExpression),
+    Literal(LiteralExpression),
+    Call(CallExpression),
+    Binary(BinaryExpression),
+    Placeholder(PlaceholderExpression),
+}
+
+impl Expression {
+    pub fn span(&self) -> Span {
+        match self {
+            Expression::Column(column) => column.span,
+            Expression::Literal(literal) => literal.span,
+            Expression::Call(call) => call.span,
+            Expression::Binary(binary) => binary.span,
+            Expression::Placeholder(placeholder) => placeholder.span,
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -35,6 +319,112 @@ pub struct ColumnExpression {
     pub name: Identifier,
 }
 
+#[derive(Clone, Debug)]
+pub struct PlaceholderExpression {
+    pub span: Span,
+    pub name: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct LiteralExpression {
+    pub span: Span,
+    pub value: Literal,
+}
+
+#[derive(Clone, Debug)]
+pub enum Literal {
+    Integer(i64),
+    Real(f64),
+    String(String),
+    Bool(bool),
+    Timespan(chrono::Duration),
+}
+
+#[derive(Clone, Debug)]
+pub struct IntegerLiteral {
+    pub span: Span,
+    pub value: i64,
+}
+
+#[derive(Clone, Debug)]
+pub struct StringLiteral {
+    pub span: Span,
+    pub value: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct TimespanLiteral {
+    pub span: Span,
+    pub value: chrono::Duration,
+}
+
+#[derive(Clone, Debug)]
+pub struct CallExpression {
+    pub span: Span,
+    pub function: Identifier,
+    pub args: Vec<Expression>,
+}
+
+#[derive(Clone, Debug)]
+pub struct BinaryExpression {
+    pub span: Span,
+    pub op: BinaryOp,
+    pub lhs: Box<Expression>,
+    pub rhs: Box<Expression>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BinaryOp {
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+    And,
+    Or,
+    Plus,
+    Minus,
+    Multiply,
+    Divide,
+    StartsWith,
+}
+
 pub(crate) fn span(l: usize, r: usize) -> Span {
     Span::new(l as u32, r as u32)
 }
+
+pub(crate) fn binary(
+    l: usize,
+    lhs: Expression,
+    op: BinaryOp,
+    rhs: Expression,
+    r: usize,
+) -> Expression {
+    Expression::Binary(BinaryExpression {
+        span: span(l, r),
+        op,
+        lhs: Box::new(lhs),
+        rhs: Box::new(rhs),
+    })
+}
+
+pub(crate) fn timespan(literal: &str) -> Option<chrono::Duration> {
+    let split = literal
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(literal.len());
+    let (value, unit) = literal.split_at(split);
+    let value = i64::from_str(value).ok()?;
+    let millis_per_unit = match unit {
+        "d" => 24 * 60 * 60 * 1000,
+        "h" => 60 * 60 * 1000,
+        "m" => 60 * 1000,
+        "s" => 1000,
+        "ms" => 1,
+        _ => return None,
+    };
+    value
+        .checked_mul(millis_per_unit)
+        .map(chrono::Duration::milliseconds)
+        .filter(|duration| duration.num_nanoseconds().is_some())
+}
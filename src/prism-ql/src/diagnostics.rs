@@ -1,7 +1,9 @@
 use std::collections::HashMap;
 
-macro_rules! define_error {
-    ($name:ident, $code:ident, $message:expr) => {
+/// Defines the constructor of a diagnostic, documented with the explanation of its code.
+macro_rules! define_diagnostic {
+    ($constructor:ident, $name:ident, $code:ident, $message:expr, $explanation:expr) => {
+        #[doc = $explanation]
         pub fn $name(
             args: HashMap<&str, String>,
         ) -> ::codespan_reporting::diagnostic::Diagnostic<::codespan::FileId> {
@@ -10,15 +12,411 @@ macro_rules! define_error {
                 .map(|(k, v)| (k.to_string(), v.into()))
                 .collect();
             let msg = strfmt::strfmt($message, &map).expect("invalid format string for diagnostic");
-            ::codespan_reporting::diagnostic::Diagnostic::error()
+            ::codespan_reporting::diagnostic::Diagnostic::$constructor()
                 .with_code(stringify!($code))
                 .with_message(msg)
         }
     };
 }
 
-define_error!(
-    column_does_not_exist,
-    E0001,
-    "column `{column}` does not exist on table `{table}`"
-);
+macro_rules! define_error {
+    ($name:ident, $code:ident, $message:expr, $explanation:expr) => {
+        define_diagnostic!(error, $name, $code, $message, $explanation);
+    };
+}
+
+macro_rules! define_warning {
+    ($name:ident, $code:ident, $message:expr, $explanation:expr) => {
+        define_diagnostic!(warning, $name, $code, $message, $explanation);
+    };
+}
+
+macro_rules! define_note {
+    ($name:ident, $code:ident, $message:expr, $explanation:expr) => {
+        define_diagnostic!(note, $name, $code, $message, $explanation);
+    };
+}
+
+/// Defines every diagnostic, and [`EXPLANATIONS`] from the explanations they are defined with, so that no code can be
+/// defined without one.
+macro_rules! diagnostics {
+    ($($define:ident!($name:ident, $code:ident, $message:expr, $explanation:expr $(,)?);)*) => {
+        $($define!($name, $code, $message, $explanation);)*
+
+        /// The markdown explanation of each diagnostic code.
+        pub static EXPLANATIONS: &[(&str, &str)] = &[$((stringify!($code), $explanation)),*];
+    };
+}
+
+diagnostics! {
+    define_error!(
+        column_does_not_exist,
+        E0001,
+        "column `{column}` does not exist on table `{table}`",
+        include_str!("explanations/E0001.md")
+    );
+
+    define_error!(
+        unknown_function,
+        E0002,
+        "unknown function `{function}`",
+        include_str!("explanations/E0002.md")
+    );
+
+    define_error!(
+        wrong_argument_count,
+        E0003,
+        "function `{function}` expects {expected} argument(s), but {actual} were given",
+        include_str!("explanations/E0003.md")
+    );
+
+    define_error!(
+        invalid_argument,
+        E0004,
+        "argument to function `{function}` must be {expected}",
+        include_str!("explanations/E0004.md")
+    );
+
+    define_error!(
+        row_limit_exceeded,
+        E0005,
+        "query requests {requested} rows, but at most {maximum} rows may be returned",
+        include_str!("explanations/E0005.md")
+    );
+
+    define_error!(
+        misplaced_aggregate,
+        E0006,
+        "aggregate function `{function}` can only be used in `summarize`",
+        include_str!("explanations/E0006.md")
+    );
+
+    define_error!(
+        expected_aggregate,
+        E0007,
+        "`summarize` expects a call to an aggregate function",
+        include_str!("explanations/E0007.md")
+    );
+
+    define_error!(
+        expected_constant_timestamp,
+        E0008,
+        "`{clause}` must be a constant timestamp, such as `ago(1h)` or `now()`",
+        include_str!("explanations/E0008.md")
+    );
+
+    define_error!(
+        empty_series_range,
+        E0009,
+        "series range is empty; `from` must be earlier than `to`",
+        include_str!("explanations/E0009.md")
+    );
+
+    define_error!(
+        too_many_series_buckets,
+        E0010,
+        "series has {buckets} buckets, but at most {maximum} are allowed",
+        include_str!("explanations/E0010.md")
+    );
+
+    define_error!(
+        misplaced_window_function,
+        E0011,
+        "window function `{function}` can only be used in `extend` or `serialize`",
+        include_str!("explanations/E0011.md")
+    );
+
+    define_error!(
+        unordered_window_function,
+        E0012,
+        "window function `{function}` requires ordered rows; add `sort by` or `serialize` before it",
+        include_str!("explanations/E0012.md")
+    );
+
+    define_error!(
+        expected_array_column,
+        E0013,
+        "column `{column}` has type `{ty}`, but `mv-expand` requires an array column",
+        include_str!("explanations/E0013.md")
+    );
+
+    define_error!(
+        unknown_type,
+        E0014,
+        "unknown type `{ty}`",
+        include_str!("explanations/E0014.md")
+    );
+
+    define_error!(
+        recursive_function,
+        E0015,
+        "function `{function}` calls itself recursively",
+        include_str!("explanations/E0015.md")
+    );
+
+    define_error!(
+        invalid_function_body,
+        E0016,
+        "body of function `{function}` is invalid: {error}",
+        include_str!("explanations/E0016.md")
+    );
+
+    define_error!(
+        wrong_function_kind,
+        E0017,
+        "`{function}` is a {actual} function, but a {expected} function is required here",
+        include_str!("explanations/E0017.md")
+    );
+
+    define_error!(
+        missing_parameter,
+        E0018,
+        "no value was provided for query parameter `{parameter}`",
+        include_str!("explanations/E0018.md")
+    );
+
+    define_error!(
+        unexpected_parameter,
+        E0019,
+        "a value was provided for query parameter `{parameter}`, but the query does not use it",
+        include_str!("explanations/E0019.md")
+    );
+
+    define_error!(
+        mistyped_parameter,
+        E0020,
+        "query parameter `{parameter}` has type `{expected}`, but was given a value of type `{actual}`",
+        include_str!("explanations/E0020.md")
+    );
+
+    define_error!(
+        ragged_datatable,
+        E0021,
+        "datatable has {values} value(s), which is not a multiple of its {columns} column(s)",
+        include_str!("explanations/E0021.md")
+    );
+
+    define_error!(
+        mistyped_datatable_value,
+        E0022,
+        "column `{column}` is declared as `{expected}`, but was given a value of type `{actual}`",
+        include_str!("explanations/E0022.md")
+    );
+
+    define_error!(
+        unknown_table,
+        E0023,
+        "table `{table}` is not defined; define it with `let` before the query",
+        include_str!("explanations/E0023.md")
+    );
+
+    define_error!(
+        mismatched_lookup_column,
+        E0024,
+        "column `{column}` has type `{left}`, but has type `{right}` in `{table}`",
+        include_str!("explanations/E0024.md")
+    );
+
+    define_error!(
+        forbidden_external_data,
+        E0025,
+        "`externaldata` may not read `{path}`",
+        include_str!("explanations/E0025.md")
+    );
+
+    define_error!(
+        unreadable_external_data,
+        E0026,
+        "cannot read `{path}`: {error}",
+        include_str!("explanations/E0026.md")
+    );
+
+    define_error!(
+        unknown_data_format,
+        E0027,
+        "unknown data format `{format}`; expected `csv`, `json` or `parquet`",
+        include_str!("explanations/E0027.md")
+    );
+
+    define_error!(
+        duplicate_column,
+        E0028,
+        "column `{column}` is declared more than once",
+        include_str!("explanations/E0028.md")
+    );
+
+    define_error!(
+        unknown_property,
+        E0029,
+        "unknown property `{property}`; `{operator}` only accepts `{accepted}`",
+        include_str!("explanations/E0029.md")
+    );
+
+    define_error!(
+        invalid_sample_percentage,
+        E0030,
+        "`sample` percentage must be between 1 and 100, but is {percentage}",
+        include_str!("explanations/E0030.md")
+    );
+
+    define_error!(
+        incompatible_branches,
+        E0031,
+        "`{function}` has values of incompatible types `{first}` and `{second}`",
+        include_str!("explanations/E0031.md")
+    );
+
+    define_error!(
+        non_string_search_column,
+        E0032,
+        "`search` can only look in string columns, but `{column}` has type `{ty}`",
+        include_str!("explanations/E0032.md")
+    );
+
+    define_error!(
+        nothing_to_search,
+        E0033,
+        "table `{table}` has no string columns to search",
+        include_str!("explanations/E0033.md")
+    );
+
+    define_error!(
+        unknown_plugin,
+        E0034,
+        "unknown plugin `{plugin}`",
+        include_str!("explanations/E0034.md")
+    );
+
+    define_error!(
+        unknown_plugin_argument,
+        E0035,
+        "plugin `{plugin}` has no parameter `{argument}`",
+        include_str!("explanations/E0035.md")
+    );
+
+    define_error!(
+        too_many_plugin_arguments,
+        E0036,
+        "plugin `{plugin}` takes at most {expected} argument(s), but {actual} were given",
+        include_str!("explanations/E0036.md")
+    );
+
+    define_error!(
+        duplicate_plugin_argument,
+        E0037,
+        "argument `{argument}` of plugin `{plugin}` is given more than once",
+        include_str!("explanations/E0037.md")
+    );
+
+    define_error!(
+        missing_plugin_argument,
+        E0038,
+        "plugin `{plugin}` requires an argument for `{argument}`",
+        include_str!("explanations/E0038.md")
+    );
+
+    define_error!(
+        mistyped_plugin_argument,
+        E0039,
+        "argument `{argument}` of plugin `{plugin}` must be a constant of type `{expected}`",
+        include_str!("explanations/E0039.md")
+    );
+
+    define_error!(
+        plugin_failed,
+        E0040,
+        "plugin `{plugin}` failed: {message}",
+        include_str!("explanations/E0040.md")
+    );
+
+    define_error!(
+        non_string_reduce,
+        E0041,
+        "`reduce` can only cluster strings, but the values have type `{ty}`",
+        include_str!("explanations/E0041.md")
+    );
+
+    define_error!(
+        unknown_visualization,
+        E0042,
+        "unknown visualization `{visualization}`; expected `table`, `timechart` or `barchart`",
+        include_str!("explanations/E0042.md")
+    );
+
+    define_error!(
+        unrenderable_results,
+        E0043,
+        "`render {visualization}` needs {expected}",
+        include_str!("explanations/E0043.md")
+    );
+
+    define_error!(
+        duplicate_facet,
+        E0044,
+        "column `{column}` is faceted more than once",
+        include_str!("explanations/E0044.md")
+    );
+
+    define_error!(
+        non_constant_argument,
+        E0045,
+        "argument `{parameter}` of tabular function `{function}` must be a constant value",
+        include_str!("explanations/E0045.md")
+    );
+
+    define_error!(
+        mistyped_placeholder,
+        E0046,
+        "query parameter `{parameter}` is used as a `{expected}`, but was given a value of type `{actual}`",
+        include_str!("explanations/E0046.md")
+    );
+
+    define_warning!(
+        implicit_row_limit,
+        W0001,
+        "query has no aggregation or limit; results are truncated to {limit} rows",
+        include_str!("explanations/W0001.md")
+    );
+
+    define_warning!(
+        no_time_filter,
+        W0002,
+        "query has no filter on `{column}` and will scan all partitions of `{table}`",
+        include_str!("explanations/W0002.md")
+    );
+
+    define_warning!(
+        string_integer_comparison,
+        W0003,
+        "comparison of string column `{column}` to integer literal",
+        include_str!("explanations/W0003.md")
+    );
+
+    define_warning!(
+        sort_without_limit,
+        W0004,
+        "`sort` without a following `take` sorts every row of its input",
+        include_str!("explanations/W0004.md")
+    );
+
+    define_warning!(
+        parameter_shadows_column,
+        W0005,
+        "parameter `{parameter}` of function `{function}` has the same name as a column, which it hides",
+        include_str!("explanations/W0005.md")
+    );
+
+    define_warning!(
+        column_shadows_query_parameter,
+        W0006,
+        "query parameter `{parameter}` has the same name as a column; `{parameter}` refers to the column and `${parameter}` to the parameter",
+        include_str!("explanations/W0006.md")
+    );
+
+    define_note!(
+        exact_dcount,
+        N0001,
+        "`{function}` counts distinct values exactly, which can be expensive for columns with many distinct values",
+        include_str!("explanations/N0001.md")
+    );
+}
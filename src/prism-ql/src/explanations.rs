@@ -0,0 +1,219 @@
+//! Long-form explanations of diagnostic codes.
+//!
+//! Each code has a markdown file in `explanations/`, which the code is defined with in `diagnostics.rs`. It has at least
+//! one example that produces the code, in a code block tagged `pql,<code>`, and one that does not, in a code block
+//! tagged `pql`. The examples are lowered in tests against
+//! an `http_requests` table with the columns `timestamp`, `method`, `status`, `host`, `path`, `bytes` and `tags`, with
+//! a value given for the `min_bytes` query parameter. `externaldata` may read the files in `testdata/`, and `evaluate`
+//! may invoke a `top(column, n = 10)` plugin that returns the most frequent values of a column.
+
+use crate::diagnostics::EXPLANATIONS;
+
+/// Returns the markdown explanation of a diagnostic code, such as `E0001`.
+pub fn explain(code: &str) -> Option<&'static str> {
+    EXPLANATIONS
+        .iter()
+        .find(|(c, _)| c.eq_ignore_ascii_case(code))
+        .map(|(_, explanation)| *explanation)
+}
+
+/// Returns every diagnostic code.
+pub fn codes() -> impl Iterator<Item = &'static str> {
+    EXPLANATIONS.iter().map(|(code, _)| *code)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, path::PathBuf, sync::Arc};
+
+    use chrono::{DateTime, TimeZone, Utc};
+    use codespan::Files;
+    use datafusion::{
+        arrow::datatypes::{DataType, Field, Schema, TimeUnit},
+        logical_expr::{builder::LogicalTableSource, expr_fn, Expr, LogicalPlanBuilder},
+        scalar::ScalarValue,
+    };
+
+    use super::{codes, explain};
+    use crate::{
+        lower::{FunctionKind, Lowerer, QueryContext, StoredFunction},
+        parser::parse,
+        plugin::{EvaluatePlugin, PluginArguments, PluginError, PluginParameter},
+    };
+
+    struct TopPlugin;
+
+    impl EvaluatePlugin for TopPlugin {
+        fn parameters(&self) -> Vec<PluginParameter> {
+            vec![
+                PluginParameter::required("column", DataType::Utf8),
+                PluginParameter::optional("n", ScalarValue::Int64(Some(10))),
+            ]
+        }
+
+        fn evaluate(
+            &self,
+            builder: LogicalPlanBuilder,
+            args: &PluginArguments,
+        ) -> Result<LogicalPlanBuilder, PluginError> {
+            let Some(ScalarValue::Int64(Some(n))) = args.get("n") else {
+                unreachable!("arguments are checked against the parameters");
+            };
+            if *n <= 0 {
+                return Err(PluginError::new("`n` must be positive").with_argument("n"));
+            }
+
+            let column = args.get("column").unwrap().to_string();
+            Ok(builder
+                .aggregate(
+                    vec![expr_fn::col(column)],
+                    vec![expr_fn::count(Expr::Wildcard).alias("count")],
+                )?
+                .sort(vec![expr_fn::col("count").sort(false, false)])?
+                .limit(0, Some(*n as usize))?)
+        }
+    }
+
+    struct ExampleQueryContext;
+
+    impl QueryContext for ExampleQueryContext {
+        fn get_tenant_id(&self) -> &str {
+            "tenant"
+        }
+
+        fn get_query_start_time(&self) -> DateTime<Utc> {
+            Utc.with_ymd_and_hms(2023, 11, 1, 12, 0, 0).unwrap()
+        }
+
+        fn get_default_row_limit(&self) -> Option<usize> {
+            Some(1000)
+        }
+
+        fn get_max_row_limit(&self) -> Option<usize> {
+            Some(10_000)
+        }
+
+        fn get_function(&self, name: &str) -> Option<StoredFunction> {
+            let (kind, parameters, body) = match name {
+                "kilobytes" => (FunctionKind::Scalar, vec!["b"], "b / 1024"),
+                "fib" => (FunctionKind::Scalar, vec!["n"], "fib(n - 1)"),
+                "broken" => (FunctionKind::Scalar, vec!["b"], "b +"),
+                "get_requests" => (FunctionKind::Tabular, vec![], r#"where method == "GET""#),
+                "large_requests" => (FunctionKind::Tabular, vec!["min"], "where bytes >= min"),
+                "is_large" => (FunctionKind::Scalar, vec!["bytes"], "bytes >= 1048576"),
+                _ => return None,
+            };
+
+            Some(StoredFunction {
+                name: name.to_string(),
+                kind,
+                parameters: parameters.into_iter().map(String::from).collect(),
+                body: body.to_string(),
+            })
+        }
+
+        fn get_external_data_root(&self) -> Option<PathBuf> {
+            Some(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("testdata"))
+        }
+
+        fn get_plugin(&self, name: &str) -> Option<Arc<dyn EvaluatePlugin>> {
+            (name == "top").then(|| Arc::new(TopPlugin) as Arc<dyn EvaluatePlugin>)
+        }
+    }
+
+    /// Lowers an example and returns the codes of the diagnostics it produces.
+    fn diagnostic_codes(input: &str) -> Vec<String> {
+        let schema = Schema::new(vec![
+            Field::new(
+                "timestamp",
+                DataType::Timestamp(TimeUnit::Millisecond, None),
+                true,
+            ),
+            Field::new("method", DataType::Utf8, true),
+            Field::new("status", DataType::Utf8, true),
+            Field::new("host", DataType::Utf8, true),
+            Field::new("path", DataType::Utf8, true),
+            Field::new("bytes", DataType::Int64, true),
+            Field::new(
+                "tags",
+                DataType::List(Arc::new(Field::new("item", DataType::Utf8, true))),
+                true,
+            ),
+        ]);
+        let source = LogicalTableSource::new(Arc::new(schema));
+        let mut files: Files<String> = Files::new();
+        let file_id = files.add("query", input.to_string());
+        let query = parse(input).unwrap_or_else(|e| panic!("example does not parse: {}", e));
+        let mut lowerer = Lowerer::new(
+            Arc::new(ExampleQueryContext),
+            Arc::new(source),
+            files,
+            file_id,
+            query.table.name.clone(),
+        );
+        let parameters = HashMap::from([("min_bytes".to_string(), ScalarValue::Int64(Some(100)))]);
+        let _ = lowerer.lower(query, &parameters);
+        lowerer
+            .into_report()
+            .diagnostics()
+            .iter()
+            .filter_map(|diagnostic| diagnostic.code.clone())
+            .collect()
+    }
+
+    /// Extracts the `pql` code blocks of an explanation, along with the code each is tagged with, if any.
+    fn examples(explanation: &str) -> Vec<(Option<&str>, String)> {
+        let mut examples = vec![];
+        let mut lines = explanation.lines();
+        while let Some(line) = lines.next() {
+            let Some(info) = line.strip_prefix("```pql") else {
+                continue;
+            };
+
+            let code = info.strip_prefix(',');
+            let body: Vec<&str> = lines.by_ref().take_while(|line| *line != "```").collect();
+            examples.push((code, body.join("\n")));
+        }
+
+        examples
+    }
+
+    #[test]
+    fn examples_produce_their_codes() {
+        for code in codes() {
+            let examples = examples(explain(code).unwrap());
+            assert!(
+                examples.iter().any(|(tag, _)| *tag == Some(code)),
+                "{} has no example that produces it",
+                code
+            );
+            assert!(
+                examples.iter().any(|(tag, _)| tag.is_none()),
+                "{} has no example that avoids it",
+                code
+            );
+
+            for (tag, example) in examples {
+                let produced = diagnostic_codes(&example);
+                match tag {
+                    Some(tag) => {
+                        assert_eq!(tag, code, "example is tagged with another code");
+                        assert!(
+                            produced.iter().any(|c| c == code),
+                            "{} example does not produce it: {}\nproduced {:?}",
+                            code,
+                            example,
+                            produced
+                        );
+                    }
+                    None => assert!(
+                        !produced.iter().any(|c| c == code),
+                        "{} example unexpectedly produces it: {}",
+                        code,
+                        example
+                    ),
+                }
+            }
+        }
+    }
+}
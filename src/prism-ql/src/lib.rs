@@ -1,16 +1,42 @@
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 
-use codespan::{FileId, Files};
-use codespan_reporting::diagnostic::Diagnostic;
-use datafusion::logical_expr::{LogicalPlan, TableSource};
-use either::Either;
+use codespan::Files;
+use datafusion::{
+    logical_expr::{LogicalPlan, TableSource},
+    scalar::ScalarValue,
+};
 
 pub mod ast;
 mod diagnostics;
+pub mod explanations;
+mod local_bin;
 mod lower;
+mod make_series;
 mod parser;
+mod patterns;
+mod plugin;
+mod report;
+mod sample;
+mod series;
+mod source_map;
+mod substrait;
+mod unparse;
+mod visualization;
 
-pub use lower::QueryContext;
+pub use explanations::explain;
+pub use local_bin::local_bin_udf;
+pub use lower::{
+    Feature, FunctionKind, QueryContext, ResultSet, StoredFunction, DENY_ALL_WARNINGS,
+};
+pub use patterns::patterns_udaf;
+pub use plugin::{EvaluatePlugin, PluginArguments, PluginError, PluginParameter};
+pub use report::{JsonDiagnostic, JsonLabel, JsonPosition, Report};
+pub use sample::{query_planner, sample_filter, sample_fraction, sample_udf, SampleNode};
+pub use series::series_udwfs;
+pub use source_map::{FailureKind, FallibleExpr, SourceMap, Stage};
+pub use substrait::{from_substrait, serializer_registry, to_substrait};
+pub use unparse::plan_to_sql;
+pub use visualization::{Visualization, VisualizationKind};
 
 use ast::Query;
 
@@ -19,17 +45,43 @@ pub fn parse(input: &str) -> anyhow::Result<Query> {
     Ok(query)
 }
 
+/// The result of lowering a query: a plan if the query is valid, along with a report of every diagnostic raised while
+/// lowering it. A valid query may still carry warnings and notes.
+#[derive(Debug)]
+pub struct Lowered {
+    /// The plan of the query. If the query ends with `facet`, this is the input shared by every facet, and its results
+    /// are in [`Lowered::facets`] instead.
+    pub plan: Option<LogicalPlan>,
+    /// The result sets of a query that ends with `facet`, one for each of its columns.
+    pub facets: Vec<ResultSet>,
+    /// How the query asks for its results to be shown, if it ends with `render`.
+    pub visualization: Option<Visualization>,
+    /// Whether the query begins with `explain`, asking for its plans rather than its results.
+    pub explain: bool,
+    /// Where in the query each node of its plan came from.
+    pub source_map: SourceMap,
+    pub report: Report,
+}
+
 pub fn lower(
     query: Query,
     ctx: Arc<dyn QueryContext>,
     table_source: Arc<dyn TableSource>,
     input: &str,
-) -> either::Either<LogicalPlan, Vec<Diagnostic<FileId>>> {
+    parameters: &HashMap<String, ScalarValue>,
+) -> Lowered {
+    let explain = query.explain;
     let mut files: Files<String> = Files::new();
     let fileid = files.add("query", input.to_string());
-    let mut lowerer = lower::Lowerer::new(ctx, table_source, fileid, query.table.name.clone());
-    match lowerer.lower(query) {
-        Ok(plan) => Either::Left(plan),
-        Err(_) => Either::Right(lowerer.diagnostics().to_vec()),
+    let mut lowerer =
+        lower::Lowerer::new(ctx, table_source, files, fileid, query.table.name.clone());
+    let plan = lowerer.lower(query, parameters).ok();
+    Lowered {
+        plan,
+        facets: lowerer.facets(),
+        visualization: lowerer.visualization(),
+        explain,
+        source_map: lowerer.source_map(),
+        report: lowerer.into_report(),
     }
 }
@@ -0,0 +1,155 @@
+//! The function that `bin()` lowers to when bins are aligned to the tenant's time zone.
+//!
+//! `date_bin` aligns bins to a fixed origin, so a single offset from UTC would be wrong for every value on the other
+//! side of a daylight saving change from it. This function instead bins each value by its local time, taking the
+//! offset that was in effect at that instant, and returns the UTC instant at which its local bin begins.
+
+use std::sync::{Arc, OnceLock};
+
+use chrono::{Duration, LocalResult, NaiveDateTime, Offset, TimeZone};
+use chrono_tz::Tz;
+use datafusion::{
+    arrow::{
+        array::{Array, ArrayRef, TimestampNanosecondArray},
+        compute::cast,
+        datatypes::{DataType, IntervalMonthDayNanoType, TimeUnit},
+    },
+    common::{cast::as_timestamp_nanosecond_array, exec_err},
+    error::{DataFusionError, Result},
+    logical_expr::{
+        ColumnarValue, ReturnTypeFunction, ScalarFunctionImplementation, ScalarUDF, Signature,
+        TypeSignature, Volatility,
+    },
+    scalar::ScalarValue,
+};
+
+/// The name of the function.
+pub const LOCAL_BIN: &str = "local_bin";
+
+const NANOS_PER_SECOND: i64 = 1_000_000_000;
+
+/// Returns the function that bins datetimes by their local time. It takes the datetimes, the width of each bin as a
+/// fixed interval and the name of the time zone, and returns datetimes of the same type as its input.
+pub fn local_bin_udf() -> Arc<ScalarUDF> {
+    static UDF: OnceLock<Arc<ScalarUDF>> = OnceLock::new();
+    UDF.get_or_init(|| {
+        let return_type: ReturnTypeFunction = Arc::new(|args| Ok(Arc::new(args[0].clone())));
+        let fun: ScalarFunctionImplementation = Arc::new(local_bin);
+        Arc::new(ScalarUDF::new(
+            LOCAL_BIN,
+            &Signature::new(TypeSignature::Any(3), Volatility::Immutable),
+            &return_type,
+            &fun,
+        ))
+    })
+    .clone()
+}
+
+fn local_bin(args: &[ColumnarValue]) -> Result<ColumnarValue> {
+    let [source, stride, zone] = args else {
+        return exec_err!("{LOCAL_BIN} expects 3 arguments, got {}", args.len());
+    };
+
+    let ColumnarValue::Scalar(ScalarValue::IntervalMonthDayNano(Some(stride))) = stride else {
+        return exec_err!("{LOCAL_BIN} expects a constant interval as its stride");
+    };
+    let (months, days, nanos) = IntervalMonthDayNanoType::to_parts(*stride);
+    if months != 0 || days != 0 || nanos <= 0 {
+        return exec_err!("{LOCAL_BIN} expects a positive fixed interval as its stride");
+    }
+
+    let ColumnarValue::Scalar(ScalarValue::Utf8(Some(zone))) = zone else {
+        return exec_err!("{LOCAL_BIN} expects a constant time zone");
+    };
+    let Ok(tz) = zone.parse::<Tz>() else {
+        return exec_err!("{LOCAL_BIN} got an unknown time zone `{zone}`");
+    };
+
+    let (array, scalar) = match source {
+        ColumnarValue::Array(array) => (array.clone(), false),
+        ColumnarValue::Scalar(value) => (value.to_array(), true),
+    };
+    let binned = bin_array(&array, nanos, tz)?;
+    if scalar {
+        return Ok(ColumnarValue::Scalar(ScalarValue::try_from_array(
+            &binned, 0,
+        )?));
+    }
+
+    Ok(ColumnarValue::Array(binned))
+}
+
+fn bin_array(array: &ArrayRef, stride: i64, tz: Tz) -> Result<ArrayRef> {
+    let DataType::Timestamp(_, _) = array.data_type() else {
+        return exec_err!("{LOCAL_BIN} expects datetimes, got {}", array.data_type());
+    };
+
+    let nanos = cast(array, &DataType::Timestamp(TimeUnit::Nanosecond, None))?;
+    let binned: TimestampNanosecondArray = as_timestamp_nanosecond_array(&nanos)?
+        .iter()
+        .map(|value| value.map(|value| bin_instant(value, stride, tz)))
+        .collect();
+    Ok(cast(&binned, array.data_type())?)
+}
+
+/// Returns the UTC instant, in nanoseconds, at which the local bin of the given instant begins.
+fn bin_instant(instant: i64, stride: i64, tz: Tz) -> i64 {
+    let local = tz.timestamp_nanos(instant).naive_local();
+    let local = naive_nanos(local);
+    let start = local - local.rem_euclid(stride);
+    let start = NaiveDateTime::from_timestamp_opt(
+        start.div_euclid(NANOS_PER_SECOND),
+        start.rem_euclid(NANOS_PER_SECOND) as u32,
+    )
+    .expect("the start of a bin is within the range of its instant");
+    match tz.from_local_datetime(&start) {
+        LocalResult::Single(start) | LocalResult::Ambiguous(start, _) => {
+            naive_nanos(start.naive_utc())
+        }
+        // The bin begins at a local time that was skipped, such as midnight in a zone that moves its clocks forward
+        // at midnight; it begins at the first instant after the gap instead, which is the start of the bin read with
+        // the offset in effect before the gap.
+        LocalResult::None => {
+            let offset = tz.offset_from_utc_datetime(&(start - Duration::days(1)));
+            naive_nanos(start) - i64::from(offset.fix().local_minus_utc()) * NANOS_PER_SECOND
+        }
+    }
+}
+
+fn naive_nanos(datetime: NaiveDateTime) -> i64 {
+    datetime.timestamp() * NANOS_PER_SECOND + i64::from(datetime.timestamp_subsec_nanos())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use chrono_tz::Tz;
+
+    use super::bin_instant;
+
+    const DAY: i64 = 86_400_000_000_000;
+
+    fn bin(instant: &str, tz: Tz) -> String {
+        let instant = instant.parse::<chrono::DateTime<Utc>>().unwrap();
+        let start = bin_instant(instant.timestamp_nanos_opt().unwrap(), DAY, tz);
+        Utc.timestamp_nanos(start).to_rfc3339()
+    }
+
+    #[test]
+    fn bins_by_the_offset_in_effect() {
+        // Daylight saving time ended in Los Angeles on 2023-11-05, moving the offset from -7h to -8h.
+        let tz = Tz::America__Los_Angeles;
+        assert_eq!(bin("2023-11-04T12:00:00Z", tz), "2023-11-04T07:00:00+00:00");
+        assert_eq!(bin("2023-11-06T12:00:00Z", tz), "2023-11-06T08:00:00+00:00");
+        assert_eq!(bin("2023-11-06T07:30:00Z", tz), "2023-11-05T07:00:00+00:00");
+    }
+
+    #[test]
+    fn bins_that_begin_in_a_gap() {
+        // Clocks in Havana moved from midnight to 1am on 2023-03-12.
+        assert_eq!(
+            bin("2023-03-12T12:00:00Z", Tz::America__Havana),
+            "2023-03-12T05:00:00+00:00"
+        );
+    }
+}
@@ -1,17 +1,58 @@
-use std::sync::Arc;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+};
 
-use codespan::FileId;
-use codespan_reporting::diagnostic::{Diagnostic, Label};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use codespan::{FileId, Files, Span};
+use codespan_reporting::diagnostic::{Diagnostic, Label, Severity};
 use datafusion::{
-    logical_expr::{expr_fn, Expr, LogicalPlan, LogicalPlanBuilder, TableSource},
+    arrow::{
+        compute,
+        datatypes::{DataType, Field, IntervalMonthDayNanoType, Schema, TimeUnit},
+    },
+    common::{Column, DFSchema, DFSchemaRef},
+    datasource::{
+        file_format::{csv::CsvFormat, json::JsonFormat, parquet::ParquetFormat, FileFormat},
+        listing::{ListingOptions, ListingTable, ListingTableConfig, ListingTableUrl},
+        provider_as_source,
+    },
+    logical_expr::{
+        expr, expr_fn, lit, type_coercion::binary::comparison_coercion, AggregateFunction,
+        BuiltInWindowFunction, BuiltinScalarFunction, EmptyRelation, Expr, ExprSchemable,
+        Extension, JoinType, LogicalPlan, LogicalPlanBuilder, Operator,
+        TableProviderFilterPushDown, TableSource, WindowFrame, WindowFrameBound, WindowFrameUnits,
+        WindowFunction,
+    },
+    scalar::ScalarValue,
     sql::TableReference,
 };
 use maplit::hashmap;
 use thiserror::Error;
 
 use crate::{
-    ast::{ColumnExpression, Count, Expression, Pipeline, Query},
+    ast::{
+        Aggregation, BinaryExpression, BinaryOp, CallExpression, ColumnDeclaration,
+        ColumnExpression, Count, Datatable, Distinct, Evaluate, Expression, ExternalData, Facet,
+        GetSchema, Identifier, IntegerLiteral, Invoke, LetStatement, Literal, LiteralExpression,
+        Lookup, MakeSeries, MvExpand, NamedExpression, ParameterDeclaration, Pipeline,
+        PlaceholderExpression, Query, Reduce, Render, Sample, SampleDistinct, Search, Serialize,
+        Sort, StringLiteral, Summarize, TableExpression, Take, Where,
+    },
     diagnostics,
+    local_bin::local_bin_udf,
+    make_series::{make_series_udaf, Buckets},
+    parser,
+    patterns::{patterns_udaf, COUNT_FIELD, EXAMPLE_FIELD, PATTERN_FIELD},
+    plugin::{EvaluatePlugin, PluginArguments},
+    report::Report,
+    sample::{sample_filter, SampleNode},
+    series::SeriesFunction,
+    source_map::{FailureKind, SourceMap},
+    visualization::{Visualization, VisualizationKind},
 };
 
 #[derive(Debug, Error)]
@@ -24,231 +65,5386 @@ pub enum LowerError {
 
 pub type LowerResult<T> = Result<T, LowerError>;
 
+/// The column that every table is partitioned by.
+const TIME_COLUMN: &str = "timestamp";
+
+/// A deny-list entry that promotes every warning to an error.
+pub const DENY_ALL_WARNINGS: &str = "warnings";
+
+/// The largest number of buckets that a single `make-series` may produce for each group.
+const MAX_SERIES_BUCKETS: i64 = 10_000;
+
+/// The column of each `facet` result set that counts the rows holding each value.
+const FACET_COUNT: &str = "count_";
+
+/// The number of most common values that each `facet` result set holds.
+const FACET_ROWS: usize = 10;
+
+/// Behaviors of the query compiler that can be switched on or off for individual tenants.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Feature {
+    /// Align the buckets produced by `bin()` to midnight in the tenant's time zone instead of midnight UTC, taking the
+    /// offset from UTC in effect at each value, so that days across a daylight saving change still begin at midnight.
+    LocalTimeBins,
+}
+
+/// Whether a stored function computes a single value or transforms a table.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FunctionKind {
+    /// A function whose body is an expression, called like any built-in function.
+    Scalar,
+    /// A function whose body is a sequence of pipeline operators, applied to its input with `invoke`.
+    Tabular,
+}
+
+impl fmt::Display for FunctionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FunctionKind::Scalar => write!(f, "scalar"),
+            FunctionKind::Tabular => write!(f, "tabular"),
+        }
+    }
+}
+
+/// One of several results produced by a single query, such as each column of `facet by`.
+#[derive(Clone, Debug)]
+pub struct ResultSet {
+    pub name: String,
+    pub plan: LogicalPlan,
+}
+
+/// A named PQL function defined by a tenant. Calls to stored functions are expanded inline when a query is lowered,
+/// with each parameter bound to the corresponding argument at the call site.
+#[derive(Clone, Debug)]
+pub struct StoredFunction {
+    pub name: String,
+    pub kind: FunctionKind,
+    pub parameters: Vec<String>,
+    /// The PQL source of the function's body.
+    pub body: String,
+}
+
+/// Information about the environment in which a query is being compiled.
 pub trait QueryContext {
+    /// The tenant that is issuing the query.
     fn get_tenant_id(&self) -> &str;
+
+    /// The instant at which the query started. `now()` and `ago()` are evaluated relative to this instant, so every
+    /// reference to the current time within a single query observes the same value.
+    fn get_query_start_time(&self) -> DateTime<Utc>;
+
+    /// The tenant's time zone.
+    fn get_time_zone(&self) -> Tz {
+        Tz::UTC
+    }
+
+    /// The number of rows returned by a query that neither aggregates nor limits its results, if any.
+    fn get_default_row_limit(&self) -> Option<usize> {
+        None
+    }
+
+    /// The largest number of rows that a query may explicitly ask for, if any.
+    fn get_max_row_limit(&self) -> Option<usize> {
+        None
+    }
+
+    /// Whether or not the given feature is enabled for this tenant.
+    fn is_feature_enabled(&self, _feature: Feature) -> bool {
+        false
+    }
+
+    /// Codes of warnings that should be treated as errors for this tenant. The entry [`DENY_ALL_WARNINGS`] denies every
+    /// warning.
+    fn get_denied_warnings(&self) -> Vec<String> {
+        vec![]
+    }
+
+    /// Looks up a function that the tenant has stored under the given name.
+    fn get_function(&self, _name: &str) -> Option<StoredFunction> {
+        None
+    }
+
+    /// Looks up the plugin that `evaluate` invokes under the given name.
+    fn get_plugin(&self, _name: &str) -> Option<Arc<dyn EvaluatePlugin>> {
+        None
+    }
+
+    /// The local directory that `externaldata` may read files from, if any. Relative paths are resolved against it.
+    fn get_external_data_root(&self) -> Option<PathBuf> {
+        None
+    }
+}
+
+/// A call to a stored function that is currently being expanded.
+struct ActiveCall {
+    function: String,
+    file_id: FileId,
+    span: Span,
 }
 
 pub struct Lowerer {
     ctx: Arc<dyn QueryContext>,
+    files: Files<String>,
+    /// The file containing the PQL source currently being lowered; either the query itself or the body of a stored
+    /// function.
     file_id: FileId,
     table_source: Arc<dyn TableSource>,
     diagnostics: Vec<Diagnostic<FileId>>,
     table_name: String,
+    schema: DFSchemaRef,
+    /// The order of the rows produced by the pipeline so far, if they are ordered. Rows that are serialized without
+    /// being sorted are ordered, but by no particular expression.
+    ordering: Option<Vec<Expr>>,
+    /// Window expressions encountered while lowering the current operator, if that operator allows window functions.
+    window_exprs: Option<Vec<Expr>>,
+    /// Stored function calls being expanded, outermost first.
+    call_stack: Vec<ActiveCall>,
+    /// Values bound to the parameters of the stored functions being expanded, innermost last.
+    bindings: Vec<HashMap<String, Expr>>,
+    /// Values of the query's declared parameters, which are referred to with `$name` placeholders.
+    declared_parameters: HashMap<String, Expr>,
+    /// Values supplied for the query's parameters, by name.
+    parameters: HashMap<String, ScalarValue>,
+    /// Parameters that the query has referred to, whether by declaring them or through a placeholder.
+    used_parameters: HashSet<String>,
+    /// Tables defined with `let`, by name.
+    tables: HashMap<String, LogicalPlan>,
+    /// The `sort` operator that has not yet been followed by a `take`, if any.
+    unlimited_sort: Option<Span>,
+    /// Whether the rows read from the table have been filtered by time.
+    time_filtered: bool,
+    /// The columns that divide the ordered rows into separate series, as grouped by `make-series ... by`.
+    series_by: Vec<Expr>,
+    visualization: Option<Visualization>,
+    facets: Vec<ResultSet>,
+    source_map: SourceMap,
+    aggregated: bool,
+    limited: bool,
 }
 
 impl Lowerer {
     pub fn new(
         ctx: Arc<dyn QueryContext>,
         table_source: Arc<dyn TableSource>,
+        files: Files<String>,
         file_id: FileId,
         table_name: String,
     ) -> Lowerer {
+        let source_map = SourceMap::new(files.source(file_id).as_str());
         Lowerer {
             ctx,
+            files,
             file_id,
             table_source,
             table_name,
             diagnostics: vec![],
+            schema: Arc::new(DFSchema::empty()),
+            ordering: None,
+            window_exprs: None,
+            call_stack: vec![],
+            bindings: vec![],
+            declared_parameters: HashMap::new(),
+            parameters: HashMap::new(),
+            used_parameters: HashSet::new(),
+            tables: HashMap::new(),
+            unlimited_sort: None,
+            time_filtered: false,
+            series_by: vec![],
+            visualization: None,
+            facets: vec![],
+            source_map,
+            aggregated: false,
+            limited: false,
         }
     }
 
-    pub fn diagnostics(&self) -> &[Diagnostic<FileId>] {
-        &self.diagnostics
+    /// How the lowered query asks for its results to be shown, if it ends with `render`.
+    pub fn visualization(&self) -> Option<Visualization> {
+        self.visualization.clone()
+    }
+
+    /// The result sets produced by `facet`, one for each of its columns, in order. Empty if the query does not end with
+    /// `facet`.
+    pub fn facets(&self) -> Vec<ResultSet> {
+        self.facets.clone()
     }
 
-    pub fn lower(&mut self, query: Query) -> LowerResult<LogicalPlan> {
+    /// The plan as it stood after each stage of the lowered query.
+    pub fn source_map(&self) -> SourceMap {
+        self.source_map.clone()
+    }
+
+    /// Consumes the lowerer, returning its diagnostics along with the sources they refer to.
+    pub fn into_report(self) -> Report {
+        Report::new(self.files, self.file_id, self.diagnostics)
+    }
+
+    pub fn lower(
+        &mut self,
+        query: Query,
+        parameters: &HashMap<String, ScalarValue>,
+    ) -> LowerResult<LogicalPlan> {
+        self.parameters = parameters.clone();
+        self.bind_parameters(query.parameters)?;
+        for statement in query.lets {
+            self.lower_let(statement)?;
+        }
+
         let table_ref = TableReference::Full {
             catalog: "prism".into(),
             schema: self.ctx.get_tenant_id().to_string().into(),
             table: query.table.name.into(),
         };
         let mut plan = LogicalPlanBuilder::scan(table_ref, self.table_source.clone(), None)?;
+        self.source_map
+            .push(query.table.span, plan.clone().build()?);
         for pipeline in query.pipelines {
+            let span = pipeline.span();
             plan = self.lower_pipeline(plan, pipeline)?;
+            self.source_map.push(span, plan.clone().build()?);
+        }
+
+        if let Some(facet) = query.facet {
+            self.lower_facet(&plan, facet)?;
+        }
+
+        if !self.aggregated && !self.limited {
+            if let Some(limit) = self.default_row_limit() {
+                self.diagnostics.push(
+                    diagnostics::implicit_row_limit(hashmap! {
+                        "limit" => limit.to_string(),
+                    })
+                    .with_labels(vec![Label::primary(self.file_id, query.span)]),
+                );
+                plan = plan.limit(0, Some(limit))?;
+                self.unlimited_sort = None;
+            }
+        }
+
+        if let Some(render) = query.render {
+            let schemas = if self.facets.is_empty() {
+                vec![plan.schema().clone()]
+            } else {
+                self.facets
+                    .iter()
+                    .map(|facet| facet.plan.schema().clone())
+                    .collect()
+            };
+            for schema in schemas {
+                self.lower_render(schema, render.clone())?;
+            }
+        }
+
+        if let Some(sort) = self.unlimited_sort {
+            self.diagnostics.push(
+                diagnostics::sort_without_limit(hashmap! {})
+                    .with_labels(vec![Label::primary(self.file_id, sort)]),
+            );
+        }
+
+        let partitioned = self
+            .table_source
+            .schema()
+            .field_with_name(TIME_COLUMN)
+            .is_ok();
+        if partitioned && !self.time_filtered {
+            self.diagnostics.push(
+                diagnostics::no_time_filter(hashmap! {
+                    "column" => TIME_COLUMN.to_string(),
+                    "table" => self.table_name.clone(),
+                })
+                .with_labels(vec![Label::primary(self.file_id, query.table.span)]),
+            );
         }
 
+        self.check_unused_parameters()?;
+        self.deny_warnings()?;
         Ok(plan.build()?)
     }
 
+    /// Promotes warnings on the tenant's deny-list to errors.
+    fn deny_warnings(&mut self) -> LowerResult<()> {
+        let denied = self.ctx.get_denied_warnings();
+        let deny_all = denied.iter().any(|code| code == DENY_ALL_WARNINGS);
+        let mut valid = true;
+        for diagnostic in &mut self.diagnostics {
+            if diagnostic.severity != Severity::Warning {
+                continue;
+            }
+
+            let Some(code) = &diagnostic.code else {
+                continue;
+            };
+
+            if deny_all || denied.contains(code) {
+                diagnostic.severity = Severity::Error;
+                diagnostic
+                    .notes
+                    .push(format!("`{}` is denied for this tenant", code));
+                valid = false;
+            }
+        }
+
+        if !valid {
+            return Err(LowerError::InvalidQuery);
+        }
+
+        Ok(())
+    }
+
+    /// Binds each of the query's declared parameters to the value supplied for it or, failing that, to its default.
+    fn bind_parameters(&mut self, declarations: Vec<ParameterDeclaration>) -> LowerResult<()> {
+        let mut declared = HashMap::new();
+        let mut valid = true;
+        for declaration in declarations {
+            let ty = self.lower_type(&declaration.ty)?;
+            let name = declaration.name.name;
+            self.used_parameters.insert(name.clone());
+            let (value, span) = match (self.parameters.get(&name), declaration.default) {
+                (Some(value), _) => (value.clone(), declaration.name.span),
+                (None, Some(default)) => (literal_value(default.value), default.span),
+                (None, None) => {
+                    self.diagnostics.push(
+                        diagnostics::missing_parameter(hashmap! {
+                            "parameter" => name.clone(),
+                        })
+                        .with_labels(vec![Label::primary(self.file_id, declaration.name.span)]),
+                    );
+
+                    valid = false;
+                    continue;
+                }
+            };
+
+            let Some(value) = coerce_value(&value, &ty) else {
+                self.diagnostics.push(
+                    diagnostics::mistyped_parameter(hashmap! {
+                        "parameter" => name.clone(),
+                        "expected" => declaration.ty.name.clone(),
+                        "actual" => value.data_type().to_string(),
+                    })
+                    .with_labels(vec![Label::primary(self.file_id, span)]),
+                );
+
+                valid = false;
+                continue;
+            };
+
+            declared.insert(name, lit(value));
+        }
+
+        if !valid {
+            return Err(LowerError::InvalidQuery);
+        }
+
+        self.declared_parameters = declared;
+        Ok(())
+    }
+
+    fn check_unused_parameters(&mut self) -> LowerResult<()> {
+        let mut unused: Vec<&String> = self
+            .parameters
+            .keys()
+            .filter(|name| !self.used_parameters.contains(*name))
+            .collect();
+        if unused.is_empty() {
+            return Ok(());
+        }
+
+        unused.sort();
+        for name in unused {
+            self.diagnostics
+                .push(diagnostics::unexpected_parameter(hashmap! {
+                    "parameter" => name.clone(),
+                }));
+        }
+
+        Err(LowerError::InvalidQuery)
+    }
+
+    fn default_row_limit(&self) -> Option<usize> {
+        match (
+            self.ctx.get_default_row_limit(),
+            self.ctx.get_max_row_limit(),
+        ) {
+            (Some(default), Some(max)) => Some(default.min(max)),
+            (Some(default), None) => Some(default),
+            (None, max) => max,
+        }
+    }
+
     fn lower_pipeline(
         &mut self,
         builder: LogicalPlanBuilder,
         pipeline: Pipeline,
     ) -> LowerResult<LogicalPlanBuilder> {
-        match pipeline {
+        self.schema = builder.schema().clone();
+        let ordering = self.ordering.clone();
+        let series = matches!(pipeline, Pipeline::MakeSeries(_));
+        let builder = match pipeline {
             Pipeline::Count(count) => self.lower_count(builder, count),
+            Pipeline::Where(where_) => self.lower_where(builder, where_),
+            Pipeline::Take(take) => self.lower_take(builder, take),
+            Pipeline::Summarize(summarize) => self.lower_summarize(builder, summarize),
+            Pipeline::Distinct(distinct) => self.lower_distinct(builder, distinct),
+            Pipeline::MakeSeries(series) => self.lower_make_series(builder, series),
+            Pipeline::Sort(sort) => self.lower_sort(builder, sort),
+            Pipeline::Extend(extend) => self.lower_extend(builder, extend.columns),
+            Pipeline::Serialize(serialize) => self.lower_serialize(builder, serialize),
+            Pipeline::MvExpand(expand) => self.lower_mv_expand(builder, expand),
+            Pipeline::Invoke(invoke) => self.lower_invoke(builder, invoke),
+            Pipeline::Lookup(lookup) => self.lower_lookup(builder, lookup),
+            Pipeline::Sample(sample) => self.lower_sample(builder, sample),
+            Pipeline::SampleDistinct(sample) => self.lower_sample_distinct(builder, sample),
+            Pipeline::GetSchema(getschema) => self.lower_getschema(getschema),
+            Pipeline::Search(search) => self.lower_search(builder, search),
+            Pipeline::Evaluate(evaluate) => self.lower_evaluate(builder, evaluate),
+            Pipeline::Reduce(reduce) => self.lower_reduce(builder, reduce),
+        }?;
+
+        // Rows that are reordered no longer form the series that `make-series` produced.
+        if !series && self.ordering != ordering {
+            self.series_by.clear();
         }
-    }
 
-    fn lower_count(
-        &mut self,
-        builder: LogicalPlanBuilder,
-        count: Count,
-    ) -> LowerResult<LogicalPlanBuilder> {
-        let aggr_expr = expr_fn::count(Expr::Wildcard);
-        let group_by: Vec<Expr> = if let Some(by) = count.by {
-            vec![self.lower_expr(by)?]
-        } else {
-            vec![]
-        };
-        Ok(builder.aggregate(group_by, vec![aggr_expr])?)
+        Ok(builder)
     }
 
-    fn lower_expr(&mut self, expr: Expression) -> LowerResult<Expr> {
-        match expr {
-            Expression::Column(column) => self.lower_column(column),
+    /// Lowers `facet by` into one aggregation per column over the rows produced by the pipeline, each counting the
+    /// most common values of its column. The pipeline itself remains the plan of the query, shared by every facet.
+    fn lower_facet(&mut self, builder: &LogicalPlanBuilder, facet: Facet) -> LowerResult<()> {
+        self.schema = builder.schema().clone();
+        for column in facet.columns {
+            if self.facets.iter().any(|facet| facet.name == column.name) {
+                self.diagnostics.push(
+                    diagnostics::duplicate_facet(hashmap! {
+                        "column" => column.name.clone(),
+                    })
+                    .with_labels(vec![Label::primary(self.file_id, column.span)]),
+                );
+
+                return Err(LowerError::InvalidQuery);
+            }
+
+            let expr = self.lower_column(ColumnExpression {
+                span: column.span,
+                name: column.clone(),
+            })?;
+            let plan = builder
+                .clone()
+                .aggregate(
+                    vec![expr.clone()],
+                    vec![expr_fn::count(Expr::Wildcard).alias(FACET_COUNT)],
+                )?
+                .sort(vec![
+                    unqualified(FACET_COUNT).sort(false, false),
+                    expr.sort(true, false),
+                ])?
+                .limit(0, Some(FACET_ROWS))?
+                .build()?;
+            self.source_map.push(facet.span, plan.clone());
+            self.facets.push(ResultSet {
+                name: column.name,
+                plan,
+            });
         }
+
+        self.aggregated = true;
+        self.ordering = None;
+        Ok(())
     }
 
-    fn lower_column(&mut self, column: ColumnExpression) -> LowerResult<Expr> {
-        let schema = self.table_source.schema();
-        if schema.field_with_name(&column.name.name).is_err() {
+    fn lower_render(&mut self, schema: DFSchemaRef, render: Render) -> LowerResult<()> {
+        let Some(kind) = VisualizationKind::from_name(&render.visualization.name) else {
             self.diagnostics.push(
-                diagnostics::column_does_not_exist(hashmap! {
-                    "column" => column.name.name.clone(),
-                    "table" => self.table_name.clone(),
+                diagnostics::unknown_visualization(hashmap! {
+                    "visualization" => render.visualization.name.clone(),
                 })
-                .with_labels(vec![Label::primary(self.file_id, column.name.span)]),
+                .with_labels(vec![Label::primary(
+                    self.file_id,
+                    render.visualization.span,
+                )]),
             );
 
             return Err(LowerError::InvalidQuery);
-        }
-
-        Ok(expr_fn::col(column.name.name))
-    }
-}
+        };
 
-#[cfg(test)]
-mod tests {
-    use std::sync::Arc;
+        let mut title = None;
+        for property in render.properties {
+            if property.name.name != "title" {
+                self.diagnostics.push(
+                    diagnostics::unknown_property(hashmap! {
+                        "property" => property.name.name.clone(),
+                        "operator" => "render".to_string(),
+                        "accepted" => "title".to_string(),
+                    })
+                    .with_labels(vec![Label::primary(self.file_id, property.name.span)]),
+                );
 
-    use codespan::Files;
-    use datafusion::{
-        arrow::datatypes::{DataType, Schema},
-        logical_expr::builder::LogicalTableSource,
-    };
-    use expect_test::{expect, Expect};
+                return Err(LowerError::InvalidQuery);
+            }
 
-    use super::{Lowerer, QueryContext};
-    use crate::parser::parse;
+            title = Some(property.value.value);
+        }
 
-    macro_rules! schema {
-        ($($key:expr => $value:expr),*) => {
+        let has = |matches: fn(&DataType) -> bool| {
+            schema
+                .fields()
+                .iter()
+                .any(|field| matches(field.data_type()))
+        };
+        let expected = match kind {
+            VisualizationKind::Table => None,
+            VisualizationKind::TimeChart
+                if !has(|ty| matches!(ty, DataType::Timestamp(_, _)))
+                    || !has(DataType::is_numeric) =>
             {
-                use datafusion::arrow::datatypes::{Schema, Field};
-
-                let fields = vec![
-                    $(
-                        Field::new($key, $value, true),
-                    )*
-                ];
-                Schema::new(fields)
+                Some("a datetime column and a numeric column")
             }
+            VisualizationKind::BarChart if !has(DataType::is_numeric) => Some("a numeric column"),
+            VisualizationKind::TimeChart | VisualizationKind::BarChart => None,
         };
+        if let Some(expected) = expected {
+            self.diagnostics.push(
+                diagnostics::unrenderable_results(hashmap! {
+                    "visualization" => kind.to_string(),
+                    "expected" => expected.to_string(),
+                })
+                .with_labels(vec![Label::primary(self.file_id, render.span)]),
+            );
+
+            return Err(LowerError::InvalidQuery);
+        }
+
+        self.visualization = Some(Visualization { kind, title });
+        Ok(())
     }
 
-    struct TestQueryContext {
-        pub tenant_id: String,
+    fn lower_let(&mut self, statement: LetStatement) -> LowerResult<()> {
+        let plan = match statement.table {
+            TableExpression::Datatable(datatable) => self.lower_datatable(datatable)?,
+            TableExpression::ExternalData(external) => {
+                self.lower_external_data(&statement.name.name, external)?
+            }
+        };
+
+        self.tables.insert(statement.name.name, plan);
+        Ok(())
     }
 
-    impl TestQueryContext {
-        pub fn new(tenant_id: impl AsRef<str>) -> TestQueryContext {
-            TestQueryContext {
-                tenant_id: tenant_id.as_ref().to_string(),
+    fn lower_table_schema(&mut self, columns: &[ColumnDeclaration]) -> LowerResult<Schema> {
+        let mut fields: Vec<Field> = vec![];
+        for column in columns {
+            let ty = self.lower_type(&column.ty)?;
+            if fields.iter().any(|field| field.name() == &column.name.name) {
+                self.diagnostics.push(
+                    diagnostics::duplicate_column(hashmap! {
+                        "column" => column.name.name.clone(),
+                    })
+                    .with_labels(vec![Label::primary(self.file_id, column.name.span)]),
+                );
+
+                return Err(LowerError::InvalidQuery);
             }
+
+            fields.push(Field::new(&column.name.name, ty, true));
         }
+
+        Ok(Schema::new(fields))
     }
 
-    impl QueryContext for TestQueryContext {
-        fn get_tenant_id(&self) -> &str {
-            &self.tenant_id
+    fn lower_datatable(&mut self, datatable: Datatable) -> LowerResult<LogicalPlan> {
+        let schema = self.lower_table_schema(&datatable.columns)?;
+        let width = schema.fields().len();
+        if width == 0 || !datatable.values.len().is_multiple_of(width) {
+            self.diagnostics.push(
+                diagnostics::ragged_datatable(hashmap! {
+                    "values" => datatable.values.len().to_string(),
+                    "columns" => width.to_string(),
+                })
+                .with_labels(vec![Label::primary(self.file_id, datatable.span)]),
+            );
+
+            return Err(LowerError::InvalidQuery);
         }
-    }
 
-    fn check(ctx: TestQueryContext, schema: Schema, input: &str, expect: Expect) {
-        let source = LogicalTableSource::new(Arc::new(schema));
-        let mut files: Files<String> = Files::new();
-        let fileid = files.add("query", input.to_string());
-        let query = parse(input).unwrap();
-        let mut lowerer = Lowerer::new(
-            Arc::new(ctx),
-            Arc::new(source),
-            fileid,
-            "http_requests".to_string(),
-        );
-        let plan = lowerer.lower(query).unwrap();
-        expect.assert_debug_eq(&plan);
-    }
+        if datatable.values.is_empty() {
+            return Ok(LogicalPlan::EmptyRelation(EmptyRelation {
+                produce_one_row: false,
+                schema: Arc::new(DFSchema::try_from(schema)?),
+            }));
+        }
 
-    fn check_err(ctx: TestQueryContext, schema: Schema, input: &str, expect: Expect) {
-        let source = LogicalTableSource::new(Arc::new(schema));
-        let mut files: Files<String> = Files::new();
-        let fileid = files.add("query", input.to_string());
-        let query = parse(input).unwrap();
-        let mut lowerer = Lowerer::new(
-            Arc::new(ctx),
-            Arc::new(source),
-            fileid,
-            "http_requests".to_string(),
-        );
-        let _ = lowerer.lower(query).unwrap_err();
-        let diags = lowerer.diagnostics();
-        expect.assert_debug_eq(&diags);
+        let mut rows = vec![];
+        let mut valid = true;
+        for row in datatable.values.chunks(width) {
+            let mut values = vec![];
+            for ((column, field), value) in datatable.columns.iter().zip(schema.fields()).zip(row) {
+                let literal = literal_value(value.value.clone());
+                match coerce_value(&literal, field.data_type()) {
+                    Some(coerced) => values.push(lit(coerced)),
+                    None => {
+                        self.diagnostics.push(
+                            diagnostics::mistyped_datatable_value(hashmap! {
+                                "column" => column.name.name.clone(),
+                                "expected" => column.ty.name.clone(),
+                                "actual" => literal.data_type().to_string(),
+                            })
+                            .with_labels(vec![Label::primary(self.file_id, value.span)]),
+                        );
+
+                        valid = false;
+                    }
+                }
+            }
+
+            rows.push(values);
+        }
+
+        if !valid {
+            return Err(LowerError::InvalidQuery);
+        }
+
+        let columns = schema
+            .fields()
+            .iter()
+            .enumerate()
+            .map(|(i, field)| expr_fn::col(format!("column{}", i + 1)).alias(field.name()));
+        Ok(LogicalPlanBuilder::values(rows)?
+            .project(columns)?
+            .build()?)
     }
 
-    #[test]
-    fn basic_count() {
-        let ctx = TestQueryContext::new("tenant");
-        let schema = schema! {
-            "bytes" => DataType::UInt64,
-            "method" => DataType::Utf8
+    fn lower_external_data(
+        &mut self,
+        name: &str,
+        external: ExternalData,
+    ) -> LowerResult<LogicalPlan> {
+        let schema = self.lower_table_schema(&external.columns)?;
+        let mut format = None;
+        for property in external.properties {
+            if property.name.name != "format" {
+                self.diagnostics.push(
+                    diagnostics::unknown_property(hashmap! {
+                        "property" => property.name.name.clone(),
+                        "operator" => "externaldata".to_string(),
+                        "accepted" => "format".to_string(),
+                    })
+                    .with_labels(vec![Label::primary(self.file_id, property.name.span)]),
+                );
+
+                return Err(LowerError::InvalidQuery);
+            }
+
+            format = Some(property.value);
+        }
+
+        let path = self.external_data_path(&external.path)?;
+        let (format, span) = match format {
+            Some(format) => (format.value, format.span),
+            None => {
+                let extension = path.extension().and_then(|extension| extension.to_str());
+                (
+                    extension.unwrap_or_default().to_string(),
+                    external.path.span,
+                )
+            }
         };
+        let file_format: Arc<dyn FileFormat> = match format.to_ascii_lowercase().as_str() {
+            "csv" => Arc::new(CsvFormat::default().with_has_header(true)),
+            "json" | "ndjson" => Arc::new(JsonFormat::default()),
+            "parquet" => Arc::new(ParquetFormat::new()),
+            _ => {
+                self.diagnostics.push(
+                    diagnostics::unknown_data_format(hashmap! {
+                        "format" => format,
+                    })
+                    .with_labels(vec![Label::primary(self.file_id, span)]),
+                );
 
-        let e = expect![[r#"
-            Aggregate: groupBy=[[]], aggr=[[COUNT(*)]]
-              TableScan: prism.tenant.http_requests
-        "#]];
-        check(ctx, schema, "http_requests | count", e);
+                return Err(LowerError::InvalidQuery);
+            }
+        };
+
+        let url = ListingTableUrl::parse(path.to_string_lossy())?;
+        let config = ListingTableConfig::new(url)
+            .with_listing_options(ListingOptions::new(file_format))
+            .with_schema(Arc::new(schema));
+        let table = ListingTable::try_new(config)?;
+        Ok(
+            LogicalPlanBuilder::scan(name.to_string(), provider_as_source(Arc::new(table)), None)?
+                .build()?,
+        )
     }
 
-    #[test]
-    fn basic_count_by() {
-        let ctx = TestQueryContext::new("tenant");
-        let schema = schema! {
-            "bytes" => DataType::UInt64,
-            "method" => DataType::Utf8
+    /// Resolves the path of an `externaldata` file, which must lie within the tenant's external data root.
+    fn external_data_path(&mut self, path: &StringLiteral) -> LowerResult<PathBuf> {
+        let forbidden = diagnostics::forbidden_external_data(hashmap! {
+            "path" => path.value.clone(),
+        })
+        .with_labels(vec![Label::primary(self.file_id, path.span)]);
+        let Some(root) = self.ctx.get_external_data_root() else {
+            self.diagnostics.push(forbidden);
+            return Err(LowerError::InvalidQuery);
         };
 
-        let e = expect![[r#"
-            Aggregate: groupBy=[[prism.tenant.http_requests.method]], aggr=[[COUNT(*)]]
-              TableScan: prism.tenant.http_requests
-        "#]];
-        check(ctx, schema, "http_requests | count by method", e);
-    }
+        let relative = Path::new(&path.value);
+        let joined = root.join(relative);
+        let escapes = relative
+            .components()
+            .any(|component| component == Component::ParentDir);
+        if escapes || !joined.starts_with(&root) {
+            self.diagnostics.push(forbidden);
+            return Err(LowerError::InvalidQuery);
+        }
 
-    #[test]
-    fn count_by_invalid_column() {
-        let ctx = TestQueryContext::new("tenant");
-        let schema = schema! {
-            "bytes" => DataType::UInt64,
-            "method" => DataType::Utf8
+        // Resolve symbolic links before checking the path again, so that links can't point out of the root.
+        let resolved = match joined.canonicalize() {
+            Ok(resolved) => resolved,
+            Err(error) => {
+                self.diagnostics.push(
+                    diagnostics::unreadable_external_data(hashmap! {
+                        "path" => path.value.clone(),
+                        "error" => error.to_string(),
+                    })
+                    .with_labels(vec![Label::primary(self.file_id, path.span)]),
+                );
+
+                return Err(LowerError::InvalidQuery);
+            }
         };
 
-        let e = expect![[r#"
-            [
-                Diagnostic {
-                    severity: Error,
-                    code: Some(
+        let root = root.canonicalize().unwrap_or(root);
+        if !resolved.starts_with(root) {
+            self.diagnostics.push(forbidden);
+            return Err(LowerError::InvalidQuery);
+        }
+
+        Ok(resolved)
+    }
+
+    fn lower_count(
+        &mut self,
+        builder: LogicalPlanBuilder,
+        count: Count,
+    ) -> LowerResult<LogicalPlanBuilder> {
+        let aggr_expr = expr_fn::count(Expr::Wildcard);
+        let group_by: Vec<Expr> = if let Some(by) = count.by {
+            vec![self.lower_expr(by)?]
+        } else {
+            vec![]
+        };
+        self.aggregated = true;
+        self.ordering = None;
+        Ok(builder.aggregate(group_by, vec![aggr_expr])?)
+    }
+
+    fn lower_where(
+        &mut self,
+        builder: LogicalPlanBuilder,
+        where_: Where,
+    ) -> LowerResult<LogicalPlanBuilder> {
+        let predicate = self.lower_expr(where_.predicate)?;
+        if !self.aggregated
+            && predicate
+                .to_columns()?
+                .iter()
+                .any(|column| column.name == TIME_COLUMN)
+        {
+            self.time_filtered = true;
+        }
+
+        Ok(builder.filter(predicate)?)
+    }
+
+    fn lower_take(
+        &mut self,
+        builder: LogicalPlanBuilder,
+        take: Take,
+    ) -> LowerResult<LogicalPlanBuilder> {
+        let requested = self.row_count(&take.count)?;
+        Ok(builder.limit(0, Some(requested))?)
+    }
+
+    /// Checks a number of rows that the query explicitly asks for against the tenant's maximum.
+    fn row_count(&mut self, count: &IntegerLiteral) -> LowerResult<usize> {
+        let requested = count.value as usize;
+        if let Some(maximum) = self.ctx.get_max_row_limit() {
+            if requested > maximum {
+                self.diagnostics.push(
+                    diagnostics::row_limit_exceeded(hashmap! {
+                        "requested" => requested.to_string(),
+                        "maximum" => maximum.to_string(),
+                    })
+                    .with_labels(vec![Label::primary(self.file_id, count.span)]),
+                );
+
+                return Err(LowerError::InvalidQuery);
+            }
+        }
+
+        self.limited = true;
+        self.unlimited_sort = None;
+        Ok(requested)
+    }
+
+    fn lower_sample(
+        &mut self,
+        builder: LogicalPlanBuilder,
+        sample: Sample,
+    ) -> LowerResult<LogicalPlanBuilder> {
+        if !sample.percent {
+            let count = self.row_count(&sample.count)?;
+            self.ordering = None;
+            return Ok(builder
+                .sort(vec![expr_fn::random().sort(true, false)])?
+                .limit(0, Some(count))?);
+        }
+
+        if !(1..=100).contains(&sample.count.value) {
+            self.diagnostics.push(
+                diagnostics::invalid_sample_percentage(hashmap! {
+                    "percentage" => sample.count.value.to_string(),
+                })
+                .with_labels(vec![Label::primary(self.file_id, sample.count.span)]),
+            );
+
+            return Err(LowerError::InvalidQuery);
+        }
+
+        let fraction = sample.count.value as f64 / 100.0;
+        let filter = sample_filter(fraction);
+
+        // Sampling the table itself lets its source skip whole files rather than discarding rows after reading them.
+        let input = builder.build()?;
+        if let LogicalPlan::TableScan(scan) = input.clone() {
+            let pushdown = scan.source.supports_filters_pushdown(&[&filter])?;
+            if scan.filters.is_empty() && pushdown == [TableProviderFilterPushDown::Exact] {
+                return Ok(LogicalPlanBuilder::scan_with_filters(
+                    scan.table_name,
+                    scan.source,
+                    None,
+                    vec![filter],
+                )?);
+            }
+        }
+
+        Ok(LogicalPlanBuilder::from(LogicalPlan::Extension(
+            Extension {
+                node: Arc::new(SampleNode::new(input, fraction)),
+            },
+        )))
+    }
+
+    fn lower_sample_distinct(
+        &mut self,
+        builder: LogicalPlanBuilder,
+        sample: SampleDistinct,
+    ) -> LowerResult<LogicalPlanBuilder> {
+        let column = self.lower_expr(sample.column)?;
+        let count = self.row_count(&sample.count)?;
+        self.ordering = None;
+        Ok(builder
+            .project(vec![column])?
+            .distinct()?
+            .limit(0, Some(count))?)
+    }
+
+    fn lower_getschema(&mut self, _getschema: GetSchema) -> LowerResult<LogicalPlanBuilder> {
+        let rows = self
+            .schema
+            .fields()
+            .iter()
+            .enumerate()
+            .map(|(ordinal, field)| {
+                vec![
+                    lit(field.name().clone()),
+                    lit(ordinal as i64),
+                    lit(field.data_type().to_string()),
+                    lit(pql_type_name(field.data_type())),
+                ]
+            })
+            .collect();
+        let columns = ["ColumnName", "ColumnOrdinal", "DataType", "ColumnType"]
+            .iter()
+            .enumerate()
+            .map(|(i, name)| expr_fn::col(format!("column{}", i + 1)).alias(*name));
+
+        // The schema is all that is read, so there is no scan to limit or to filter by time.
+        self.aggregated = true;
+        self.time_filtered = true;
+        self.ordering = None;
+        Ok(LogicalPlanBuilder::values(rows)?.project(columns)?)
+    }
+
+    fn lower_search(
+        &mut self,
+        builder: LogicalPlanBuilder,
+        search: Search,
+    ) -> LowerResult<LogicalPlanBuilder> {
+        let mut columns = vec![];
+        if search.columns.is_empty() {
+            columns.extend(
+                self.schema
+                    .fields()
+                    .iter()
+                    .filter(|field| field.data_type() == &DataType::Utf8)
+                    .map(|field| expr_fn::col(field.name())),
+            );
+        } else {
+            for column in search.columns {
+                let span = column.span;
+                let name = column.name.clone();
+                let expr = self.lower_column(ColumnExpression { span, name: column })?;
+                let ty = expr.get_type(self.schema.as_ref())?;
+                if ty != DataType::Utf8 {
+                    self.diagnostics.push(
+                        diagnostics::non_string_search_column(hashmap! {
+                            "column" => name,
+                            "ty" => ty.to_string(),
+                        })
+                        .with_labels(vec![Label::primary(self.file_id, span)]),
+                    );
+
+                    return Err(LowerError::InvalidQuery);
+                }
+
+                columns.push(expr);
+            }
+        }
+
+        // As in KQL, searching ignores case.
+        let term = lit(search.term.value.to_lowercase());
+        let predicate = columns
+            .into_iter()
+            .map(|column| expr_fn::strpos(expr_fn::lower(column), term.clone()).gt(lit(0)))
+            .reduce(Expr::or);
+        match predicate {
+            Some(predicate) => Ok(builder.filter(predicate)?),
+            None => {
+                self.diagnostics.push(
+                    diagnostics::nothing_to_search(hashmap! {
+                        "table" => self.table_name.clone(),
+                    })
+                    .with_labels(vec![Label::primary(self.file_id, search.span)]),
+                );
+
+                Err(LowerError::InvalidQuery)
+            }
+        }
+    }
+
+    /// Lowers `reduce by` to an aggregate that clusters the values into patterns, with one row per pattern, most
+    /// frequent first.
+    fn lower_reduce(
+        &mut self,
+        builder: LogicalPlanBuilder,
+        reduce: Reduce,
+    ) -> LowerResult<LogicalPlanBuilder> {
+        let span = reduce.by.span();
+        let by = self.lower_expr(reduce.by)?;
+        let ty = by.get_type(self.schema.as_ref())?;
+        if ty != DataType::Utf8 {
+            self.diagnostics.push(
+                diagnostics::non_string_reduce(hashmap! {
+                    "ty" => ty.to_string(),
+                })
+                .with_labels(vec![Label::primary(self.file_id, span)]),
+            );
+
+            return Err(LowerError::InvalidQuery);
+        }
+
+        let clusters = "clusters";
+        let ordering = vec![unqualified("Count").sort(false, false)];
+        let builder = builder
+            .aggregate(
+                Vec::<Expr>::new(),
+                vec![patterns_udaf().call(vec![by]).alias(clusters)],
+            )?
+            .unnest_column(clusters)?
+            // Without any values to cluster, the list is empty and unnests to a single null.
+            .filter(expr_fn::col(clusters).is_not_null())?
+            .project(vec![
+                expr_fn::col(clusters).field(PATTERN_FIELD).alias("Pattern"),
+                expr_fn::col(clusters).field(COUNT_FIELD).alias("Count"),
+                expr_fn::col(clusters)
+                    .field(EXAMPLE_FIELD)
+                    .alias("Representative"),
+            ])?
+            .sort(ordering.clone())?;
+
+        self.aggregated = true;
+        self.ordering = Some(ordering);
+        Ok(builder)
+    }
+
+    fn lower_summarize(
+        &mut self,
+        builder: LogicalPlanBuilder,
+        summarize: Summarize,
+    ) -> LowerResult<LogicalPlanBuilder> {
+        let mut aggr_exprs = vec![];
+        for aggregation in summarize.aggregations {
+            aggr_exprs.push(self.lower_aggregation(aggregation)?);
+        }
+
+        let mut group_by = vec![];
+        for by in summarize.by {
+            group_by.push(self.lower_expr(by)?);
+        }
+
+        self.aggregated = true;
+        self.ordering = None;
+        Ok(builder.aggregate(group_by, aggr_exprs)?)
+    }
+
+    fn lower_aggregation(&mut self, aggregation: Aggregation) -> LowerResult<Expr> {
+        let call = match aggregation.expr {
+            Expression::Call(call) if is_aggregate_function(&call.function.name) => call,
+            expr => {
+                self.diagnostics.push(
+                    diagnostics::expected_aggregate(hashmap! {})
+                        .with_labels(vec![Label::primary(self.file_id, expr.span())]),
+                );
+
+                return Err(LowerError::InvalidQuery);
+            }
+        };
+
+        let expr = match call.function.name.as_str() {
+            "count" if call.args.is_empty() => expr_fn::count(Expr::Wildcard),
+            "count" => expr_fn::count(self.single_argument(call)?),
+            "dcount" | "count_distinct" => {
+                self.diagnostics.push(
+                    diagnostics::exact_dcount(hashmap! {
+                        "function" => call.function.name.clone(),
+                    })
+                    .with_labels(vec![Label::primary(self.file_id, call.function.span)]),
+                );
+
+                expr_fn::count_distinct(self.single_argument(call)?)
+            }
+            "sum" => expr_fn::sum(self.single_argument(call)?),
+            "min" => expr_fn::min(self.single_argument(call)?),
+            "max" => expr_fn::max(self.single_argument(call)?),
+            "avg" => expr_fn::avg(self.single_argument(call)?),
+            _ => unreachable!("not an aggregate function"),
+        };
+
+        match aggregation.name {
+            Some(name) => Ok(expr.alias(name.name)),
+            None => Ok(expr),
+        }
+    }
+
+    fn lower_distinct(
+        &mut self,
+        builder: LogicalPlanBuilder,
+        distinct: Distinct,
+    ) -> LowerResult<LogicalPlanBuilder> {
+        let mut columns = vec![];
+        for column in distinct.columns {
+            columns.push(self.lower_expr(column)?);
+        }
+
+        self.aggregated = true;
+        self.ordering = None;
+        Ok(builder.project(columns)?.distinct()?)
+    }
+
+    /// Lowers a `make-series` operator into a dense series: every group receives one row per `step` between `from` and
+    /// `to`, even if no events fell into that bucket.
+    ///
+    /// The events are aggregated into buckets as if by `summarize`. Each group's buckets are then collected by
+    /// [`make_series_udaf`] into a list that holds one struct per bucket of the range, in order, with a null for every
+    /// bucket that has no aggregate row. Unnesting that list gives one row per bucket, and buckets without a row take on
+    /// the aggregation's default value.
+    fn lower_make_series(
+        &mut self,
+        builder: LogicalPlanBuilder,
+        series: MakeSeries,
+    ) -> LowerResult<LogicalPlanBuilder> {
+        let on = self.lower_column(ColumnExpression {
+            span: series.on.span,
+            name: series.on.clone(),
+        })?;
+        let from = self.constant_timestamp(series.from, "from")?;
+        let to = self.constant_timestamp(series.to, "to")?;
+        let step = series.step.value.num_milliseconds();
+        if from >= to || step <= 0 {
+            self.diagnostics.push(
+                diagnostics::empty_series_range(hashmap! {})
+                    .with_labels(vec![Label::primary(self.file_id, series.span)]),
+            );
+
+            return Err(LowerError::InvalidQuery);
+        }
+
+        let buckets = (to - from + step - 1) / step;
+        if buckets > MAX_SERIES_BUCKETS {
+            self.diagnostics.push(
+                diagnostics::too_many_series_buckets(hashmap! {
+                    "buckets" => buckets.to_string(),
+                    "maximum" => MAX_SERIES_BUCKETS.to_string(),
+                })
+                .with_labels(vec![Label::primary(self.file_id, series.step.span)]),
+            );
+
+            return Err(LowerError::InvalidQuery);
+        }
+
+        let mut group_by = vec![];
+        for by in series.by {
+            group_by.push(self.lower_expr(by)?);
+        }
+
+        let group_count = group_by.len();
+        group_by.push(
+            expr_fn::date_bin(
+                interval(series.step.value),
+                on.clone(),
+                lit(ScalarValue::TimestampNanosecond(
+                    Some(from * 1_000_000),
+                    None,
+                )),
+            )
+            .alias(&series.on.name),
+        );
+
+        let mut aggr_exprs = vec![];
+        let mut defaults = vec![];
+        for aggregation in series.aggregations {
+            aggr_exprs.push(self.lower_aggregation(aggregation.aggregation)?);
+            defaults.push(match aggregation.default {
+                Some(default) => self.lower_expr(default)?,
+                None => lit(0i64),
+            });
+        }
+
+        if series.on.name == TIME_COLUMN {
+            self.time_filtered = true;
+        }
+
+        let aggregate = builder
+            .filter(
+                on.clone()
+                    .gt_eq(timestamp_millis(from))
+                    .and(on.lt(timestamp_millis(to))),
+            )?
+            .aggregate(group_by, aggr_exprs)?
+            .build()?;
+        let names: Vec<String> = aggregate
+            .schema()
+            .fields()
+            .iter()
+            .map(|field| field.name().clone())
+            .collect();
+        let (group_names, value_names) = names.split_at(group_count + 1);
+        let group_names = &group_names[..group_count];
+
+        let buckets = Buckets {
+            from,
+            step,
+            count: buckets,
+        };
+        let series_column = "series";
+        let make_series = make_series_udaf(buckets, &series.on.name, value_names);
+        let series_args = std::iter::once(&series.on.name)
+            .chain(value_names)
+            .map(|name| unqualified(name))
+            .collect();
+        let filled = LogicalPlanBuilder::from(aggregate)
+            .aggregate(
+                group_names.iter().map(|name| unqualified(name)),
+                vec![make_series.call(series_args).alias(series_column)],
+            )?
+            .unnest_column(series_column)?;
+
+        let keys: Vec<&String> = group_names
+            .iter()
+            .chain(std::iter::once(&series.on.name))
+            .collect();
+        let mut projection: Vec<Expr> = group_names
+            .iter()
+            .map(|name| unqualified(name).alias(name))
+            .collect();
+        projection.push(
+            expr_fn::col(series_column)
+                .field(series.on.name.as_str())
+                .alias(&series.on.name),
+        );
+        for (name, default) in value_names.iter().zip(defaults) {
+            projection.push(
+                expr_fn::coalesce(vec![
+                    expr_fn::col(series_column).field(name.as_str()),
+                    default,
+                ])
+                .alias(name),
+            );
+        }
+
+        let ordering = keys
+            .iter()
+            .map(|key| unqualified(key).sort(true, false))
+            .collect::<Vec<_>>();
+        self.aggregated = true;
+        self.ordering = Some(ordering.clone());
+        self.series_by = group_names.iter().map(|name| unqualified(name)).collect();
+        Ok(filled.project(projection)?.sort(ordering)?)
+    }
+
+    fn lower_sort(
+        &mut self,
+        builder: LogicalPlanBuilder,
+        sort: Sort,
+    ) -> LowerResult<LogicalPlanBuilder> {
+        let mut ordering = vec![];
+        for by in sort.by {
+            let expr = self.lower_expr(by.expr)?;
+            ordering.push(expr.sort(!by.descending, by.descending));
+        }
+
+        self.ordering = Some(ordering.clone());
+        self.unlimited_sort = Some(sort.span);
+        Ok(builder.sort(ordering)?)
+    }
+
+    /// Lowers the columns of an `extend` (or `serialize`) operator, which are appended to the current row set or
+    /// replace existing columns of the same name.
+    ///
+    /// Window functions may appear in these columns. Each is evaluated by a window node inserted below the projection,
+    /// and the column refers to the window's output by name.
+    fn lower_extend(
+        &mut self,
+        builder: LogicalPlanBuilder,
+        columns: Vec<NamedExpression>,
+    ) -> LowerResult<LogicalPlanBuilder> {
+        self.window_exprs = Some(vec![]);
+        let mut extensions = vec![];
+        for column in columns {
+            let expr = self.lower_expr(column.expr);
+            let expr = match (expr, column.name) {
+                (Ok(expr), Some(name)) => expr.alias(name.name),
+                (Ok(expr), None) => expr,
+                (Err(e), _) => {
+                    self.window_exprs = None;
+                    return Err(e);
+                }
+            };
+
+            extensions.push(expr);
+        }
+
+        let window_exprs = self.window_exprs.take().unwrap_or_default();
+        let builder = if window_exprs.is_empty() {
+            builder
+        } else {
+            builder.window(window_exprs)?
+        };
+
+        // A column takes the name of the field it produces, so that `extend bytes` names its column `bytes` rather
+        // than after the qualified column it refers to.
+        let mut names = vec![];
+        for extension in &extensions {
+            names.push(extension.to_field(builder.schema())?.name().clone());
+        }
+
+        let mut projection = vec![];
+        let mut replaced = HashSet::new();
+        for field in self.schema.fields() {
+            let column = Expr::Column(field.qualified_column());
+            match names.iter().position(|name| name == field.name()) {
+                Some(index) => {
+                    if extensions[index] != column {
+                        replaced.insert(field.name().clone());
+                    }
+                    projection.push(extensions[index].clone());
+                }
+                None => projection.push(column),
+            }
+        }
+
+        for (name, extension) in names.iter().zip(extensions) {
+            if !self.schema.has_column_with_unqualified_name(name) {
+                projection.push(extension);
+            }
+        }
+
+        // Rows sorted by a column that has been replaced keep their order, but can no longer be sorted by it again, so
+        // later window functions take them in the order they are in, as after `serialize`.
+        if let Some(ordering) = &self.ordering {
+            let sorted_by_replaced = ordering.iter().any(|sort| {
+                sort.to_columns().is_ok_and(|columns| {
+                    columns.iter().any(|column| replaced.contains(&column.name))
+                })
+            });
+            if sorted_by_replaced {
+                self.ordering = Some(vec![]);
+            }
+        }
+
+        Ok(builder.project(projection)?)
+    }
+
+    fn lower_serialize(
+        &mut self,
+        builder: LogicalPlanBuilder,
+        serialize: Serialize,
+    ) -> LowerResult<LogicalPlanBuilder> {
+        if self.ordering.is_none() {
+            self.ordering = Some(vec![]);
+        }
+
+        if serialize.columns.is_empty() {
+            return Ok(builder);
+        }
+
+        self.lower_extend(builder, serialize.columns)
+    }
+
+    /// Lowers an `mv-expand` operator, which produces one row for each element of an array column. The expanded column
+    /// takes on the element type of the array, unless it is cast to another type with `to typeof(...)`.
+    fn lower_mv_expand(
+        &mut self,
+        builder: LogicalPlanBuilder,
+        expand: MvExpand,
+    ) -> LowerResult<LogicalPlanBuilder> {
+        self.lower_column(ColumnExpression {
+            span: expand.column.span,
+            name: expand.column.clone(),
+        })?;
+
+        let field = self
+            .schema
+            .field_with_unqualified_name(&expand.column.name)?
+            .clone();
+        if !matches!(
+            field.data_type(),
+            DataType::List(_) | DataType::LargeList(_) | DataType::FixedSizeList(_, _)
+        ) {
+            self.diagnostics.push(
+                diagnostics::expected_array_column(hashmap! {
+                    "column" => expand.column.name.clone(),
+                    "ty" => field.data_type().to_string(),
+                })
+                .with_labels(vec![Label::primary(self.file_id, expand.column.span)]),
+            );
+
+            return Err(LowerError::InvalidQuery);
+        }
+
+        let element_type = match expand.ty {
+            Some(ty) => Some(self.lower_type(&ty)?),
+            None => None,
+        };
+
+        let builder = builder.unnest_column(field.qualified_column())?;
+        let Some(element_type) = element_type else {
+            return Ok(builder);
+        };
+
+        self.source_map
+            .push_fallible(self.file_id, expand.span, FailureKind::Cast);
+
+        let projection = self
+            .schema
+            .fields()
+            .iter()
+            .map(|f| {
+                let column = Expr::Column(f.qualified_column());
+                if f.name() == field.name() {
+                    expr_fn::cast(column, element_type.clone()).alias(f.name())
+                } else {
+                    column
+                }
+            })
+            .collect::<Vec<_>>();
+        Ok(builder.project(projection)?)
+    }
+
+    /// Joins each row to the matching rows of a table defined with `let`, keeping rows that have no match.
+    fn lower_lookup(
+        &mut self,
+        builder: LogicalPlanBuilder,
+        lookup: Lookup,
+    ) -> LowerResult<LogicalPlanBuilder> {
+        let Some(table) = self.tables.get(&lookup.table.name).cloned() else {
+            self.diagnostics.push(
+                diagnostics::unknown_table(hashmap! {
+                    "table" => lookup.table.name.clone(),
+                })
+                .with_labels(vec![Label::primary(self.file_id, lookup.table.span)]),
+            );
+
+            return Err(LowerError::InvalidQuery);
+        };
+
+        let table_schema = table.schema().clone();
+        let mut left_keys = vec![];
+        let mut right_keys = vec![];
+        let mut valid = true;
+        for column in &lookup.on {
+            let left = self.schema.field_with_unqualified_name(&column.name);
+            let right = table_schema.field_with_unqualified_name(&column.name);
+            let (left, right) = match (left, right) {
+                (Ok(left), Ok(right)) => (left, right),
+                (left, _) => {
+                    let table = if left.is_err() {
+                        &self.table_name
+                    } else {
+                        &lookup.table.name
+                    };
+                    self.diagnostics.push(
+                        diagnostics::column_does_not_exist(hashmap! {
+                            "column" => column.name.clone(),
+                            "table" => table.clone(),
+                        })
+                        .with_labels(vec![Label::primary(self.file_id, column.span)]),
+                    );
+
+                    valid = false;
+                    continue;
+                }
+            };
+
+            if left.data_type() != right.data_type() {
+                self.diagnostics.push(
+                    diagnostics::mismatched_lookup_column(hashmap! {
+                        "column" => column.name.clone(),
+                        "left" => left.data_type().to_string(),
+                        "right" => right.data_type().to_string(),
+                        "table" => lookup.table.name.clone(),
+                    })
+                    .with_labels(vec![Label::primary(self.file_id, column.span)]),
+                );
+
+                valid = false;
+                continue;
+            }
+
+            left_keys.push(left.qualified_column());
+            right_keys.push(Column::new(Some(lookup.table.name.clone()), &column.name));
+        }
+
+        if !valid {
+            return Err(LowerError::InvalidQuery);
+        }
+
+        // The lookup table's columns follow the input's, without the join keys. Columns that share a name with one of
+        // the input's are suffixed with `1`.
+        let mut columns: Vec<Expr> = self
+            .schema
+            .fields()
+            .iter()
+            .map(|field| Expr::Column(field.qualified_column()))
+            .collect();
+        for field in table_schema.fields() {
+            if lookup.on.iter().any(|key| &key.name == field.name()) {
+                continue;
+            }
+
+            let column = Expr::Column(Column::new(Some(lookup.table.name.clone()), field.name()));
+            if self.schema.has_column_with_unqualified_name(field.name()) {
+                columns.push(column.alias(format!("{}1", field.name())));
+            } else {
+                columns.push(column);
+            }
+        }
+
+        let table = LogicalPlanBuilder::from(table)
+            .alias(lookup.table.name.clone())?
+            .build()?;
+        self.ordering = None;
+        Ok(builder
+            .join_detailed(table, JoinType::Left, (left_keys, right_keys), None, false)?
+            .project(columns)?)
+    }
+
+    fn lower_invoke(
+        &mut self,
+        builder: LogicalPlanBuilder,
+        invoke: Invoke,
+    ) -> LowerResult<LogicalPlanBuilder> {
+        let Some(function) = self.stored_function(&invoke.call) else {
+            return Err(LowerError::InvalidQuery);
+        };
+
+        self.expand_function(
+            invoke.call,
+            function,
+            FunctionKind::Tabular,
+            |body| {
+                parser::parse_pipelines(body).map_err(|e| (e.to_string(), parser::error_span(&e)))
+            },
+            |this, pipelines| {
+                let mut builder = builder;
+                for pipeline in pipelines {
+                    builder = this.lower_pipeline(builder, pipeline)?;
+                }
+
+                Ok(builder)
+            },
+        )
+    }
+
+    fn lower_evaluate(
+        &mut self,
+        builder: LogicalPlanBuilder,
+        evaluate: Evaluate,
+    ) -> LowerResult<LogicalPlanBuilder> {
+        let name = evaluate.plugin.name;
+        let Some(plugin) = self.ctx.get_plugin(&name) else {
+            self.diagnostics.push(
+                diagnostics::unknown_plugin(hashmap! {
+                    "plugin" => name,
+                })
+                .with_labels(vec![Label::primary(self.file_id, evaluate.plugin.span)]),
+            );
+
+            return Err(LowerError::InvalidQuery);
+        };
+
+        let parameters = plugin.parameters();
+        let mut values = HashMap::new();
+        let mut spans: HashMap<String, Span> = HashMap::new();
+        for (i, arg) in evaluate.args.iter().enumerate() {
+            let parameter = match &arg.name {
+                Some(argument) => {
+                    let parameter = parameters.iter().find(|p| p.name == argument.name);
+                    let Some(parameter) = parameter else {
+                        self.diagnostics.push(
+                            diagnostics::unknown_plugin_argument(hashmap! {
+                                "plugin" => name,
+                                "argument" => argument.name.clone(),
+                            })
+                            .with_labels(vec![Label::primary(self.file_id, argument.span)]),
+                        );
+
+                        return Err(LowerError::InvalidQuery);
+                    };
+
+                    parameter
+                }
+                None => {
+                    let Some(parameter) = parameters.get(i) else {
+                        self.diagnostics.push(
+                            diagnostics::too_many_plugin_arguments(hashmap! {
+                                "plugin" => name,
+                                "expected" => parameters.len().to_string(),
+                                "actual" => evaluate.args.len().to_string(),
+                            })
+                            .with_labels(vec![Label::primary(self.file_id, arg.span)]),
+                        );
+
+                        return Err(LowerError::InvalidQuery);
+                    };
+
+                    parameter
+                }
+            };
+
+            if let Some(previous) = spans.get(&parameter.name) {
+                self.diagnostics.push(
+                    diagnostics::duplicate_plugin_argument(hashmap! {
+                        "plugin" => name,
+                        "argument" => parameter.name.clone(),
+                    })
+                    .with_labels(vec![
+                        Label::primary(self.file_id, arg.span),
+                        Label::secondary(self.file_id, *previous).with_message("first given here"),
+                    ]),
+                );
+
+                return Err(LowerError::InvalidQuery);
+            }
+
+            let span = arg.value.span();
+            let value = match self.lower_expr(arg.value.clone())? {
+                Expr::Literal(value) => coerce_value(&value, &parameter.ty),
+                _ => None,
+            };
+            let Some(value) = value else {
+                self.diagnostics.push(
+                    diagnostics::mistyped_plugin_argument(hashmap! {
+                        "plugin" => name,
+                        "argument" => parameter.name.clone(),
+                        "expected" => parameter.ty.to_string(),
+                    })
+                    .with_labels(vec![Label::primary(self.file_id, span)]),
+                );
+
+                return Err(LowerError::InvalidQuery);
+            };
+
+            values.insert(parameter.name.clone(), value);
+            spans.insert(parameter.name.clone(), arg.span);
+        }
+
+        for parameter in &parameters {
+            if values.contains_key(&parameter.name) {
+                continue;
+            }
+
+            let Some(default) = &parameter.default else {
+                self.diagnostics.push(
+                    diagnostics::missing_plugin_argument(hashmap! {
+                        "plugin" => name,
+                        "argument" => parameter.name.clone(),
+                    })
+                    .with_labels(vec![Label::primary(self.file_id, evaluate.span)]),
+                );
+
+                return Err(LowerError::InvalidQuery);
+            };
+
+            values.insert(parameter.name.clone(), default.clone());
+        }
+
+        match plugin.evaluate(builder, &PluginArguments::new(values)) {
+            Ok(builder) => {
+                self.ordering = None;
+                Ok(builder)
+            }
+            Err(error) => {
+                let span = error
+                    .argument
+                    .as_ref()
+                    .and_then(|argument| spans.get(argument))
+                    .copied()
+                    .unwrap_or(evaluate.span);
+                self.diagnostics.push(
+                    diagnostics::plugin_failed(hashmap! {
+                        "plugin" => name,
+                        "message" => error.message,
+                    })
+                    .with_labels(vec![Label::primary(self.file_id, span)]),
+                );
+
+                Err(LowerError::InvalidQuery)
+            }
+        }
+    }
+
+    fn stored_function(&mut self, call: &CallExpression) -> Option<StoredFunction> {
+        let function = self.ctx.get_function(&call.function.name);
+        if function.is_none() {
+            self.diagnostics.push(
+                diagnostics::unknown_function(hashmap! {
+                    "function" => call.function.name.clone(),
+                })
+                .with_labels(vec![Label::primary(self.file_id, call.function.span)]),
+            );
+        }
+
+        function
+    }
+
+    /// Expands a call to a stored function by parsing its body and lowering it with the function's parameters bound
+    /// to the call's arguments.
+    ///
+    /// The body is added to the file database so that diagnostics can point into it. Any diagnostic raised while
+    /// lowering the body also receives a label pointing at the call site.
+    fn expand_function<T, R>(
+        &mut self,
+        call: CallExpression,
+        function: StoredFunction,
+        expected: FunctionKind,
+        parse: impl FnOnce(&str) -> Result<T, (String, Option<Span>)>,
+        lower: impl FnOnce(&mut Self, T) -> LowerResult<R>,
+    ) -> LowerResult<R> {
+        if function.kind != expected {
+            self.diagnostics.push(
+                diagnostics::wrong_function_kind(hashmap! {
+                    "function" => function.name.clone(),
+                    "actual" => function.kind.to_string(),
+                    "expected" => expected.to_string(),
+                })
+                .with_labels(vec![Label::primary(self.file_id, call.function.span)]),
+            );
+
+            return Err(LowerError::InvalidQuery);
+        }
+
+        if self
+            .call_stack
+            .iter()
+            .any(|active| active.function == function.name)
+        {
+            let mut labels = vec![Label::primary(self.file_id, call.span)];
+            for active in &self.call_stack {
+                labels.push(
+                    Label::secondary(active.file_id, active.span)
+                        .with_message(format!("`{}` called here", active.function)),
+                );
+            }
+
+            self.diagnostics.push(
+                diagnostics::recursive_function(hashmap! {
+                    "function" => function.name.clone(),
+                })
+                .with_labels(labels),
+            );
+
+            return Err(LowerError::InvalidQuery);
+        }
+
+        self.check_argument_count(&call, function.parameters.len())?;
+        let mut bindings = HashMap::new();
+        for (parameter, arg) in function.parameters.iter().zip(call.args) {
+            let span = arg.span();
+            let value = self.lower_expr(arg)?;
+            // The body of a tabular function runs its own operators, after which the columns of the call site may no
+            // longer exist, so only values can be passed to it.
+            if expected == FunctionKind::Tabular && !matches!(value, Expr::Literal(_)) {
+                self.diagnostics.push(
+                    diagnostics::non_constant_argument(hashmap! {
+                        "parameter" => parameter.clone(),
+                        "function" => function.name.clone(),
+                    })
+                    .with_labels(vec![Label::primary(self.file_id, span)]),
+                );
+
+                return Err(LowerError::InvalidQuery);
+            }
+
+            bindings.insert(parameter.clone(), value);
+        }
+
+        let body_file = self.files.add(function.name.clone(), function.body.clone());
+        let body = match parse(&function.body) {
+            Ok(body) => body,
+            Err((error, span)) => {
+                let span = span.unwrap_or_else(|| Span::from_str(&function.body));
+                self.diagnostics.push(
+                    diagnostics::invalid_function_body(hashmap! {
+                        "function" => function.name.clone(),
+                        "error" => error,
+                    })
+                    .with_labels(vec![
+                        Label::primary(self.file_id, call.span),
+                        Label::secondary(body_file, span),
+                    ]),
+                );
+
+                return Err(LowerError::InvalidQuery);
+            }
+        };
+
+        let call_file = std::mem::replace(&mut self.file_id, body_file);
+        let first_diagnostic = self.diagnostics.len();
+        self.call_stack.push(ActiveCall {
+            function: function.name.clone(),
+            file_id: call_file,
+            span: call.span,
+        });
+        self.bindings.push(bindings);
+        let result = lower(self, body);
+        self.bindings.pop();
+        self.call_stack.pop();
+        self.file_id = call_file;
+        let call_range = std::ops::Range::<usize>::from(call.span);
+        for diagnostic in &mut self.diagnostics[first_diagnostic..] {
+            let labelled = diagnostic
+                .labels
+                .iter()
+                .any(|label| label.file_id == call_file && label.range == call_range);
+            if !labelled {
+                diagnostic.labels.push(
+                    Label::secondary(call_file, call.span)
+                        .with_message(format!("in this call to `{}`", function.name)),
+                );
+            }
+        }
+
+        result
+    }
+
+    fn lower_type(&mut self, ty: &Identifier) -> LowerResult<DataType> {
+        let data_type = match ty.name.as_str() {
+            "bool" => DataType::Boolean,
+            "int" => DataType::Int32,
+            "long" => DataType::Int64,
+            "real" | "double" => DataType::Float64,
+            "string" => DataType::Utf8,
+            "datetime" => DataType::Timestamp(TimeUnit::Millisecond, None),
+            _ => {
+                self.diagnostics.push(
+                    diagnostics::unknown_type(hashmap! {
+                        "ty" => ty.name.clone(),
+                    })
+                    .with_labels(vec![Label::primary(self.file_id, ty.span)]),
+                );
+
+                return Err(LowerError::InvalidQuery);
+            }
+        };
+
+        Ok(data_type)
+    }
+
+    fn constant_timestamp(&mut self, expr: Expression, clause: &str) -> LowerResult<i64> {
+        let span = expr.span();
+        if let Expr::Literal(ScalarValue::TimestampMillisecond(Some(value), _)) =
+            self.lower_expr(expr)?
+        {
+            return Ok(value);
+        }
+
+        self.diagnostics.push(
+            diagnostics::expected_constant_timestamp(hashmap! {
+                "clause" => clause.to_string(),
+            })
+            .with_labels(vec![Label::primary(self.file_id, span)]),
+        );
+
+        Err(LowerError::InvalidQuery)
+    }
+
+    fn lower_expr(&mut self, expr: Expression) -> LowerResult<Expr> {
+        let span = expr.span();
+        let lowered = match expr {
+            Expression::Column(column) => self.lower_column(column),
+            Expression::Literal(literal) => Ok(self.lower_literal(literal)),
+            Expression::Call(call) => self.lower_call(call),
+            Expression::Binary(binary) => self.lower_binary(binary),
+            Expression::Placeholder(placeholder) => self.lower_placeholder(placeholder),
+        }?;
+
+        if let Some(kind) = self.failure_kind(&lowered) {
+            self.source_map.push_fallible(self.file_id, span, kind);
+        }
+
+        Ok(lowered)
+    }
+
+    /// Returns how evaluating a lowered expression can fail at run time, if it can. Only the outermost operation is
+    /// considered, as the operands were lowered from expressions of their own.
+    fn failure_kind(&self, expr: &Expr) -> Option<FailureKind> {
+        let ty = |expr: &Expr| expr.get_type(&self.schema).ok();
+        match expr {
+            Expr::BinaryExpr(expr::BinaryExpr { left, op, right }) => match op {
+                // Dividing floating point numbers by zero produces infinity rather than an error, and a constant
+                // divisor other than zero never fails.
+                Operator::Divide | Operator::Modulo => ([left, right]
+                    .iter()
+                    .all(|operand| ty(operand).is_none_or(|ty| ty.is_integer()))
+                    && !is_nonzero_constant(right))
+                .then_some(FailureKind::DivideByZero),
+                Operator::RegexMatch
+                | Operator::RegexIMatch
+                | Operator::RegexNotMatch
+                | Operator::RegexNotIMatch => Some(FailureKind::Regex),
+                _ => {
+                    let (Some(left), Some(right)) = (ty(left), ty(right)) else {
+                        return None;
+                    };
+
+                    let is_string =
+                        |ty: &DataType| matches!(ty, DataType::Utf8 | DataType::LargeUtf8);
+                    (left != right && (is_string(&left) || is_string(&right)))
+                        .then_some(FailureKind::Cast)
+                }
+            },
+            Expr::Cast(_) => Some(FailureKind::Cast),
+            Expr::ScalarFunction(expr::ScalarFunction {
+                fun: BuiltinScalarFunction::RegexpMatch | BuiltinScalarFunction::RegexpReplace,
+                ..
+            }) => Some(FailureKind::Regex),
+            _ => None,
+        }
+    }
+
+    /// Lowers a `$name` placeholder. Placeholders may refer to declared query parameters or, if the query does not
+    /// declare one by that name, directly to a supplied parameter value, whose type is checked where it is used.
+    fn lower_placeholder(&mut self, placeholder: PlaceholderExpression) -> LowerResult<Expr> {
+        if let Some(declared) = self.declared_parameters.get(&placeholder.name) {
+            return Ok(declared.clone());
+        }
+
+        if let Some(value) = self.parameters.get(&placeholder.name) {
+            self.used_parameters.insert(placeholder.name);
+            return Ok(lit(value.clone()));
+        }
+
+        self.diagnostics.push(
+            diagnostics::missing_parameter(hashmap! {
+                "parameter" => placeholder.name,
+            })
+            .with_labels(vec![Label::primary(self.file_id, placeholder.span)]),
+        );
+
+        Err(LowerError::InvalidQuery)
+    }
+
+    /// Lowers a bare name, which refers to a parameter of the stored function being expanded, a column or, if no column
+    /// has that name, a declared query parameter.
+    fn lower_column(&mut self, column: ColumnExpression) -> LowerResult<Expr> {
+        let bound = self
+            .bindings
+            .last()
+            .and_then(|bindings| bindings.get(&column.name.name));
+        if let Some(bound) = bound {
+            let bound = bound.clone();
+            let shadowed = self
+                .schema
+                .has_column_with_unqualified_name(&column.name.name);
+            if let (true, Some(active)) = (shadowed, self.call_stack.last()) {
+                self.diagnostics.push(
+                    diagnostics::parameter_shadows_column(hashmap! {
+                        "parameter" => column.name.name.clone(),
+                        "function" => active.function.clone(),
+                    })
+                    .with_labels(vec![Label::primary(self.file_id, column.name.span)]),
+                );
+            }
+
+            return Ok(bound);
+        }
+
+        let has_column = self
+            .schema
+            .has_column_with_unqualified_name(&column.name.name);
+        if let Some(declared) = self.declared_parameters.get(&column.name.name) {
+            if !has_column {
+                return Ok(declared.clone());
+            }
+
+            self.diagnostics.push(
+                diagnostics::column_shadows_query_parameter(hashmap! {
+                    "parameter" => column.name.name.clone(),
+                })
+                .with_labels(vec![Label::primary(self.file_id, column.name.span)]),
+            );
+        }
+
+        if !has_column {
+            self.diagnostics.push(
+                diagnostics::column_does_not_exist(hashmap! {
+                    "column" => column.name.name.clone(),
+                    "table" => self.table_name.clone(),
+                })
+                .with_labels(vec![Label::primary(self.file_id, column.name.span)]),
+            );
+
+            return Err(LowerError::InvalidQuery);
+        }
+
+        Ok(expr_fn::col(column.name.name))
+    }
+
+    fn lower_literal(&mut self, literal: LiteralExpression) -> Expr {
+        lit(literal_value(literal.value))
+    }
+
+    fn lower_binary(&mut self, binary: BinaryExpression) -> LowerResult<Expr> {
+        let lhs_placeholder = self.undeclared_placeholder(&binary.lhs);
+        let rhs_placeholder = self.undeclared_placeholder(&binary.rhs);
+        let lhs = self.lower_expr(*binary.lhs)?;
+        let rhs = self.lower_expr(*binary.rhs)?;
+        let mut valid = true;
+        if let Some(placeholder) = lhs_placeholder {
+            valid &= self.check_placeholder_type(placeholder, &lhs, &rhs);
+        }
+        if let Some(placeholder) = rhs_placeholder {
+            valid &= self.check_placeholder_type(placeholder, &rhs, &lhs);
+        }
+        if !valid {
+            return Err(LowerError::InvalidQuery);
+        }
+
+        let op = match binary.op {
+            BinaryOp::Eq => Operator::Eq,
+            BinaryOp::NotEq => Operator::NotEq,
+            BinaryOp::Lt => Operator::Lt,
+            BinaryOp::LtEq => Operator::LtEq,
+            BinaryOp::Gt => Operator::Gt,
+            BinaryOp::GtEq => Operator::GtEq,
+            BinaryOp::And => Operator::And,
+            BinaryOp::Or => Operator::Or,
+            BinaryOp::Plus => Operator::Plus,
+            BinaryOp::Minus => Operator::Minus,
+            BinaryOp::Multiply => Operator::Multiply,
+            BinaryOp::Divide => Operator::Divide,
+            BinaryOp::StartsWith => return Ok(expr_fn::starts_with(lhs, rhs)),
+        };
+
+        if op.is_comparison_operator() {
+            self.check_comparison(&lhs, &rhs, binary.span);
+            self.check_comparison(&rhs, &lhs, binary.span);
+        }
+
+        Ok(expr_fn::binary_expr(lhs, op, rhs))
+    }
+
+    /// Returns the placeholder that an operand consists of, if it refers to a parameter that the query does not
+    /// declare, and so has no type of its own.
+    fn undeclared_placeholder(&self, operand: &Expression) -> Option<PlaceholderExpression> {
+        match operand {
+            Expression::Placeholder(placeholder)
+                if !self.declared_parameters.contains_key(&placeholder.name) =>
+            {
+                Some(placeholder.clone())
+            }
+            _ => None,
+        }
+    }
+
+    /// Checks that the value of an undeclared parameter can be used with the operand on the other side of it, and
+    /// reports it otherwise.
+    fn check_placeholder_type(
+        &mut self,
+        placeholder: PlaceholderExpression,
+        value: &Expr,
+        other: &Expr,
+    ) -> bool {
+        let (Some(actual), Some(expected)) = (self.expr_type(value), self.expr_type(other)) else {
+            return true;
+        };
+
+        if unify_types(&expected, &actual).is_some() {
+            return true;
+        }
+
+        self.diagnostics.push(
+            diagnostics::mistyped_placeholder(hashmap! {
+                "parameter" => placeholder.name,
+                "expected" => match pql_type_name(&expected) {
+                    "dynamic" => expected.to_string(),
+                    name => name.to_string(),
+                },
+                "actual" => actual.to_string(),
+            })
+            .with_labels(vec![Label::primary(self.file_id, placeholder.span)]),
+        );
+
+        false
+    }
+
+    /// Warns about comparisons of a string column to an integer, which compare the values as strings.
+    fn check_comparison(&mut self, column: &Expr, literal: &Expr, span: Span) {
+        let Expr::Column(column) = column else {
+            return;
+        };
+
+        let Ok(field) = self.schema.field_from_column(column) else {
+            return;
+        };
+
+        if field.data_type() == &DataType::Utf8
+            && matches!(literal, Expr::Literal(ScalarValue::Int64(_)))
+        {
+            self.diagnostics.push(
+                diagnostics::string_integer_comparison(hashmap! {
+                    "column" => column.name.clone(),
+                })
+                .with_labels(vec![Label::primary(self.file_id, span)]),
+            );
+        }
+    }
+
+    fn lower_call(&mut self, call: CallExpression) -> LowerResult<Expr> {
+        match call.function.name.as_str() {
+            "now" => {
+                self.check_argument_count(&call, 0)?;
+                Ok(timestamp(self.ctx.get_query_start_time()))
+            }
+            "ago" => {
+                self.check_argument_count(&call, 1)?;
+                let span = self.timespan_argument(&call, 0)?;
+                Ok(timestamp(self.ctx.get_query_start_time() - span))
+            }
+            "bin" => {
+                self.check_argument_count(&call, 2)?;
+                let span = self.timespan_argument(&call, 1)?;
+                let source = self.lower_expr(call.args.into_iter().next().unwrap())?;
+                if self.ctx.is_feature_enabled(Feature::LocalTimeBins) {
+                    let zone = self.ctx.get_time_zone().name().to_string();
+                    return Ok(local_bin_udf().call(vec![source, interval(span), lit(zone)]));
+                }
+
+                Ok(expr_fn::date_bin(
+                    interval(span),
+                    source,
+                    lit(ScalarValue::TimestampNanosecond(Some(0), None)),
+                ))
+            }
+            "not" => Ok(Expr::Not(Box::new(self.single_argument(call)?))),
+            "iff" => {
+                self.check_argument_count(&call, 3)?;
+                self.lower_case(call)
+            }
+            "case" => {
+                if call.args.len() < 3 || call.args.len().is_multiple_of(2) {
+                    self.diagnostics.push(
+                        diagnostics::wrong_argument_count(hashmap! {
+                            "function" => call.function.name.clone(),
+                            "expected" => "an odd number (at least 3) of".to_string(),
+                            "actual" => call.args.len().to_string(),
+                        })
+                        .with_labels(vec![Label::primary(self.file_id, call.span)]),
+                    );
+
+                    return Err(LowerError::InvalidQuery);
+                }
+
+                self.lower_case(call)
+            }
+            "coalesce" => {
+                if call.args.len() < 2 {
+                    self.diagnostics.push(
+                        diagnostics::wrong_argument_count(hashmap! {
+                            "function" => call.function.name.clone(),
+                            "expected" => "at least 2".to_string(),
+                            "actual" => call.args.len().to_string(),
+                        })
+                        .with_labels(vec![Label::primary(self.file_id, call.span)]),
+                    );
+
+                    return Err(LowerError::InvalidQuery);
+                }
+
+                let name = call.function.name.clone();
+                let mut args = vec![];
+                for arg in call.args {
+                    let span = arg.span();
+                    args.push((span, self.lower_expr(arg)?));
+                }
+
+                Ok(expr_fn::coalesce(self.unify_branches(&name, args)?))
+            }
+            "isnull" => Ok(self.single_argument(call)?.is_null()),
+            "isnotnull" => Ok(self.single_argument(call)?.is_not_null()),
+            "isempty" => {
+                let arg = self.single_argument(call)?;
+                Ok(self.is_empty(arg))
+            }
+            "isnotempty" => {
+                let arg = self.single_argument(call)?;
+                Ok(Expr::Not(Box::new(self.is_empty(arg))))
+            }
+            name if is_window_function(name) => self.lower_window_function(call),
+            name if is_aggregate_function(name) => {
+                self.diagnostics.push(
+                    diagnostics::misplaced_aggregate(hashmap! {
+                        "function" => call.function.name.clone(),
+                    })
+                    .with_labels(vec![Label::primary(self.file_id, call.function.span)]),
+                );
+
+                Err(LowerError::InvalidQuery)
+            }
+            _ => {
+                let Some(function) = self.stored_function(&call) else {
+                    return Err(LowerError::InvalidQuery);
+                };
+
+                self.expand_function(
+                    call,
+                    function,
+                    FunctionKind::Scalar,
+                    |body| {
+                        parser::parse_expression(body)
+                            .map_err(|e| (e.to_string(), parser::error_span(&e)))
+                    },
+                    |this, expr| this.lower_expr(expr),
+                )
+            }
+        }
+    }
+
+    /// Lowers `iff(condition, then, else)` and `case(condition, then, ..., else)` to a `CASE` expression.
+    fn lower_case(&mut self, call: CallExpression) -> LowerResult<Expr> {
+        let name = call.function.name.clone();
+        let mut conditions = vec![];
+        let mut values = vec![];
+        for (i, arg) in call.args.into_iter().enumerate() {
+            let span = arg.span();
+            let expr = self.lower_expr(arg)?;
+            if i % 2 == 1 {
+                values.push((span, expr));
+            } else {
+                conditions.push((span, expr));
+            }
+        }
+
+        // The final argument is the value when no condition holds.
+        let otherwise = conditions
+            .pop()
+            .expect("case has an odd number of arguments");
+        values.push(otherwise);
+        for (span, condition) in &conditions {
+            let ty = self.expr_type(condition);
+            if !matches!(ty, None | Some(DataType::Boolean) | Some(DataType::Null)) {
+                self.diagnostics.push(
+                    diagnostics::invalid_argument(hashmap! {
+                        "function" => name.clone(),
+                        "expected" => "a boolean condition".to_string(),
+                    })
+                    .with_labels(vec![Label::primary(self.file_id, *span)]),
+                );
+
+                return Err(LowerError::InvalidQuery);
+            }
+        }
+
+        let mut values = self.unify_branches(&name, values)?;
+        let otherwise = values
+            .pop()
+            .expect("case has a value when no condition holds");
+        let mut branches = conditions.into_iter().zip(values);
+        let ((_, condition), value) = branches.next().expect("case has a condition");
+        let mut case = expr_fn::when(condition, value);
+        for ((_, condition), value) in branches {
+            case.when(condition, value);
+        }
+
+        Ok(case.otherwise(otherwise)?)
+    }
+
+    /// Converts values that are alternatives for a single result, such as the branches of `case`, to a common type.
+    fn unify_branches(
+        &mut self,
+        function: &str,
+        branches: Vec<(Span, Expr)>,
+    ) -> LowerResult<Vec<Expr>> {
+        let mut unified: Option<(Span, DataType)> = None;
+        for (span, expr) in &branches {
+            let Some(ty) = self.expr_type(expr) else {
+                // Leave types that can't be determined yet to DataFusion.
+                return Ok(branches.into_iter().map(|(_, expr)| expr).collect());
+            };
+
+            unified = match unified {
+                None => Some((*span, ty)),
+                Some((first_span, first)) => match unify_types(&first, &ty) {
+                    Some(ty) => Some((first_span, ty)),
+                    None => {
+                        self.diagnostics.push(
+                            diagnostics::incompatible_branches(hashmap! {
+                                "function" => function.to_string(),
+                                "first" => first.to_string(),
+                                "second" => ty.to_string(),
+                            })
+                            .with_labels(vec![
+                                Label::primary(self.file_id, *span)
+                                    .with_message(format!("this has type `{}`", ty)),
+                                Label::secondary(self.file_id, first_span)
+                                    .with_message(format!("this has type `{}`", first)),
+                            ]),
+                        );
+
+                        return Err(LowerError::InvalidQuery);
+                    }
+                },
+            };
+        }
+
+        let (_, unified) = unified.expect("there is at least one branch");
+        let mut exprs = vec![];
+        for (_, expr) in branches {
+            if self.expr_type(&expr).as_ref() == Some(&unified) {
+                exprs.push(expr);
+            } else {
+                exprs.push(expr_fn::cast(expr, unified.clone()));
+            }
+        }
+
+        Ok(exprs)
+    }
+
+    /// Whether a value is null or, for strings, empty.
+    fn is_empty(&self, expr: Expr) -> Expr {
+        if self.expr_type(&expr) == Some(DataType::Utf8) {
+            expr.clone().is_null().or(expr.eq(lit("")))
+        } else {
+            expr.is_null()
+        }
+    }
+
+    /// The type of an expression evaluated against the current input, if it can be determined.
+    fn expr_type(&self, expr: &Expr) -> Option<DataType> {
+        expr.get_type(self.schema.as_ref()).ok()
+    }
+
+    fn lower_window_function(&mut self, call: CallExpression) -> LowerResult<Expr> {
+        if self.window_exprs.is_none() {
+            self.diagnostics.push(
+                diagnostics::misplaced_window_function(hashmap! {
+                    "function" => call.function.name.clone(),
+                })
+                .with_labels(vec![Label::primary(self.file_id, call.function.span)]),
+            );
+
+            return Err(LowerError::InvalidQuery);
+        }
+
+        let Some(ordering) = self.ordering.clone() else {
+            self.diagnostics.push(
+                diagnostics::unordered_window_function(hashmap! {
+                    "function" => call.function.name.clone(),
+                })
+                .with_labels(vec![Label::primary(self.file_id, call.function.span)]),
+            );
+
+            return Err(LowerError::InvalidQuery);
+        };
+
+        let mut partition_by = vec![];
+        let (function, args, window_frame) = match call.function.name.as_str() {
+            "prev" | "next" => {
+                if call.args.is_empty() || call.args.len() > 3 {
+                    self.diagnostics.push(
+                        diagnostics::wrong_argument_count(hashmap! {
+                            "function" => call.function.name.clone(),
+                            "expected" => "1 to 3".to_string(),
+                            "actual" => call.args.len().to_string(),
+                        })
+                        .with_labels(vec![Label::primary(self.file_id, call.span)]),
+                    );
+
+                    return Err(LowerError::InvalidQuery);
+                }
+
+                let function = if call.function.name == "prev" {
+                    BuiltInWindowFunction::Lag
+                } else {
+                    BuiltInWindowFunction::Lead
+                };
+                let mut args = vec![];
+                for arg in call.args {
+                    args.push(self.lower_expr(arg)?);
+                }
+
+                (
+                    WindowFunction::BuiltInWindowFunction(function),
+                    args,
+                    WindowFrame::new(!ordering.is_empty()),
+                )
+            }
+            "row_number" | "row_rank" => {
+                self.check_argument_count(&call, 0)?;
+                let function = if call.function.name == "row_number" {
+                    BuiltInWindowFunction::RowNumber
+                } else {
+                    BuiltInWindowFunction::Rank
+                };
+
+                (
+                    WindowFunction::BuiltInWindowFunction(function),
+                    vec![],
+                    WindowFrame::new(!ordering.is_empty()),
+                )
+            }
+            "row_cumsum" => {
+                let arg = self.single_argument(call)?;
+                let frame = WindowFrame {
+                    units: WindowFrameUnits::Rows,
+                    start_bound: WindowFrameBound::Preceding(ScalarValue::UInt64(None)),
+                    end_bound: WindowFrameBound::CurrentRow,
+                };
+
+                (
+                    WindowFunction::AggregateFunction(AggregateFunction::Sum),
+                    vec![arg],
+                    frame,
+                )
+            }
+            name => {
+                let function = SeriesFunction::from_name(name).expect("not a window function");
+                self.check_argument_count(&call, 2)?;
+                let mut args = call.args.into_iter();
+                let (series, parameter) = (args.next().unwrap(), args.next().unwrap());
+                let span = series.span();
+                let series = self.lower_expr(series)?;
+                if !self.expr_type(&series).is_some_and(|ty| ty.is_numeric()) {
+                    self.diagnostics.push(
+                        diagnostics::invalid_argument(hashmap! {
+                            "function" => name.to_string(),
+                            "expected" => "a number".to_string(),
+                        })
+                        .with_labels(vec![Label::primary(self.file_id, span)]),
+                    );
+
+                    return Err(LowerError::InvalidQuery);
+                }
+
+                let span = parameter.span();
+                let parameter = self.lower_expr(parameter)?;
+                let value = match &parameter {
+                    Expr::Literal(ScalarValue::Int64(Some(value))) => Some(*value as f64),
+                    Expr::Literal(ScalarValue::Float64(Some(value))) => Some(*value),
+                    _ => None,
+                };
+                if !value.is_some_and(|value| function.accepts(value)) {
+                    self.diagnostics.push(
+                        diagnostics::invalid_argument(hashmap! {
+                            "function" => name.to_string(),
+                            "expected" => function.expected().to_string(),
+                        })
+                        .with_labels(vec![Label::primary(self.file_id, span)]),
+                    );
+
+                    return Err(LowerError::InvalidQuery);
+                }
+
+                partition_by = self.series_by.clone();
+                (
+                    WindowFunction::WindowUDF(function.udwf()),
+                    vec![series, parameter],
+                    WindowFrame::new(!ordering.is_empty()),
+                )
+            }
+        };
+
+        let window_exprs = self
+            .window_exprs
+            .as_mut()
+            .expect("window functions are allowed");
+        let name = format!("__window_{}", window_exprs.len());
+        window_exprs.push(
+            Expr::WindowFunction(expr::WindowFunction::new(
+                function,
+                args,
+                partition_by,
+                ordering,
+                window_frame,
+            ))
+            .alias(&name),
+        );
+
+        Ok(unqualified(&name))
+    }
+
+    fn check_argument_count(&mut self, call: &CallExpression, expected: usize) -> LowerResult<()> {
+        if call.args.len() != expected {
+            self.diagnostics.push(
+                diagnostics::wrong_argument_count(hashmap! {
+                    "function" => call.function.name.clone(),
+                    "expected" => expected.to_string(),
+                    "actual" => call.args.len().to_string(),
+                })
+                .with_labels(vec![Label::primary(self.file_id, call.span)]),
+            );
+
+            return Err(LowerError::InvalidQuery);
+        }
+
+        Ok(())
+    }
+
+    fn single_argument(&mut self, call: CallExpression) -> LowerResult<Expr> {
+        self.check_argument_count(&call, 1)?;
+        let arg = call.args.into_iter().next().unwrap();
+        self.lower_expr(arg)
+    }
+
+    fn timespan_argument(
+        &mut self,
+        call: &CallExpression,
+        index: usize,
+    ) -> LowerResult<chrono::Duration> {
+        let arg = &call.args[index];
+        if let Expression::Literal(LiteralExpression {
+            value: Literal::Timespan(span),
+            ..
+        }) = arg
+        {
+            return Ok(*span);
+        }
+
+        self.diagnostics.push(
+            diagnostics::invalid_argument(hashmap! {
+                "function" => call.function.name.clone(),
+                "expected" => "a timespan literal".to_string(),
+            })
+            .with_labels(vec![Label::primary(self.file_id, arg.span())]),
+        );
+
+        Err(LowerError::InvalidQuery)
+    }
+}
+
+fn is_aggregate_function(name: &str) -> bool {
+    matches!(
+        name,
+        "count" | "dcount" | "count_distinct" | "sum" | "min" | "max" | "avg"
+    )
+}
+
+fn is_window_function(name: &str) -> bool {
+    matches!(
+        name,
+        "prev" | "next" | "row_number" | "row_rank" | "row_cumsum"
+    ) || SeriesFunction::from_name(name).is_some()
+}
+
+/// Returns the PQL name of a type, as accepted in declarations; other types are `dynamic`.
+fn pql_type_name(data_type: &DataType) -> &'static str {
+    match data_type {
+        DataType::Boolean => "bool",
+        DataType::Int32 => "int",
+        DataType::Int64 => "long",
+        DataType::Float64 => "real",
+        DataType::Utf8 => "string",
+        DataType::Timestamp(TimeUnit::Millisecond, None) => "datetime",
+        _ => "dynamic",
+    }
+}
+
+/// Whether an expression is a constant other than zero or null, which can never make a division fail.
+fn is_nonzero_constant(expr: &Expr) -> bool {
+    let Expr::Literal(value) = expr else {
+        return false;
+    };
+
+    !value.is_null() && ScalarValue::new_zero(&value.data_type()).is_ok_and(|zero| *value != zero)
+}
+
+/// Finds the type that values of two types can both be converted to without changing their meaning: numbers can be
+/// widened and nulls take on any type, but strings, booleans and numbers are never mixed.
+fn unify_types(first: &DataType, second: &DataType) -> Option<DataType> {
+    if first == second || *second == DataType::Null {
+        return Some(first.clone());
+    }
+
+    if *first == DataType::Null {
+        return Some(second.clone());
+    }
+
+    let numeric = first.is_numeric() && second.is_numeric();
+    let temporal = first.is_temporal() && second.is_temporal();
+    if numeric || temporal {
+        return comparison_coercion(first, second);
+    }
+
+    None
+}
+
+fn literal_value(literal: Literal) -> ScalarValue {
+    match literal {
+        Literal::Integer(value) => ScalarValue::Int64(Some(value)),
+        Literal::String(value) => ScalarValue::Utf8(Some(value)),
+        Literal::Bool(value) => ScalarValue::Boolean(Some(value)),
+        Literal::Real(value) => ScalarValue::Float64(Some(value)),
+        Literal::Timespan(value) => interval_value(value),
+    }
+}
+
+/// Converts a parameter or datatable value to its declared type. Values must already have the declared type, except
+/// that integers are accepted for any numeric type.
+fn coerce_value(value: &ScalarValue, ty: &DataType) -> Option<ScalarValue> {
+    if value.data_type() == *ty {
+        return Some(value.clone());
+    }
+
+    if !value.data_type().is_integer() || !ty.is_numeric() {
+        return None;
+    }
+
+    let array = compute::cast(&value.to_array(), ty).ok()?;
+    ScalarValue::try_from_array(&array, 0).ok()
+}
+
+fn timestamp(instant: DateTime<Utc>) -> Expr {
+    timestamp_millis(instant.timestamp_millis())
+}
+
+fn timestamp_millis(millis: i64) -> Expr {
+    lit(ScalarValue::TimestampMillisecond(Some(millis), None))
+}
+
+fn unqualified(name: &str) -> Expr {
+    Expr::Column(Column::from_name(name))
+}
+
+fn interval(span: chrono::Duration) -> Expr {
+    lit(interval_value(span))
+}
+
+fn interval_value(span: chrono::Duration) -> ScalarValue {
+    let nanos = span
+        .num_nanoseconds()
+        .expect("timespan literals are bounded by the parser");
+    ScalarValue::IntervalMonthDayNano(Some(IntervalMonthDayNanoType::make_value(0, 0, nanos)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, path::PathBuf, sync::Arc};
+
+    use chrono::{DateTime, TimeZone, Utc};
+    use chrono_tz::Tz;
+    use codespan::Files;
+    use codespan_reporting::term::{self, termcolor::NoColor};
+    use datafusion::{
+        arrow::{
+            datatypes::{DataType, IntervalUnit, Schema, TimeUnit},
+            error::ArrowError,
+        },
+        error::DataFusionError,
+        logical_expr::{builder::LogicalTableSource, expr_fn, lit, Expr, LogicalPlanBuilder},
+        scalar::ScalarValue,
+    };
+    use expect_test::{expect, Expect};
+    use maplit::hashmap;
+
+    use super::{Feature, FunctionKind, Lowerer, QueryContext, StoredFunction, DENY_ALL_WARNINGS};
+    use crate::{
+        ast::Query,
+        parser::parse,
+        plugin::{EvaluatePlugin, PluginArguments, PluginError, PluginParameter},
+    };
+
+    macro_rules! schema {
+        ($($key:expr => $value:expr),*) => {
+            {
+                use datafusion::arrow::datatypes::{Schema, Field};
+
+                let fields = vec![
+                    $(
+                        Field::new($key, $value, true),
+                    )*
+                ];
+                Schema::new(fields)
+            }
+        };
+    }
+
+    struct TestQueryContext {
+        pub tenant_id: String,
+        pub start_time: DateTime<Utc>,
+        pub time_zone: Tz,
+        pub default_row_limit: Option<usize>,
+        pub max_row_limit: Option<usize>,
+        pub features: Vec<Feature>,
+        pub functions: Vec<StoredFunction>,
+        pub denied_warnings: Vec<String>,
+        pub external_data_root: Option<PathBuf>,
+        pub plugins: HashMap<String, Arc<dyn EvaluatePlugin>>,
+    }
+
+    impl TestQueryContext {
+        pub fn new(tenant_id: impl AsRef<str>) -> TestQueryContext {
+            TestQueryContext {
+                tenant_id: tenant_id.as_ref().to_string(),
+                start_time: Utc.with_ymd_and_hms(2023, 11, 1, 12, 0, 0).unwrap(),
+                time_zone: Tz::UTC,
+                default_row_limit: None,
+                max_row_limit: None,
+                features: vec![],
+                functions: vec![],
+                denied_warnings: vec![],
+                external_data_root: None,
+                plugins: HashMap::new(),
+            }
+        }
+    }
+
+    impl QueryContext for TestQueryContext {
+        fn get_tenant_id(&self) -> &str {
+            &self.tenant_id
+        }
+
+        fn get_query_start_time(&self) -> DateTime<Utc> {
+            self.start_time
+        }
+
+        fn get_time_zone(&self) -> Tz {
+            self.time_zone
+        }
+
+        fn get_default_row_limit(&self) -> Option<usize> {
+            self.default_row_limit
+        }
+
+        fn get_max_row_limit(&self) -> Option<usize> {
+            self.max_row_limit
+        }
+
+        fn is_feature_enabled(&self, feature: Feature) -> bool {
+            self.features.contains(&feature)
+        }
+
+        fn get_function(&self, name: &str) -> Option<StoredFunction> {
+            self.functions.iter().find(|f| f.name == name).cloned()
+        }
+
+        fn get_denied_warnings(&self) -> Vec<String> {
+            self.denied_warnings.clone()
+        }
+
+        fn get_external_data_root(&self) -> Option<PathBuf> {
+            self.external_data_root.clone()
+        }
+
+        fn get_plugin(&self, name: &str) -> Option<Arc<dyn EvaluatePlugin>> {
+            self.plugins.get(name).cloned()
+        }
+    }
+
+    /// Adds a column that buckets `timestamp` into steps of the given length.
+    struct BucketPlugin;
+
+    impl EvaluatePlugin for BucketPlugin {
+        fn parameters(&self) -> Vec<PluginParameter> {
+            vec![
+                PluginParameter::required("size", DataType::Interval(IntervalUnit::MonthDayNano)),
+                PluginParameter::optional("name", ScalarValue::from("bucket")),
+            ]
+        }
+
+        fn evaluate(
+            &self,
+            builder: LogicalPlanBuilder,
+            args: &PluginArguments,
+        ) -> Result<LogicalPlanBuilder, PluginError> {
+            let Some(ScalarValue::IntervalMonthDayNano(Some(size))) = args.get("size") else {
+                unreachable!("arguments are checked against the parameters");
+            };
+            if *size <= 0 {
+                return Err(PluginError::new("`size` must be positive").with_argument("size"));
+            }
+
+            let name = args.get("name").unwrap().to_string();
+            let mut columns: Vec<Expr> = builder
+                .schema()
+                .fields()
+                .iter()
+                .map(|field| Expr::Column(field.qualified_column()))
+                .collect();
+            columns.push(
+                expr_fn::date_bin(
+                    lit(ScalarValue::IntervalMonthDayNano(Some(*size))),
+                    expr_fn::col("timestamp"),
+                    lit(ScalarValue::TimestampMillisecond(Some(0), None)),
+                )
+                .alias(name),
+            );
+            Ok(builder.project(columns)?)
+        }
+    }
+
+    fn with_bucket_plugin(mut ctx: TestQueryContext) -> TestQueryContext {
+        ctx.plugins
+            .insert("bucket".to_string(), Arc::new(BucketPlugin));
+        ctx
+    }
+
+    fn lowerer(ctx: TestQueryContext, schema: Schema, input: &str) -> (Lowerer, Query) {
+        let source = LogicalTableSource::new(Arc::new(schema));
+        let mut files: Files<String> = Files::new();
+        let fileid = files.add("query", input.to_string());
+        let query = parse(input).unwrap();
+        let lowerer = Lowerer::new(
+            Arc::new(ctx),
+            Arc::new(source),
+            files,
+            fileid,
+            "http_requests".to_string(),
+        );
+        (lowerer, query)
+    }
+
+    fn check(ctx: TestQueryContext, schema: Schema, input: &str, expect: Expect) {
+        check_with_parameters(ctx, schema, input, HashMap::new(), expect);
+    }
+
+    fn check_with_parameters(
+        ctx: TestQueryContext,
+        schema: Schema,
+        input: &str,
+        parameters: HashMap<String, ScalarValue>,
+        expect: Expect,
+    ) {
+        let (mut lowerer, query) = lowerer(ctx, schema, input);
+        let plan = lowerer.lower(query, &parameters).unwrap();
+        expect.assert_debug_eq(&plan);
+    }
+
+    fn check_with_diagnostics(ctx: TestQueryContext, schema: Schema, input: &str, expect: Expect) {
+        let (mut lowerer, query) = lowerer(ctx, schema, input);
+        let plan = lowerer.lower(query, &HashMap::new()).unwrap();
+        let report = lowerer.into_report();
+        let diags = report.diagnostics();
+        expect.assert_debug_eq(&(plan, diags));
+    }
+
+    fn check_visualization(ctx: TestQueryContext, schema: Schema, input: &str, expect: Expect) {
+        let (mut lowerer, query) = lowerer(ctx, schema, input);
+        lowerer.lower(query, &HashMap::new()).unwrap();
+        expect.assert_debug_eq(&lowerer.visualization());
+    }
+
+    fn check_source_map(ctx: TestQueryContext, schema: Schema, input: &str, expect: Expect) {
+        let (mut lowerer, query) = lowerer(ctx, schema, input);
+        let plan = lowerer.lower(query, &HashMap::new()).unwrap();
+        expect.assert_eq(&lowerer.source_map().display_annotated(&plan));
+    }
+
+    fn check_execution_error(
+        ctx: TestQueryContext,
+        schema: Schema,
+        input: &str,
+        error: DataFusionError,
+        expect: Expect,
+    ) {
+        let (mut lowerer, query) = lowerer(ctx, schema, input);
+        let plan = lowerer.lower(query, &HashMap::new()).unwrap();
+        let source_map = lowerer.source_map();
+        let report = lowerer
+            .into_report()
+            .execution_error(&source_map, &plan, &error);
+        let mut writer = NoColor::new(Vec::new());
+        report.emit(&mut writer, &term::Config::default()).unwrap();
+        expect.assert_eq(&String::from_utf8(writer.into_inner()).unwrap());
+    }
+
+    fn check_facets(ctx: TestQueryContext, schema: Schema, input: &str, expect: Expect) {
+        let (mut lowerer, query) = lowerer(ctx, schema, input);
+        lowerer.lower(query, &HashMap::new()).unwrap();
+        let facets: String = lowerer
+            .facets()
+            .iter()
+            .map(|facet| format!("{}:\n{}\n", facet.name, facet.plan.display_indent()))
+            .collect();
+        expect.assert_eq(&facets);
+    }
+
+    fn check_err(ctx: TestQueryContext, schema: Schema, input: &str, expect: Expect) {
+        check_err_with_parameters(ctx, schema, input, HashMap::new(), expect);
+    }
+
+    fn check_err_with_parameters(
+        ctx: TestQueryContext,
+        schema: Schema,
+        input: &str,
+        parameters: HashMap<String, ScalarValue>,
+        expect: Expect,
+    ) {
+        let (mut lowerer, query) = lowerer(ctx, schema, input);
+        let _ = lowerer.lower(query, &parameters).unwrap_err();
+        let report = lowerer.into_report();
+        let diags = report.diagnostics();
+        expect.assert_debug_eq(&diags);
+    }
+
+    #[test]
+    fn basic_count() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "bytes" => DataType::UInt64,
+            "method" => DataType::Utf8
+        };
+
+        let e = expect![[r#"
+            Aggregate: groupBy=[[]], aggr=[[COUNT(*)]]
+              TableScan: prism.tenant.http_requests
+        "#]];
+        check(ctx, schema, "http_requests | count", e);
+    }
+
+    #[test]
+    fn basic_count_by() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "bytes" => DataType::UInt64,
+            "method" => DataType::Utf8
+        };
+
+        let e = expect![[r#"
+            Aggregate: groupBy=[[prism.tenant.http_requests.method]], aggr=[[COUNT(*)]]
+              TableScan: prism.tenant.http_requests
+        "#]];
+        check(ctx, schema, "http_requests | count by method", e);
+    }
+
+    #[test]
+    fn count_by_invalid_column() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "bytes" => DataType::UInt64,
+            "method" => DataType::Utf8
+        };
+
+        let e = expect![[r#"
+            [
+                Diagnostic {
+                    severity: Error,
+                    code: Some(
+                        "E0001",
+                    ),
+                    message: "column `something` does not exist on table `http_requests`",
+                    labels: [
+                        Label {
+                            style: Primary,
+                            file_id: FileId(
+                                1,
+                            ),
+                            range: 25..34,
+                            message: "",
+                        },
+                    ],
+                    notes: [],
+                },
+            ]
+        "#]];
+        check_err(ctx, schema, "http_requests | count by something", e);
+    }
+
+    #[test]
+    fn where_ago() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "timestamp" => DataType::Timestamp(TimeUnit::Millisecond, None),
+            "method" => DataType::Utf8
+        };
+
+        let e = expect![[r#"
+            Aggregate: groupBy=[[]], aggr=[[COUNT(*)]]
+              Filter: prism.tenant.http_requests.timestamp > TimestampMillisecond(1698836400000, None) AND prism.tenant.http_requests.method = Utf8("GET")
+                TableScan: prism.tenant.http_requests
+        "#]];
+        check(
+            ctx,
+            schema,
+            r#"http_requests | where timestamp > ago(1h) and method == "GET" | count"#,
+            e,
+        );
+    }
+
+    #[test]
+    fn count_by_bin() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "timestamp" => DataType::Timestamp(TimeUnit::Millisecond, None)
+        };
+
+        let e = expect![[r#"
+            Aggregate: groupBy=[[date_bin(IntervalMonthDayNano("86400000000000"), prism.tenant.http_requests.timestamp, TimestampNanosecond(0, None))]], aggr=[[COUNT(*)]]
+              TableScan: prism.tenant.http_requests
+        "#]];
+        check(
+            ctx,
+            schema,
+            "http_requests | count by bin(timestamp, 1d)",
+            e,
+        );
+    }
+
+    #[test]
+    fn count_by_bin_local_time() {
+        let mut ctx = TestQueryContext::new("tenant");
+        ctx.time_zone = Tz::America__Los_Angeles;
+        ctx.features.push(Feature::LocalTimeBins);
+        let schema = schema! {
+            "timestamp" => DataType::Timestamp(TimeUnit::Millisecond, None)
+        };
+
+        let e = expect![[r#"
+            Aggregate: groupBy=[[local_bin(prism.tenant.http_requests.timestamp, IntervalMonthDayNano("86400000000000"), Utf8("America/Los_Angeles"))]], aggr=[[COUNT(*)]]
+              TableScan: prism.tenant.http_requests
+        "#]];
+        check(
+            ctx,
+            schema,
+            "http_requests | count by bin(timestamp, 1d)",
+            e,
+        );
+    }
+
+    #[test]
+    fn implicit_limit() {
+        let mut ctx = TestQueryContext::new("tenant");
+        ctx.default_row_limit = Some(1000);
+        let schema = schema! {
+            "bytes" => DataType::UInt64,
+            "method" => DataType::Utf8
+        };
+
+        let e = expect![[r#"
+            (
+                Limit: skip=0, fetch=1000
+                  Filter: prism.tenant.http_requests.bytes > Int64(100)
+                    TableScan: prism.tenant.http_requests,
+                [
+                    Diagnostic {
+                        severity: Warning,
+                        code: Some(
+                            "W0001",
+                        ),
+                        message: "query has no aggregation or limit; results are truncated to 1000 rows",
+                        labels: [
+                            Label {
+                                style: Primary,
+                                file_id: FileId(
+                                    1,
+                                ),
+                                range: 0..33,
+                                message: "",
+                            },
+                        ],
+                        notes: [],
+                    },
+                ],
+            )
+        "#]];
+        check_with_diagnostics(ctx, schema, "http_requests | where bytes > 100", e);
+    }
+
+    #[test]
+    fn explicit_limit() {
+        let mut ctx = TestQueryContext::new("tenant");
+        ctx.default_row_limit = Some(1000);
+        let schema = schema! {
+            "bytes" => DataType::UInt64,
+            "method" => DataType::Utf8
+        };
+
+        let e = expect![[r#"
+            (
+                Limit: skip=0, fetch=10
+                  TableScan: prism.tenant.http_requests,
+                [],
+            )
+        "#]];
+        check_with_diagnostics(ctx, schema, "http_requests | take 10", e);
+    }
+
+    #[test]
+    fn take_exceeds_max_row_limit() {
+        let mut ctx = TestQueryContext::new("tenant");
+        ctx.max_row_limit = Some(5000);
+        let schema = schema! {
+            "bytes" => DataType::UInt64,
+            "method" => DataType::Utf8
+        };
+
+        let e = expect![[r#"
+            [
+                Diagnostic {
+                    severity: Error,
+                    code: Some(
+                        "E0005",
+                    ),
+                    message: "query requests 10000 rows, but at most 5000 rows may be returned",
+                    labels: [
+                        Label {
+                            style: Primary,
+                            file_id: FileId(
+                                1,
+                            ),
+                            range: 21..26,
+                            message: "",
+                        },
+                    ],
+                    notes: [],
+                },
+            ]
+        "#]];
+        check_err(ctx, schema, "http_requests | take 10000", e);
+    }
+
+    #[test]
+    fn ago_requires_timespan() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "timestamp" => DataType::Timestamp(TimeUnit::Millisecond, None)
+        };
+
+        let e = expect![[r#"
+            [
+                Diagnostic {
+                    severity: Error,
+                    code: Some(
+                        "E0004",
+                    ),
+                    message: "argument to function `ago` must be a timespan literal",
+                    labels: [
+                        Label {
+                            style: Primary,
+                            file_id: FileId(
+                                1,
+                            ),
+                            range: 38..39,
+                            message: "",
+                        },
+                    ],
+                    notes: [],
+                },
+            ]
+        "#]];
+        check_err(ctx, schema, "http_requests | where timestamp > ago(5)", e);
+    }
+
+    #[test]
+    fn unknown_function() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "timestamp" => DataType::Timestamp(TimeUnit::Millisecond, None)
+        };
+
+        let e = expect![[r#"
+            [
+                Diagnostic {
+                    severity: Error,
+                    code: Some(
+                        "E0002",
+                    ),
+                    message: "unknown function `yesterday`",
+                    labels: [
+                        Label {
+                            style: Primary,
+                            file_id: FileId(
+                                1,
+                            ),
+                            range: 34..43,
+                            message: "",
+                        },
+                    ],
+                    notes: [],
+                },
+            ]
+        "#]];
+        check_err(
+            ctx,
+            schema,
+            "http_requests | where timestamp > yesterday()",
+            e,
+        );
+    }
+
+    #[test]
+    fn summarize_dcount() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "host" => DataType::Utf8,
+            "method" => DataType::Utf8
+        };
+
+        let e = expect![[r#"
+            Filter: hosts > Int64(1)
+              Aggregate: groupBy=[[prism.tenant.http_requests.method]], aggr=[[COUNT(DISTINCT prism.tenant.http_requests.host) AS hosts, COUNT(*)]]
+                TableScan: prism.tenant.http_requests
+        "#]];
+        check(
+            ctx,
+            schema,
+            "http_requests | summarize hosts = dcount(host), count() by method | where hosts > 1",
+            e,
+        );
+    }
+
+    #[test]
+    fn summarize_non_aggregate() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "host" => DataType::Utf8,
+            "method" => DataType::Utf8
+        };
+
+        let e = expect![[r#"
+            [
+                Diagnostic {
+                    severity: Error,
+                    code: Some(
+                        "E0007",
+                    ),
+                    message: "`summarize` expects a call to an aggregate function",
+                    labels: [
+                        Label {
+                            style: Primary,
+                            file_id: FileId(
+                                1,
+                            ),
+                            range: 26..30,
+                            message: "",
+                        },
+                    ],
+                    notes: [],
+                },
+            ]
+        "#]];
+        check_err(ctx, schema, "http_requests | summarize host by method", e);
+    }
+
+    #[test]
+    fn aggregate_outside_summarize() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "host" => DataType::Utf8,
+            "method" => DataType::Utf8
+        };
+
+        let e = expect![[r#"
+            [
+                Diagnostic {
+                    severity: Error,
+                    code: Some(
+                        "E0006",
+                    ),
+                    message: "aggregate function `dcount` can only be used in `summarize`",
+                    labels: [
+                        Label {
+                            style: Primary,
+                            file_id: FileId(
+                                1,
+                            ),
+                            range: 22..28,
+                            message: "",
+                        },
+                    ],
+                    notes: [],
+                },
+            ]
+        "#]];
+        check_err(ctx, schema, "http_requests | where dcount(host) > 1", e);
+    }
+
+    #[test]
+    fn distinct() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "host" => DataType::Utf8,
+            "method" => DataType::Utf8,
+            "path" => DataType::Utf8
+        };
+
+        let e = expect![[r#"
+            Distinct:
+              Projection: prism.tenant.http_requests.host, prism.tenant.http_requests.method
+                Filter: prism.tenant.http_requests.path = Utf8("/login")
+                  TableScan: prism.tenant.http_requests
+        "#]];
+        check(
+            ctx,
+            schema,
+            r#"http_requests | where path == "/login" | distinct host, method"#,
+            e,
+        );
+    }
+
+    #[test]
+    fn make_series() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "timestamp" => DataType::Timestamp(TimeUnit::Millisecond, None),
+            "host" => DataType::Utf8
+        };
+
+        let e = expect![[r#"
+            Sort: host ASC NULLS LAST, timestamp ASC NULLS LAST
+              Projection: prism.tenant.http_requests.host AS host, (series)[timestamp] AS timestamp, coalesce((series)[COUNT(*)], Int64(0)) AS COUNT(*)
+                Unnest: series
+                  Aggregate: groupBy=[[prism.tenant.http_requests.host]], aggr=[[make_series(timestamp, COUNT(*)) AS series]]
+                    Aggregate: groupBy=[[prism.tenant.http_requests.host, date_bin(IntervalMonthDayNano("60000000000"), prism.tenant.http_requests.timestamp, TimestampNanosecond(1698839700000000000, None)) AS timestamp]], aggr=[[COUNT(*)]]
+                      Filter: prism.tenant.http_requests.timestamp >= TimestampMillisecond(1698839700000, None) AND prism.tenant.http_requests.timestamp < TimestampMillisecond(1698840000000, None)
+                        TableScan: prism.tenant.http_requests
+        "#]];
+        check(
+            ctx,
+            schema,
+            "http_requests | make-series count() default=0 on timestamp from ago(5m) to now() step 1m by host",
+            e,
+        );
+    }
+
+    #[test]
+    fn series_functions_per_group() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "timestamp" => DataType::Timestamp(TimeUnit::Millisecond, None),
+            "host" => DataType::Utf8
+        };
+
+        let e = expect![[r#"
+            Projection: host, timestamp, requests, __window_0 AS average, __window_1 AS anomaly
+              WindowAggr: windowExpr=[[series_moving_avg(requests, Int64(2)) PARTITION BY [host] ORDER BY [host ASC NULLS LAST, timestamp ASC NULLS LAST] RANGE BETWEEN UNBOUNDED PRECEDING AND CURRENT ROW AS __window_0, series_mad_anomalies(requests, Float64(3.5)) PARTITION BY [host] ORDER BY [host ASC NULLS LAST, timestamp ASC NULLS LAST] RANGE BETWEEN UNBOUNDED PRECEDING AND CURRENT ROW AS __window_1]]
+                Sort: host ASC NULLS LAST, timestamp ASC NULLS LAST
+                  Projection: prism.tenant.http_requests.host AS host, (series)[timestamp] AS timestamp, coalesce((series)[requests], Int64(0)) AS requests
+                    Unnest: series
+                      Aggregate: groupBy=[[prism.tenant.http_requests.host]], aggr=[[make_series(timestamp, requests) AS series]]
+                        Aggregate: groupBy=[[prism.tenant.http_requests.host, date_bin(IntervalMonthDayNano("60000000000"), prism.tenant.http_requests.timestamp, TimestampNanosecond(1698839820000000000, None)) AS timestamp]], aggr=[[COUNT(*) AS requests]]
+                          Filter: prism.tenant.http_requests.timestamp >= TimestampMillisecond(1698839820000, None) AND prism.tenant.http_requests.timestamp < TimestampMillisecond(1698840000000, None)
+                            TableScan: prism.tenant.http_requests
+        "#]];
+        check(
+            ctx,
+            schema,
+            "http_requests | make-series requests = count() default=0 on timestamp from ago(3m) to now() step 1m by host | extend average = series_moving_avg(requests, 2), anomaly = series_mad_anomalies(requests, 3.5)",
+            e,
+        );
+    }
+
+    #[test]
+    fn series_functions_after_sort() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "timestamp" => DataType::Timestamp(TimeUnit::Millisecond, None),
+            "bytes" => DataType::Int64
+        };
+
+        let e = expect![[r#"
+            Projection: prism.tenant.http_requests.timestamp, prism.tenant.http_requests.bytes, __window_0 AS smooth
+              WindowAggr: windowExpr=[[series_exp_smooth(prism.tenant.http_requests.bytes, Float64(0.3)) ORDER BY [prism.tenant.http_requests.timestamp ASC NULLS LAST] RANGE BETWEEN UNBOUNDED PRECEDING AND CURRENT ROW AS __window_0]]
+                Limit: skip=0, fetch=100
+                  Sort: prism.tenant.http_requests.timestamp ASC NULLS LAST
+                    TableScan: prism.tenant.http_requests
+        "#]];
+        check(
+            ctx,
+            schema,
+            "http_requests | sort by timestamp | take 100 | extend smooth = series_exp_smooth(bytes, 0.3)",
+            e,
+        );
+    }
+
+    #[test]
+    fn invalid_series_parameter() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "timestamp" => DataType::Timestamp(TimeUnit::Millisecond, None),
+            "bytes" => DataType::Int64
+        };
+
+        let e = expect![[r#"
+            [
+                Diagnostic {
+                    severity: Error,
+                    code: Some(
+                        "E0004",
+                    ),
+                    message: "argument to function `series_exp_smooth` must be a smoothing factor greater than 0 and at most 1",
+                    labels: [
+                        Label {
+                            style: Primary,
+                            file_id: FileId(
+                                1,
+                            ),
+                            range: 88..89,
+                            message: "",
+                        },
+                    ],
+                    notes: [],
+                },
+            ]
+        "#]];
+        check_err(
+            ctx,
+            schema,
+            "http_requests | sort by timestamp | take 100 | extend smooth = series_exp_smooth(bytes, 2)",
+            e,
+        );
+    }
+
+    #[test]
+    fn make_series_without_groups() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "timestamp" => DataType::Timestamp(TimeUnit::Millisecond, None),
+            "bytes" => DataType::Int64
+        };
+
+        let e = expect![[r#"
+            Sort: timestamp ASC NULLS LAST
+              Projection: (series)[timestamp] AS timestamp, coalesce((series)[total], Int64(0)) AS total
+                Unnest: series
+                  Aggregate: groupBy=[[]], aggr=[[make_series(timestamp, total) AS series]]
+                    Aggregate: groupBy=[[date_bin(IntervalMonthDayNano("60000000000"), prism.tenant.http_requests.timestamp, TimestampNanosecond(1698839880000000000, None)) AS timestamp]], aggr=[[SUM(prism.tenant.http_requests.bytes) AS total]]
+                      Filter: prism.tenant.http_requests.timestamp >= TimestampMillisecond(1698839880000, None) AND prism.tenant.http_requests.timestamp < TimestampMillisecond(1698840000000, None)
+                        TableScan: prism.tenant.http_requests
+        "#]];
+        check(
+            ctx,
+            schema,
+            "http_requests | make-series total = sum(bytes) on timestamp from ago(2m) to now() step 1m",
+            e,
+        );
+    }
+
+    #[test]
+    fn make_series_too_many_buckets() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "timestamp" => DataType::Timestamp(TimeUnit::Millisecond, None)
+        };
+
+        let e = expect![[r#"
+            [
+                Diagnostic {
+                    severity: Error,
+                    code: Some(
+                        "E0010",
+                    ),
+                    message: "series has 2592000 buckets, but at most 10000 are allowed",
+                    labels: [
+                        Label {
+                            style: Primary,
+                            file_id: FileId(
+                                1,
+                            ),
+                            range: 77..79,
+                            message: "",
+                        },
+                    ],
+                    notes: [],
+                },
+            ]
+        "#]];
+        check_err(
+            ctx,
+            schema,
+            "http_requests | make-series count() on timestamp from ago(30d) to now() step 1s",
+            e,
+        );
+    }
+
+    #[test]
+    fn make_series_non_constant_range() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "timestamp" => DataType::Timestamp(TimeUnit::Millisecond, None)
+        };
+
+        let e = expect![[r#"
+            [
+                Diagnostic {
+                    severity: Error,
+                    code: Some(
+                        "E0008",
+                    ),
+                    message: "`from` must be a constant timestamp, such as `ago(1h)` or `now()`",
+                    labels: [
+                        Label {
+                            style: Primary,
+                            file_id: FileId(
+                                1,
+                            ),
+                            range: 54..63,
+                            message: "",
+                        },
+                    ],
+                    notes: [],
+                },
+            ]
+        "#]];
+        check_err(
+            ctx,
+            schema,
+            "http_requests | make-series count() on timestamp from timestamp to now() step 1m",
+            e,
+        );
+    }
+
+    #[test]
+    fn extend_prev() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "timestamp" => DataType::Timestamp(TimeUnit::Millisecond, None),
+            "bytes" => DataType::Int64
+        };
+
+        let e = expect![[r#"
+            Projection: prism.tenant.http_requests.timestamp, prism.tenant.http_requests.bytes, prism.tenant.http_requests.timestamp - __window_0 AS gap, __window_1 AS total
+              WindowAggr: windowExpr=[[LAG(prism.tenant.http_requests.timestamp) ORDER BY [prism.tenant.http_requests.timestamp ASC NULLS LAST] RANGE BETWEEN UNBOUNDED PRECEDING AND CURRENT ROW AS __window_0, SUM(prism.tenant.http_requests.bytes) ORDER BY [prism.tenant.http_requests.timestamp ASC NULLS LAST] ROWS BETWEEN UNBOUNDED PRECEDING AND CURRENT ROW AS __window_1]]
+                Sort: prism.tenant.http_requests.timestamp ASC NULLS LAST
+                  TableScan: prism.tenant.http_requests
+        "#]];
+        check(
+            ctx,
+            schema,
+            "http_requests | sort by timestamp | extend gap = timestamp - prev(timestamp), total = row_cumsum(bytes)",
+            e,
+        );
+    }
+
+    #[test]
+    fn serialize_row_number() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "timestamp" => DataType::Timestamp(TimeUnit::Millisecond, None),
+            "bytes" => DataType::Int64
+        };
+
+        let e = expect![[r#"
+            Projection: prism.tenant.http_requests.timestamp, __window_1 AS bytes, __window_0 AS rank
+              WindowAggr: windowExpr=[[RANK() ORDER BY [prism.tenant.http_requests.bytes DESC NULLS FIRST] RANGE BETWEEN UNBOUNDED PRECEDING AND CURRENT ROW AS __window_0, LEAD(prism.tenant.http_requests.bytes, Int64(1), Int64(0)) ORDER BY [prism.tenant.http_requests.bytes DESC NULLS FIRST] RANGE BETWEEN UNBOUNDED PRECEDING AND CURRENT ROW AS __window_1]]
+                Sort: prism.tenant.http_requests.bytes DESC NULLS FIRST
+                  TableScan: prism.tenant.http_requests
+        "#]];
+        check(
+            ctx,
+            schema,
+            "http_requests | sort by bytes desc | serialize rank = row_rank(), bytes = next(bytes, 1, 0)",
+            e,
+        );
+    }
+
+    #[test]
+    fn window_function_after_replacing_sort_column() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "timestamp" => DataType::Timestamp(TimeUnit::Millisecond, None),
+            "bytes" => DataType::Int64
+        };
+
+        let e = expect![[r#"
+            Projection: prism.tenant.http_requests.timestamp, bytes, __window_0 AS n
+              WindowAggr: windowExpr=[[ROW_NUMBER() ROWS BETWEEN UNBOUNDED PRECEDING AND UNBOUNDED FOLLOWING AS __window_0]]
+                Projection: prism.tenant.http_requests.timestamp, __window_0 AS bytes
+                  WindowAggr: windowExpr=[[LEAD(prism.tenant.http_requests.bytes, Int64(1), Int64(0)) ORDER BY [prism.tenant.http_requests.bytes ASC NULLS LAST] RANGE BETWEEN UNBOUNDED PRECEDING AND CURRENT ROW AS __window_0]]
+                    Sort: prism.tenant.http_requests.bytes ASC NULLS LAST
+                      TableScan: prism.tenant.http_requests
+        "#]];
+        check(
+            ctx,
+            schema,
+            "http_requests | sort by bytes | extend bytes = next(bytes, 1, 0) | extend n = row_number()",
+            e,
+        );
+    }
+
+    #[test]
+    fn extend_existing_column() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "timestamp" => DataType::Timestamp(TimeUnit::Millisecond, None),
+            "bytes" => DataType::Int64
+        };
+
+        let e = expect![[r#"
+            Projection: prism.tenant.http_requests.timestamp, prism.tenant.http_requests.bytes, prism.tenant.http_requests.bytes AS size
+              TableScan: prism.tenant.http_requests
+        "#]];
+        check(ctx, schema, "http_requests | extend bytes, size = bytes", e);
+    }
+
+    #[test]
+    fn prev_argument_count() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "bytes" => DataType::Int64
+        };
+
+        let e = expect![[r#"
+            [
+                Diagnostic {
+                    severity: Error,
+                    code: Some(
+                        "E0003",
+                    ),
+                    message: "function `prev` expects 1 to 3 argument(s), but 0 were given",
+                    labels: [
+                        Label {
+                            style: Primary,
+                            file_id: FileId(
+                                1,
+                            ),
+                            range: 43..49,
+                            message: "",
+                        },
+                    ],
+                    notes: [],
+                },
+            ]
+        "#]];
+        check_err(
+            ctx,
+            schema,
+            "http_requests | sort by bytes | extend p = prev()",
+            e,
+        );
+    }
+
+    #[test]
+    fn window_function_without_order() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "timestamp" => DataType::Timestamp(TimeUnit::Millisecond, None),
+            "bytes" => DataType::Int64
+        };
+
+        let e = expect![[r#"
+            [
+                Diagnostic {
+                    severity: Error,
+                    code: Some(
+                        "E0012",
+                    ),
+                    message: "window function `row_number` requires ordered rows; add `sort by` or `serialize` before it",
+                    labels: [
+                        Label {
+                            style: Primary,
+                            file_id: FileId(
+                                1,
+                            ),
+                            range: 27..37,
+                            message: "",
+                        },
+                    ],
+                    notes: [],
+                },
+            ]
+        "#]];
+        check_err(ctx, schema, "http_requests | extend n = row_number()", e);
+    }
+
+    #[test]
+    fn window_function_in_where() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "timestamp" => DataType::Timestamp(TimeUnit::Millisecond, None),
+            "bytes" => DataType::Int64
+        };
+
+        let e = expect![[r#"
+            [
+                Diagnostic {
+                    severity: Error,
+                    code: Some(
+                        "E0011",
+                    ),
+                    message: "window function `prev` can only be used in `extend` or `serialize`",
+                    labels: [
+                        Label {
+                            style: Primary,
+                            file_id: FileId(
+                                1,
+                            ),
+                            range: 42..46,
+                            message: "",
+                        },
+                    ],
+                    notes: [],
+                },
+            ]
+        "#]];
+        check_err(
+            ctx,
+            schema,
+            "http_requests | sort by timestamp | where prev(bytes) > bytes",
+            e,
+        );
+    }
+
+    #[test]
+    fn mv_expand() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "host" => DataType::Utf8,
+            "tags" => DataType::List(Arc::new(Field::new("item", DataType::Utf8, true)))
+        };
+
+        let e = expect![[r#"
+            Aggregate: groupBy=[[prism.tenant.http_requests.tags]], aggr=[[COUNT(*)]]
+              Unnest: prism.tenant.http_requests.tags
+                TableScan: prism.tenant.http_requests
+        "#]];
+        check(
+            ctx,
+            schema,
+            "http_requests | mv-expand tags | count by tags",
+            e,
+        );
+    }
+
+    #[test]
+    fn mv_expand_to_type() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "host" => DataType::Utf8,
+            "codes" => DataType::List(Arc::new(Field::new("item", DataType::Utf8, true)))
+        };
+
+        let e = expect![[r#"
+            Filter: codes >= Int64(500)
+              Projection: prism.tenant.http_requests.host, CAST(prism.tenant.http_requests.codes AS Int64) AS codes
+                Unnest: prism.tenant.http_requests.codes
+                  TableScan: prism.tenant.http_requests
+        "#]];
+        check(
+            ctx,
+            schema,
+            "http_requests | mv-expand codes to typeof(long) | where codes >= 500",
+            e,
+        );
+    }
+
+    #[test]
+    fn mv_expand_non_array() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "host" => DataType::Utf8
+        };
+
+        let e = expect![[r#"
+            [
+                Diagnostic {
+                    severity: Error,
+                    code: Some(
+                        "E0013",
+                    ),
+                    message: "column `host` has type `Utf8`, but `mv-expand` requires an array column",
+                    labels: [
+                        Label {
+                            style: Primary,
+                            file_id: FileId(
+                                1,
+                            ),
+                            range: 26..30,
+                            message: "",
+                        },
+                    ],
+                    notes: [],
+                },
+            ]
+        "#]];
+        check_err(ctx, schema, "http_requests | mv-expand host", e);
+    }
+
+    fn function(name: &str, kind: FunctionKind, parameters: &[&str], body: &str) -> StoredFunction {
+        StoredFunction {
+            name: name.to_string(),
+            kind,
+            parameters: parameters.iter().map(|p| p.to_string()).collect(),
+            body: body.to_string(),
+        }
+    }
+
+    #[test]
+    fn stored_functions() {
+        let mut ctx = TestQueryContext::new("tenant");
+        ctx.functions.push(function(
+            "is_internal",
+            FunctionKind::Scalar,
+            &["address"],
+            r#"address startswith "10.""#,
+        ));
+        ctx.functions.push(function(
+            "external_requests",
+            FunctionKind::Tabular,
+            &[],
+            "where not(is_internal(host))",
+        ));
+        let schema = schema! {
+            "host" => DataType::Utf8,
+            "method" => DataType::Utf8
+        };
+
+        let e = expect![[r#"
+            Aggregate: groupBy=[[prism.tenant.http_requests.method]], aggr=[[COUNT(*)]]
+              Filter: NOT starts_with(prism.tenant.http_requests.host, Utf8("10."))
+                TableScan: prism.tenant.http_requests
+        "#]];
+        check(
+            ctx,
+            schema,
+            "http_requests | invoke external_requests() | count by method",
+            e,
+        );
+    }
+
+    #[test]
+    fn stored_function_error_in_body() {
+        let mut ctx = TestQueryContext::new("tenant");
+        ctx.functions.push(function(
+            "slow_requests",
+            FunctionKind::Tabular,
+            &["threshold"],
+            "where duration > threshold",
+        ));
+        let schema = schema! {
+            "host" => DataType::Utf8,
+            "method" => DataType::Utf8
+        };
+
+        let e = expect![[r#"
+            [
+                Diagnostic {
+                    severity: Error,
+                    code: Some(
                         "E0001",
                     ),
-                    message: "column `something` does not exist on table `http_requests`",
+                    message: "column `duration` does not exist on table `http_requests`",
+                    labels: [
+                        Label {
+                            style: Primary,
+                            file_id: FileId(
+                                2,
+                            ),
+                            range: 6..14,
+                            message: "",
+                        },
+                        Label {
+                            style: Secondary,
+                            file_id: FileId(
+                                1,
+                            ),
+                            range: 23..41,
+                            message: "in this call to `slow_requests`",
+                        },
+                    ],
+                    notes: [],
+                },
+            ]
+        "#]];
+        check_err(ctx, schema, "http_requests | invoke slow_requests(500)", e);
+    }
+
+    #[test]
+    fn stored_function_parse_error() {
+        let mut ctx = TestQueryContext::new("tenant");
+        ctx.functions.push(function(
+            "slow_requests",
+            FunctionKind::Tabular,
+            &[],
+            "where duration > 500 | take",
+        ));
+        let schema = schema! {
+            "duration" => DataType::Int64
+        };
+
+        let e = expect![[r##"
+            [
+                Diagnostic {
+                    severity: Error,
+                    code: Some(
+                        "E0016",
+                    ),
+                    message: "body of function `slow_requests` is invalid: Unrecognized EOF found at 27\nExpected one of r#\"[0-9]+\"#",
+                    labels: [
+                        Label {
+                            style: Primary,
+                            file_id: FileId(
+                                1,
+                            ),
+                            range: 23..38,
+                            message: "",
+                        },
+                        Label {
+                            style: Secondary,
+                            file_id: FileId(
+                                2,
+                            ),
+                            range: 27..27,
+                            message: "",
+                        },
+                    ],
+                    notes: [],
+                },
+            ]
+        "##]];
+        check_err(ctx, schema, "http_requests | invoke slow_requests()", e);
+    }
+
+    #[test]
+    fn tabular_function_column_argument() {
+        let mut ctx = TestQueryContext::new("tenant");
+        ctx.functions.push(function(
+            "top_hosts",
+            FunctionKind::Tabular,
+            &["by"],
+            "summarize n = count() by host | where n > by",
+        ));
+        let schema = schema! {
+            "host" => DataType::Utf8,
+            "bytes" => DataType::Int64
+        };
+
+        let e = expect![[r#"
+            [
+                Diagnostic {
+                    severity: Error,
+                    code: Some(
+                        "E0045",
+                    ),
+                    message: "argument `by` of tabular function `top_hosts` must be a constant value",
+                    labels: [
+                        Label {
+                            style: Primary,
+                            file_id: FileId(
+                                1,
+                            ),
+                            range: 33..38,
+                            message: "",
+                        },
+                    ],
+                    notes: [],
+                },
+            ]
+        "#]];
+        check_err(ctx, schema, "http_requests | invoke top_hosts(bytes)", e);
+    }
+
+    #[test]
+    fn parameter_shadows_column() {
+        let mut ctx = TestQueryContext::new("tenant");
+        ctx.functions.push(function(
+            "is_large",
+            FunctionKind::Scalar,
+            &["bytes"],
+            "bytes > 1000",
+        ));
+        let schema = schema! {
+            "status" => DataType::Int64,
+            "bytes" => DataType::Int64
+        };
+
+        let e = expect![[r#"
+            (
+                Filter: prism.tenant.http_requests.status > Int64(1000)
+                  TableScan: prism.tenant.http_requests,
+                [
+                    Diagnostic {
+                        severity: Warning,
+                        code: Some(
+                            "W0005",
+                        ),
+                        message: "parameter `bytes` of function `is_large` has the same name as a column, which it hides",
+                        labels: [
+                            Label {
+                                style: Primary,
+                                file_id: FileId(
+                                    2,
+                                ),
+                                range: 0..5,
+                                message: "",
+                            },
+                            Label {
+                                style: Secondary,
+                                file_id: FileId(
+                                    1,
+                                ),
+                                range: 22..38,
+                                message: "in this call to `is_large`",
+                            },
+                        ],
+                        notes: [],
+                    },
+                ],
+            )
+        "#]];
+        check_with_diagnostics(ctx, schema, "http_requests | where is_large(status)", e);
+    }
+
+    #[test]
+    fn recursive_stored_functions() {
+        let mut ctx = TestQueryContext::new("tenant");
+        ctx.functions
+            .push(function("ping", FunctionKind::Scalar, &["x"], "pong(x)"));
+        ctx.functions
+            .push(function("pong", FunctionKind::Scalar, &["x"], "ping(x)"));
+        let schema = schema! {
+            "host" => DataType::Utf8
+        };
+
+        let e = expect![[r#"
+            [
+                Diagnostic {
+                    severity: Error,
+                    code: Some(
+                        "E0015",
+                    ),
+                    message: "function `ping` calls itself recursively",
+                    labels: [
+                        Label {
+                            style: Primary,
+                            file_id: FileId(
+                                3,
+                            ),
+                            range: 0..7,
+                            message: "",
+                        },
+                        Label {
+                            style: Secondary,
+                            file_id: FileId(
+                                1,
+                            ),
+                            range: 22..32,
+                            message: "`ping` called here",
+                        },
+                        Label {
+                            style: Secondary,
+                            file_id: FileId(
+                                2,
+                            ),
+                            range: 0..7,
+                            message: "`pong` called here",
+                        },
+                    ],
+                    notes: [],
+                },
+            ]
+        "#]];
+        check_err(ctx, schema, "http_requests | where ping(host)", e);
+    }
+
+    #[test]
+    fn invoke_scalar_function() {
+        let mut ctx = TestQueryContext::new("tenant");
+        ctx.functions.push(function(
+            "is_internal",
+            FunctionKind::Scalar,
+            &["address"],
+            r#"address startswith "10.""#,
+        ));
+        let schema = schema! {
+            "host" => DataType::Utf8
+        };
+
+        let e = expect![[r#"
+            [
+                Diagnostic {
+                    severity: Error,
+                    code: Some(
+                        "E0017",
+                    ),
+                    message: "`is_internal` is a scalar function, but a tabular function is required here",
+                    labels: [
+                        Label {
+                            style: Primary,
+                            file_id: FileId(
+                                1,
+                            ),
+                            range: 23..34,
+                            message: "",
+                        },
+                    ],
+                    notes: [],
+                },
+            ]
+        "#]];
+        check_err(ctx, schema, "http_requests | invoke is_internal(host)", e);
+    }
+
+    #[test]
+    fn declared_parameters() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "status" => DataType::Int64,
+            "method" => DataType::Utf8
+        };
+
+        let e = expect![[r#"
+            Aggregate: groupBy=[[]], aggr=[[COUNT(*)]]
+              Filter: prism.tenant.http_requests.status >= Int64(500) AND prism.tenant.http_requests.method = Utf8("GET")
+                TableScan: prism.tenant.http_requests
+        "#]];
+        check_with_parameters(
+            ctx,
+            schema,
+            r#"declare query_parameters(min_status: long = 500, verb: string); http_requests | where status >= $min_status and method == $verb | count"#,
+            hashmap! { "verb".to_string() => ScalarValue::Utf8(Some("GET".to_string())) },
+            e,
+        );
+    }
+
+    #[test]
+    fn placeholder_parameters() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "status" => DataType::Int64,
+            "method" => DataType::Utf8
+        };
+
+        let e = expect![[r#"
+            Aggregate: groupBy=[[]], aggr=[[COUNT(*)]]
+              Filter: prism.tenant.http_requests.status >= Int64(400)
+                TableScan: prism.tenant.http_requests
+        "#]];
+        check_with_parameters(
+            ctx,
+            schema,
+            "http_requests | where status >= $min_status | count",
+            hashmap! { "min_status".to_string() => ScalarValue::Int64(Some(400)) },
+            e,
+        );
+    }
+
+    #[test]
+    fn invalid_parameters() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "status" => DataType::Int64,
+            "method" => DataType::Utf8
+        };
+
+        let e = expect![[r#"
+            [
+                Diagnostic {
+                    severity: Error,
+                    code: Some(
+                        "E0018",
+                    ),
+                    message: "no value was provided for query parameter `min_status`",
+                    labels: [
+                        Label {
+                            style: Primary,
+                            file_id: FileId(
+                                1,
+                            ),
+                            range: 25..35,
+                            message: "",
+                        },
+                    ],
+                    notes: [],
+                },
+                Diagnostic {
+                    severity: Error,
+                    code: Some(
+                        "E0020",
+                    ),
+                    message: "query parameter `verb` has type `string`, but was given a value of type `Int64`",
+                    labels: [
+                        Label {
+                            style: Primary,
+                            file_id: FileId(
+                                1,
+                            ),
+                            range: 43..47,
+                            message: "",
+                        },
+                    ],
+                    notes: [],
+                },
+            ]
+        "#]];
+        check_err_with_parameters(
+            ctx,
+            schema,
+            r#"declare query_parameters(min_status: long, verb: string); http_requests | where status >= $min_status and method == $verb"#,
+            hashmap! { "verb".to_string() => ScalarValue::Int64(Some(1)) },
+            e,
+        );
+    }
+
+    #[test]
+    fn declared_parameter_named_like_column() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "status" => DataType::Int64
+        };
+
+        // Without a `$`, the name refers to the column, with a warning at each use.
+        let e = expect![[r#"
+            (
+                Filter: prism.tenant.http_requests.status >= Int64(500) AND prism.tenant.http_requests.status < Int64(600)
+                  TableScan: prism.tenant.http_requests,
+                [
+                    Diagnostic {
+                        severity: Warning,
+                        code: Some(
+                            "W0006",
+                        ),
+                        message: "query parameter `status` has the same name as a column; `status` refers to the column and `$status` to the parameter",
+                        labels: [
+                            Label {
+                                style: Primary,
+                                file_id: FileId(
+                                    1,
+                                ),
+                                range: 68..74,
+                                message: "",
+                            },
+                        ],
+                        notes: [],
+                    },
+                    Diagnostic {
+                        severity: Warning,
+                        code: Some(
+                            "W0006",
+                        ),
+                        message: "query parameter `status` has the same name as a column; `status` refers to the column and `$status` to the parameter",
+                        labels: [
+                            Label {
+                                style: Primary,
+                                file_id: FileId(
+                                    1,
+                                ),
+                                range: 90..96,
+                                message: "",
+                            },
+                        ],
+                        notes: [],
+                    },
+                ],
+            )
+        "#]];
+        check_with_diagnostics(
+            ctx,
+            schema,
+            "declare query_parameters(status: long = 500); http_requests | where status >= $status and status < 600",
+            e,
+        );
+    }
+
+    #[test]
+    fn declared_parameter_by_name() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "status" => DataType::Int64
+        };
+
+        let e = expect![[r#"
+            Filter: prism.tenant.http_requests.status >= Int64(500)
+              TableScan: prism.tenant.http_requests
+        "#]];
+        check(
+            ctx,
+            schema,
+            "declare query_parameters(min_status: long = 500); http_requests | where status >= min_status",
+            e,
+        );
+    }
+
+    #[test]
+    fn mistyped_placeholder() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "status" => DataType::Int64,
+            "method" => DataType::Utf8
+        };
+
+        let e = expect![[r#"
+            [
+                Diagnostic {
+                    severity: Error,
+                    code: Some(
+                        "E0046",
+                    ),
+                    message: "query parameter `min_status` is used as a `long`, but was given a value of type `Utf8`",
+                    labels: [
+                        Label {
+                            style: Primary,
+                            file_id: FileId(
+                                1,
+                            ),
+                            range: 32..43,
+                            message: "",
+                        },
+                    ],
+                    notes: [],
+                },
+            ]
+        "#]];
+        check_err_with_parameters(
+            ctx,
+            schema,
+            "http_requests | where status >= $min_status",
+            hashmap! { "min_status".to_string() => ScalarValue::Utf8(Some("500".to_string())) },
+            e,
+        );
+    }
+
+    #[test]
+    fn unexpected_parameters() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "status" => DataType::Int64,
+            "method" => DataType::Utf8
+        };
+
+        let e = expect![[r#"
+            [
+                Diagnostic {
+                    severity: Error,
+                    code: Some(
+                        "E0019",
+                    ),
+                    message: "a value was provided for query parameter `min_status`, but the query does not use it",
+                    labels: [],
+                    notes: [],
+                },
+            ]
+        "#]];
+        check_err_with_parameters(
+            ctx,
+            schema,
+            "http_requests | count",
+            hashmap! { "min_status".to_string() => ScalarValue::Int64(Some(500)) },
+            e,
+        );
+    }
+
+    #[test]
+    fn no_time_filter() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "timestamp" => DataType::Timestamp(TimeUnit::Millisecond, None),
+            "method" => DataType::Utf8
+        };
+
+        let e = expect![[r#"
+            (
+                Aggregate: groupBy=[[prism.tenant.http_requests.method]], aggr=[[COUNT(*)]]
+                  TableScan: prism.tenant.http_requests,
+                [
+                    Diagnostic {
+                        severity: Warning,
+                        code: Some(
+                            "W0002",
+                        ),
+                        message: "query has no filter on `timestamp` and will scan all partitions of `http_requests`",
+                        labels: [
+                            Label {
+                                style: Primary,
+                                file_id: FileId(
+                                    1,
+                                ),
+                                range: 0..13,
+                                message: "",
+                            },
+                        ],
+                        notes: [],
+                    },
+                ],
+            )
+        "#]];
+        check_with_diagnostics(ctx, schema, "http_requests | count by method", e);
+    }
+
+    #[test]
+    fn string_integer_comparison() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "status" => DataType::Utf8
+        };
+
+        let e = expect![[r#"
+            (
+                Aggregate: groupBy=[[]], aggr=[[COUNT(*)]]
+                  Filter: prism.tenant.http_requests.status = Int64(404)
+                    TableScan: prism.tenant.http_requests,
+                [
+                    Diagnostic {
+                        severity: Warning,
+                        code: Some(
+                            "W0003",
+                        ),
+                        message: "comparison of string column `status` to integer literal",
+                        labels: [
+                            Label {
+                                style: Primary,
+                                file_id: FileId(
+                                    1,
+                                ),
+                                range: 22..35,
+                                message: "",
+                            },
+                        ],
+                        notes: [],
+                    },
+                ],
+            )
+        "#]];
+        check_with_diagnostics(
+            ctx,
+            schema,
+            "http_requests | where status == 404 | count",
+            e,
+        );
+    }
+
+    #[test]
+    fn sort_without_limit() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "bytes" => DataType::UInt64
+        };
+
+        let e = expect![[r#"
+            (
+                Sort: prism.tenant.http_requests.bytes DESC NULLS FIRST
+                  TableScan: prism.tenant.http_requests,
+                [
+                    Diagnostic {
+                        severity: Warning,
+                        code: Some(
+                            "W0004",
+                        ),
+                        message: "`sort` without a following `take` sorts every row of its input",
+                        labels: [
+                            Label {
+                                style: Primary,
+                                file_id: FileId(
+                                    1,
+                                ),
+                                range: 16..34,
+                                message: "",
+                            },
+                        ],
+                        notes: [],
+                    },
+                ],
+            )
+        "#]];
+        check_with_diagnostics(ctx, schema, "http_requests | sort by bytes desc", e);
+    }
+
+    #[test]
+    fn sort_with_limit() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "bytes" => DataType::UInt64
+        };
+
+        let e = expect![[r#"
+            (
+                Limit: skip=0, fetch=10
+                  Sort: prism.tenant.http_requests.bytes DESC NULLS FIRST
+                    TableScan: prism.tenant.http_requests,
+                [],
+            )
+        "#]];
+        check_with_diagnostics(
+            ctx,
+            schema,
+            "http_requests | sort by bytes desc | take 10",
+            e,
+        );
+    }
+
+    #[test]
+    fn exact_dcount() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "host" => DataType::Utf8
+        };
+
+        let e = expect![[r#"
+            (
+                Aggregate: groupBy=[[]], aggr=[[COUNT(DISTINCT prism.tenant.http_requests.host)]]
+                  TableScan: prism.tenant.http_requests,
+                [
+                    Diagnostic {
+                        severity: Note,
+                        code: Some(
+                            "N0001",
+                        ),
+                        message: "`dcount` counts distinct values exactly, which can be expensive for columns with many distinct values",
+                        labels: [
+                            Label {
+                                style: Primary,
+                                file_id: FileId(
+                                    1,
+                                ),
+                                range: 26..32,
+                                message: "",
+                            },
+                        ],
+                        notes: [],
+                    },
+                ],
+            )
+        "#]];
+        check_with_diagnostics(ctx, schema, "http_requests | summarize dcount(host)", e);
+    }
+
+    #[test]
+    fn denied_warning() {
+        let mut ctx = TestQueryContext::new("tenant");
+        ctx.denied_warnings = vec!["W0004".to_string()];
+        let schema = schema! {
+            "bytes" => DataType::UInt64
+        };
+
+        let e = expect![[r#"
+            [
+                Diagnostic {
+                    severity: Error,
+                    code: Some(
+                        "W0004",
+                    ),
+                    message: "`sort` without a following `take` sorts every row of its input",
+                    labels: [
+                        Label {
+                            style: Primary,
+                            file_id: FileId(
+                                1,
+                            ),
+                            range: 16..34,
+                            message: "",
+                        },
+                    ],
+                    notes: [
+                        "`W0004` is denied for this tenant",
+                    ],
+                },
+            ]
+        "#]];
+        check_err(ctx, schema, "http_requests | sort by bytes desc", e);
+    }
+
+    #[test]
+    fn deny_all_warnings() {
+        let mut ctx = TestQueryContext::new("tenant");
+        ctx.denied_warnings = vec![DENY_ALL_WARNINGS.to_string()];
+        let schema = schema! {
+            "timestamp" => DataType::Timestamp(TimeUnit::Millisecond, None),
+            "status" => DataType::Utf8
+        };
+
+        let e = expect![[r#"
+            [
+                Diagnostic {
+                    severity: Error,
+                    code: Some(
+                        "W0003",
+                    ),
+                    message: "comparison of string column `status` to integer literal",
+                    labels: [
+                        Label {
+                            style: Primary,
+                            file_id: FileId(
+                                1,
+                            ),
+                            range: 22..35,
+                            message: "",
+                        },
+                    ],
+                    notes: [
+                        "`W0003` is denied for this tenant",
+                    ],
+                },
+                Diagnostic {
+                    severity: Error,
+                    code: Some(
+                        "W0002",
+                    ),
+                    message: "query has no filter on `timestamp` and will scan all partitions of `http_requests`",
+                    labels: [
+                        Label {
+                            style: Primary,
+                            file_id: FileId(
+                                1,
+                            ),
+                            range: 0..13,
+                            message: "",
+                        },
+                    ],
+                    notes: [
+                        "`W0002` is denied for this tenant",
+                    ],
+                },
+            ]
+        "#]];
+        check_err(
+            ctx,
+            schema,
+            "http_requests | where status == 404 | count",
+            e,
+        );
+    }
+
+    #[test]
+    fn datatable_lookup() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "status" => DataType::Utf8,
+            "bytes" => DataType::Int64
+        };
+
+        let e = expect![[r#"
+            Aggregate: groupBy=[[codes.meaning]], aggr=[[COUNT(*)]]
+              Projection: prism.tenant.http_requests.status, prism.tenant.http_requests.bytes, codes.meaning
+                Left Join: prism.tenant.http_requests.status = codes.status
+                  TableScan: prism.tenant.http_requests
+                  SubqueryAlias: codes
+                    Projection: column1 AS status, column2 AS meaning
+                      Values: (Utf8("500"), Utf8("ISE")), (Utf8("404"), Utf8("Not Found"))
+        "#]];
+        check(
+            ctx,
+            schema,
+            r#"let codes = datatable(status: string, meaning: string) ["500", "ISE", "404", "Not Found"];
+http_requests | lookup codes on status | count by meaning"#,
+            e,
+        );
+    }
+
+    #[test]
+    fn ragged_datatable() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "status" => DataType::Utf8
+        };
+
+        let e = expect![[r#"
+            [
+                Diagnostic {
+                    severity: Error,
+                    code: Some(
+                        "E0021",
+                    ),
+                    message: "datatable has 3 value(s), which is not a multiple of its 2 column(s)",
+                    labels: [
+                        Label {
+                            style: Primary,
+                            file_id: FileId(
+                                1,
+                            ),
+                            range: 12..76,
+                            message: "",
+                        },
+                    ],
+                    notes: [],
+                },
+            ]
+        "#]];
+        check_err(
+            ctx,
+            schema,
+            r#"let codes = datatable(status: string, meaning: string) ["500", "ISE", "404"];
+http_requests | lookup codes on status | count"#,
+            e,
+        );
+    }
+
+    #[test]
+    fn mistyped_datatable_value() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "status" => DataType::Utf8
+        };
+
+        let e = expect![[r#"
+            [
+                Diagnostic {
+                    severity: Error,
+                    code: Some(
+                        "E0022",
+                    ),
+                    message: "column `status` is declared as `long`, but was given a value of type `Utf8`",
+                    labels: [
+                        Label {
+                            style: Primary,
+                            file_id: FileId(
+                                1,
+                            ),
+                            range: 66..71,
+                            message: "",
+                        },
+                    ],
+                    notes: [],
+                },
+            ]
+        "#]];
+        check_err(
+            ctx,
+            schema,
+            r#"let codes = datatable(status: long, meaning: string) [500, "ISE", "404", "Not Found"];
+http_requests | lookup codes on status | count"#,
+            e,
+        );
+    }
+
+    #[test]
+    fn mismatched_lookup_column() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "status" => DataType::Utf8
+        };
+
+        let e = expect![[r#"
+            [
+                Diagnostic {
+                    severity: Error,
+                    code: Some(
+                        "E0024",
+                    ),
+                    message: "column `status` has type `Utf8`, but has type `Int64` in `codes`",
+                    labels: [
+                        Label {
+                            style: Primary,
+                            file_id: FileId(
+                                1,
+                            ),
+                            range: 99..105,
+                            message: "",
+                        },
+                    ],
+                    notes: [],
+                },
+            ]
+        "#]];
+        check_err(
+            ctx,
+            schema,
+            r#"let codes = datatable(status: long, meaning: string) [500, "ISE"];
+http_requests | lookup codes on status | count"#,
+            e,
+        );
+    }
+
+    #[test]
+    fn unknown_table() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "status" => DataType::Utf8
+        };
+
+        let e = expect![[r#"
+            [
+                Diagnostic {
+                    severity: Error,
+                    code: Some(
+                        "E0023",
+                    ),
+                    message: "table `codes` is not defined; define it with `let` before the query",
+                    labels: [
+                        Label {
+                            style: Primary,
+                            file_id: FileId(
+                                1,
+                            ),
+                            range: 23..28,
+                            message: "",
+                        },
+                    ],
+                    notes: [],
+                },
+            ]
+        "#]];
+        check_err(
+            ctx,
+            schema,
+            "http_requests | lookup codes on status | count",
+            e,
+        );
+    }
+
+    #[test]
+    fn external_data() {
+        let mut ctx = TestQueryContext::new("tenant");
+        ctx.external_data_root = Some(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("testdata"));
+        let schema = schema! {
+            "status" => DataType::Utf8
+        };
+
+        let e = expect![[r#"
+            Aggregate: groupBy=[[codes.meaning]], aggr=[[COUNT(*)]]
+              Projection: prism.tenant.http_requests.status, codes.meaning
+                Left Join: prism.tenant.http_requests.status = codes.status
+                  TableScan: prism.tenant.http_requests
+                  SubqueryAlias: codes
+                    TableScan: codes
+        "#]];
+        check(
+            ctx,
+            schema,
+            r#"let codes = externaldata(status: string, meaning: string) ["status_codes.csv"];
+http_requests | lookup codes on status | count by meaning"#,
+            e,
+        );
+    }
+
+    #[test]
+    fn forbidden_external_data() {
+        let mut ctx = TestQueryContext::new("tenant");
+        ctx.external_data_root = Some(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("testdata"));
+        let schema = schema! {
+            "status" => DataType::Utf8
+        };
+
+        let e = expect![[r#"
+            [
+                Diagnostic {
+                    severity: Error,
+                    code: Some(
+                        "E0025",
+                    ),
+                    message: "`externaldata` may not read `../Cargo.toml`",
+                    labels: [
+                        Label {
+                            style: Primary,
+                            file_id: FileId(
+                                1,
+                            ),
+                            range: 59..74,
+                            message: "",
+                        },
+                    ],
+                    notes: [],
+                },
+            ]
+        "#]];
+        check_err(
+            ctx,
+            schema,
+            r#"let codes = externaldata(status: string, meaning: string) ["../Cargo.toml"] with (format = "csv");
+http_requests | lookup codes on status | count"#,
+            e,
+        );
+    }
+
+    #[test]
+    fn sample_rows() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "bytes" => DataType::Int64
+        };
+
+        let e = expect![[r#"
+            Limit: skip=0, fetch=100
+              Sort: random() ASC NULLS LAST
+                TableScan: prism.tenant.http_requests
+        "#]];
+        check(ctx, schema, "http_requests | sample 100", e);
+    }
+
+    #[test]
+    fn sample_percentage() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "bytes" => DataType::Int64
+        };
+
+        let e = expect![[r#"
+            Aggregate: groupBy=[[]], aggr=[[COUNT(*)]]
+              TableScan: prism.tenant.http_requests, full_filters=[sample(random(), Float64(0.01))]
+        "#]];
+        check(ctx, schema, "http_requests | sample 1% | count", e);
+    }
+
+    #[test]
+    fn sample_percentage_after_where() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "bytes" => DataType::Int64
+        };
+
+        let e = expect![[r#"
+            Aggregate: groupBy=[[]], aggr=[[COUNT(*)]]
+              Sample: sample(random(), Float64(0.01))
+                Filter: prism.tenant.http_requests.bytes > Int64(100)
+                  TableScan: prism.tenant.http_requests
+        "#]];
+        check(
+            ctx,
+            schema,
+            "http_requests | where bytes > 100 | sample 1% | count",
+            e,
+        );
+    }
+
+    #[test]
+    fn invalid_sample_percentage() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "bytes" => DataType::Int64
+        };
+
+        let e = expect![[r#"
+            [
+                Diagnostic {
+                    severity: Error,
+                    code: Some(
+                        "E0030",
+                    ),
+                    message: "`sample` percentage must be between 1 and 100, but is 150",
+                    labels: [
+                        Label {
+                            style: Primary,
+                            file_id: FileId(
+                                1,
+                            ),
+                            range: 23..26,
+                            message: "",
+                        },
+                    ],
+                    notes: [],
+                },
+            ]
+        "#]];
+        check_err(ctx, schema, "http_requests | sample 150% | count", e);
+    }
+
+    #[test]
+    fn sample_distinct() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "host" => DataType::Utf8
+        };
+
+        let e = expect![[r#"
+            Limit: skip=0, fetch=10
+              Distinct:
+                Projection: prism.tenant.http_requests.host
+                  TableScan: prism.tenant.http_requests
+        "#]];
+        check(ctx, schema, "http_requests | sample-distinct 10 of host", e);
+    }
+
+    #[test]
+    fn getschema() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "timestamp" => DataType::Timestamp(TimeUnit::Millisecond, None),
+            "host" => DataType::Utf8,
+            "bytes" => DataType::Int64
+        };
+
+        let e = expect![[r#"
+            Projection: column1 AS ColumnName, column2 AS ColumnOrdinal, column3 AS DataType, column4 AS ColumnType
+              Values: (Utf8("timestamp"), Int64(0), Utf8("Timestamp(Millisecond, None)"), Utf8("datetime")), (Utf8("host"), Int64(1), Utf8("Utf8"), Utf8("string")), (Utf8("bytes"), Int64(2), Utf8("Int64"), Utf8("long"))
+        "#]];
+        check(ctx, schema, "http_requests | getschema", e);
+    }
+
+    #[test]
+    fn evaluate_plugin() {
+        let ctx = with_bucket_plugin(TestQueryContext::new("tenant"));
+        let schema = schema! {
+            "timestamp" => DataType::Timestamp(TimeUnit::Millisecond, None),
+            "bytes" => DataType::Int64
+        };
+
+        let e = expect![[r#"
+            Aggregate: groupBy=[[bucket]], aggr=[[SUM(prism.tenant.http_requests.bytes)]]
+              Projection: prism.tenant.http_requests.timestamp, prism.tenant.http_requests.bytes, date_bin(IntervalMonthDayNano("300000000000"), prism.tenant.http_requests.timestamp, TimestampMillisecond(0, None)) AS bucket
+                TableScan: prism.tenant.http_requests
+        "#]];
+        check(
+            ctx,
+            schema,
+            "http_requests | evaluate bucket(5m) | summarize sum(bytes) by bucket",
+            e,
+        );
+    }
+
+    #[test]
+    fn evaluate_plugin_named_arguments() {
+        let ctx = with_bucket_plugin(TestQueryContext::new("tenant"));
+        let schema = schema! {
+            "timestamp" => DataType::Timestamp(TimeUnit::Millisecond, None),
+            "bytes" => DataType::Int64
+        };
+
+        let e = expect![[r#"
+            Aggregate: groupBy=[[minute]], aggr=[[COUNT(*)]]
+              Projection: prism.tenant.http_requests.timestamp, prism.tenant.http_requests.bytes, date_bin(IntervalMonthDayNano("60000000000"), prism.tenant.http_requests.timestamp, TimestampMillisecond(0, None)) AS minute
+                TableScan: prism.tenant.http_requests
+        "#]];
+        check(
+            ctx,
+            schema,
+            r#"http_requests | evaluate bucket(name = "minute", size = 1m) | count by minute"#,
+            e,
+        );
+    }
+
+    #[test]
+    fn evaluate_unknown_plugin() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "timestamp" => DataType::Timestamp(TimeUnit::Millisecond, None)
+        };
+
+        let e = expect![[r#"
+            [
+                Diagnostic {
+                    severity: Error,
+                    code: Some(
+                        "E0034",
+                    ),
+                    message: "unknown plugin `bucket`",
+                    labels: [
+                        Label {
+                            style: Primary,
+                            file_id: FileId(
+                                1,
+                            ),
+                            range: 25..31,
+                            message: "",
+                        },
+                    ],
+                    notes: [],
+                },
+            ]
+        "#]];
+        check_err(ctx, schema, "http_requests | evaluate bucket(5m)", e);
+    }
+
+    #[test]
+    fn evaluate_plugin_duplicate_argument() {
+        let ctx = with_bucket_plugin(TestQueryContext::new("tenant"));
+        let schema = schema! {
+            "timestamp" => DataType::Timestamp(TimeUnit::Millisecond, None)
+        };
+
+        let e = expect![[r#"
+            [
+                Diagnostic {
+                    severity: Error,
+                    code: Some(
+                        "E0037",
+                    ),
+                    message: "argument `size` of plugin `bucket` is given more than once",
+                    labels: [
+                        Label {
+                            style: Primary,
+                            file_id: FileId(
+                                1,
+                            ),
+                            range: 36..45,
+                            message: "",
+                        },
+                        Label {
+                            style: Secondary,
+                            file_id: FileId(
+                                1,
+                            ),
+                            range: 32..34,
+                            message: "first given here",
+                        },
+                    ],
+                    notes: [],
+                },
+            ]
+        "#]];
+        check_err(
+            ctx,
+            schema,
+            "http_requests | evaluate bucket(5m, size = 1m)",
+            e,
+        );
+    }
+
+    #[test]
+    fn evaluate_plugin_missing_argument() {
+        let ctx = with_bucket_plugin(TestQueryContext::new("tenant"));
+        let schema = schema! {
+            "timestamp" => DataType::Timestamp(TimeUnit::Millisecond, None)
+        };
+
+        let e = expect![[r#"
+            [
+                Diagnostic {
+                    severity: Error,
+                    code: Some(
+                        "E0038",
+                    ),
+                    message: "plugin `bucket` requires an argument for `size`",
+                    labels: [
+                        Label {
+                            style: Primary,
+                            file_id: FileId(
+                                1,
+                            ),
+                            range: 16..48,
+                            message: "",
+                        },
+                    ],
+                    notes: [],
+                },
+            ]
+        "#]];
+        check_err(
+            ctx,
+            schema,
+            r#"http_requests | evaluate bucket(name = "minute")"#,
+            e,
+        );
+    }
+
+    #[test]
+    fn evaluate_plugin_mistyped_argument() {
+        let ctx = with_bucket_plugin(TestQueryContext::new("tenant"));
+        let schema = schema! {
+            "timestamp" => DataType::Timestamp(TimeUnit::Millisecond, None)
+        };
+
+        let e = expect![[r#"
+            [
+                Diagnostic {
+                    severity: Error,
+                    code: Some(
+                        "E0039",
+                    ),
+                    message: "argument `size` of plugin `bucket` must be a constant of type `Interval(MonthDayNano)`",
+                    labels: [
+                        Label {
+                            style: Primary,
+                            file_id: FileId(
+                                1,
+                            ),
+                            range: 32..36,
+                            message: "",
+                        },
+                    ],
+                    notes: [],
+                },
+            ]
+        "#]];
+        check_err(ctx, schema, r#"http_requests | evaluate bucket("5m")"#, e);
+    }
+
+    #[test]
+    fn evaluate_plugin_failure() {
+        let ctx = with_bucket_plugin(TestQueryContext::new("tenant"));
+        let schema = schema! {
+            "timestamp" => DataType::Timestamp(TimeUnit::Millisecond, None)
+        };
+
+        let e = expect![[r#"
+            [
+                Diagnostic {
+                    severity: Error,
+                    code: Some(
+                        "E0040",
+                    ),
+                    message: "plugin `bucket` failed: `size` must be positive",
+                    labels: [
+                        Label {
+                            style: Primary,
+                            file_id: FileId(
+                                1,
+                            ),
+                            range: 32..41,
+                            message: "",
+                        },
+                    ],
+                    notes: [],
+                },
+            ]
+        "#]];
+        check_err(ctx, schema, "http_requests | evaluate bucket(size = 0s)", e);
+    }
+
+    #[test]
+    fn reduce() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "timestamp" => DataType::Timestamp(TimeUnit::Millisecond, None),
+            "path" => DataType::Utf8
+        };
+
+        let e = expect![[r#"
+            Sort: Count DESC NULLS LAST
+              Projection: (clusters)[pattern] AS Pattern, (clusters)[count] AS Count, (clusters)[example] AS Representative
+                Filter: clusters IS NOT NULL
+                  Unnest: clusters
+                    Aggregate: groupBy=[[]], aggr=[[patterns(prism.tenant.http_requests.path) AS clusters]]
+                      Filter: prism.tenant.http_requests.timestamp > TimestampMillisecond(1698836400000, None)
+                        TableScan: prism.tenant.http_requests
+        "#]];
+        check(
+            ctx,
+            schema,
+            "http_requests | where timestamp > ago(1h) | reduce by path",
+            e,
+        );
+    }
+
+    #[test]
+    fn reduce_non_string() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "bytes" => DataType::Int64
+        };
+
+        let e = expect![[r#"
+            [
+                Diagnostic {
+                    severity: Error,
+                    code: Some(
+                        "E0041",
+                    ),
+                    message: "`reduce` can only cluster strings, but the values have type `Int64`",
+                    labels: [
+                        Label {
+                            style: Primary,
+                            file_id: FileId(
+                                1,
+                            ),
+                            range: 26..31,
+                            message: "",
+                        },
+                    ],
+                    notes: [],
+                },
+            ]
+        "#]];
+        check_err(ctx, schema, "http_requests | reduce by bytes", e);
+    }
+
+    #[test]
+    fn render_timechart() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "timestamp" => DataType::Timestamp(TimeUnit::Millisecond, None),
+            "bytes" => DataType::Int64
+        };
+
+        let e = expect![[r#"
+            Some(
+                Visualization {
+                    kind: TimeChart,
+                    title: Some(
+                        "Traffic",
+                    ),
+                },
+            )
+        "#]];
+        check_visualization(
+            ctx,
+            schema,
+            r#"http_requests | summarize sum(bytes) by bin(timestamp, 1h) | render timechart with (title = "Traffic")"#,
+            e,
+        );
+    }
+
+    #[test]
+    fn render_barchart_without_numbers() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "timestamp" => DataType::Timestamp(TimeUnit::Millisecond, None),
+            "host" => DataType::Utf8
+        };
+
+        let e = expect![[r#"
+            [
+                Diagnostic {
+                    severity: Error,
+                    code: Some(
+                        "E0043",
+                    ),
+                    message: "`render barchart` needs a numeric column",
+                    labels: [
+                        Label {
+                            style: Primary,
+                            file_id: FileId(
+                                1,
+                            ),
+                            range: 32..47,
+                            message: "",
+                        },
+                    ],
+                    notes: [],
+                },
+            ]
+        "#]];
+        check_err(
+            ctx,
+            schema,
+            "http_requests | distinct host | render barchart",
+            e,
+        );
+    }
+
+    #[test]
+    fn render_unknown_property() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "host" => DataType::Utf8,
+            "bytes" => DataType::Int64
+        };
+
+        let e = expect![[r#"
+            [
+                Diagnostic {
+                    severity: Error,
+                    code: Some(
+                        "E0029",
+                    ),
+                    message: "unknown property `xtitle`; `render` only accepts `title`",
+                    labels: [
+                        Label {
+                            style: Primary,
+                            file_id: FileId(
+                                1,
+                            ),
+                            range: 69..75,
+                            message: "",
+                        },
+                    ],
+                    notes: [],
+                },
+            ]
+        "#]];
+        check_err(
+            ctx,
+            schema,
+            r#"http_requests | summarize sum(bytes) by host | render barchart with (xtitle = "host")"#,
+            e,
+        );
+    }
+
+    #[test]
+    fn facet() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "timestamp" => DataType::Timestamp(TimeUnit::Millisecond, None),
+            "status" => DataType::Int64,
+            "host" => DataType::Utf8,
+            "method" => DataType::Utf8
+        };
+
+        let e = expect![[r#"
+            host:
+            Limit: skip=0, fetch=10
+              Sort: count_ DESC NULLS LAST, prism.tenant.http_requests.host ASC NULLS LAST
+                Aggregate: groupBy=[[prism.tenant.http_requests.host]], aggr=[[COUNT(*) AS count_]]
+                  Filter: prism.tenant.http_requests.status >= Int64(500)
+                    TableScan: prism.tenant.http_requests
+            method:
+            Limit: skip=0, fetch=10
+              Sort: count_ DESC NULLS LAST, prism.tenant.http_requests.method ASC NULLS LAST
+                Aggregate: groupBy=[[prism.tenant.http_requests.method]], aggr=[[COUNT(*) AS count_]]
+                  Filter: prism.tenant.http_requests.status >= Int64(500)
+                    TableScan: prism.tenant.http_requests
+        "#]];
+        check_facets(
+            ctx,
+            schema,
+            "http_requests | where status >= 500 | facet by host, method",
+            e,
+        );
+    }
+
+    #[test]
+    fn facet_shares_input() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "status" => DataType::Int64,
+            "host" => DataType::Utf8
+        };
+
+        let e = expect![[r#"
+            Filter: prism.tenant.http_requests.status >= Int64(500)
+              TableScan: prism.tenant.http_requests
+        "#]];
+        check(
+            ctx,
+            schema,
+            "http_requests | where status >= 500 | facet by host | render barchart",
+            e,
+        );
+    }
+
+    #[test]
+    fn facet_twice() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "host" => DataType::Utf8
+        };
+
+        let e = expect![[r#"
+            [
+                Diagnostic {
+                    severity: Error,
+                    code: Some(
+                        "E0044",
+                    ),
+                    message: "column `host` is faceted more than once",
                     labels: [
                         Label {
                             style: Primary,
                             file_id: FileId(
                                 1,
                             ),
-                            range: 25..34,
+                            range: 31..35,
                             message: "",
                         },
                     ],
@@ -256,6 +5452,430 @@ mod tests {
                 },
             ]
         "#]];
-        check_err(ctx, schema, "http_requests | count by something", e);
+        check_err(ctx, schema, "http_requests | facet by host, host", e);
+    }
+
+    #[test]
+    fn source_map() {
+        let mut ctx = TestQueryContext::new("tenant");
+        ctx.default_row_limit = Some(100);
+        let schema = schema! {
+            "status" => DataType::Int64,
+            "host" => DataType::Utf8
+        };
+
+        let e = expect![[r#"
+            Limit: skip=0, fetch=100
+              Projection: prism.tenant.http_requests.status, prism.tenant.http_requests.host, prism.tenant.http_requests.host AS server  [3:3 extend server = host]
+                Filter: prism.tenant.http_requests.status >= Int64(500)  [2:3 where status >= 500]
+                  TableScan: prism.tenant.http_requests  [1:1 http_requests]
+        "#]];
+        check_source_map(
+            ctx,
+            schema,
+            "http_requests\n| where status >= 500\n| extend server = host",
+            e,
+        );
+    }
+
+    #[test]
+    fn execution_error_division() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "timestamp" => DataType::Timestamp(TimeUnit::Millisecond, None),
+            "bytes" => DataType::Int64,
+            "status" => DataType::Int64
+        };
+
+        let e = expect![[r#"
+            error: query failed: Arrow error: Divide by zero error
+              ┌─ query:3:18
+              │
+            3 │ | extend ratio = bytes / (status - 200), half = bytes / 2.0
+              │                  ^^^^^^^^^^^^^^^^^^^^^^ this may have divided by zero
+              │
+              = the error does not say which expression failed; this is the only candidate, as no other in the plan can fail this way
+
+        "#]];
+        check_execution_error(
+            ctx,
+            schema,
+            "http_requests\n| where timestamp > ago(1h)\n| extend ratio = bytes / (status - 200), half = bytes / 2.0",
+            DataFusionError::ArrowError(ArrowError::DivideByZero),
+            e,
+        );
+    }
+
+    #[test]
+    fn execution_error_candidates_in_failing_plan() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "bytes" => DataType::Int64,
+            "status" => DataType::Int64
+        };
+
+        let input =
+            "http_requests | extend ratio = bytes / status | where bytes / (status - 200) > 1";
+        let (mut lowerer, query) = lowerer(ctx, schema, input);
+        lowerer.lower(query, &HashMap::new()).unwrap();
+        let source_map = lowerer.source_map();
+        let error = DataFusionError::ArrowError(ArrowError::DivideByZero);
+        let spans = |plan| {
+            source_map
+                .culprits(&error, plan)
+                .iter()
+                .map(|culprit| {
+                    &input[culprit.span.start().to_usize()..culprit.span.end().to_usize()]
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // The plan as it stood after `extend` does not include the division in `where`.
+        assert_eq!(spans(&source_map.stages()[1].plan), ["bytes / status"]);
+        assert_eq!(
+            spans(&source_map.stages()[2].plan),
+            ["bytes / status", "bytes / (status - 200)"]
+        );
+    }
+
+    #[test]
+    fn execution_error_candidates() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "bytes" => DataType::Int64,
+            "status" => DataType::Int64,
+            "requests" => DataType::Int64
+        };
+
+        let e = expect![[r#"
+            error: query failed: Arrow error: Divide by zero error
+              ┌─ query:1:32
+              │
+            1 │ http_requests | extend ratio = bytes / status, average = bytes / requests, kb = bytes / 1024
+              │                                --------------            ---------------- this may have divided by zero
+              │                                │                          
+              │                                this may have divided by zero
+              │
+              = the error does not say which expression failed; any of these 2 candidates can fail this way
+
+        "#]];
+        check_execution_error(
+            ctx,
+            schema,
+            "http_requests | extend ratio = bytes / status, average = bytes / requests, kb = bytes / 1024",
+            DataFusionError::ArrowError(ArrowError::DivideByZero),
+            e,
+        );
+    }
+
+    #[test]
+    fn execution_error_cast() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "timestamp" => DataType::Timestamp(TimeUnit::Millisecond, None),
+            "status" => DataType::Utf8
+        };
+
+        let e = expect![[r#"
+            error: query failed: Arrow error: Cast error: Cannot cast string 'OK' to value of Int64 type
+              ┌─ query:1:47
+              │
+            1 │ http_requests | where timestamp > ago(1h) and status == 404
+              │                                               ^^^^^^^^^^^^^ this conversion may have failed
+              │
+              = the error does not say which expression failed; this is the only candidate, as no other in the plan can fail this way
+
+        "#]];
+        check_execution_error(
+            ctx,
+            schema,
+            "http_requests | where timestamp > ago(1h) and status == 404",
+            DataFusionError::Context(
+                "while filtering".to_string(),
+                Box::new(DataFusionError::ArrowError(ArrowError::CastError(
+                    "Cannot cast string 'OK' to value of Int64 type".to_string(),
+                ))),
+            ),
+            e,
+        );
+    }
+
+    #[test]
+    fn execution_error_unknown() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "timestamp" => DataType::Timestamp(TimeUnit::Millisecond, None),
+            "bytes" => DataType::Int64
+        };
+
+        let e = expect![[r#"
+            error: query failed: Resources exhausted: memory limit reached
+             = no expression in the query is known to fail this way
+
+        "#]];
+        check_execution_error(
+            ctx,
+            schema,
+            "http_requests | where timestamp > ago(1h) | extend kb = bytes / 1024",
+            DataFusionError::ResourcesExhausted("memory limit reached".to_string()),
+            e,
+        );
+    }
+
+    #[test]
+    fn search() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "host" => DataType::Utf8,
+            "path" => DataType::Utf8,
+            "bytes" => DataType::Int64
+        };
+
+        let e = expect![[r#"
+            Filter: strpos(lower(prism.tenant.http_requests.host), Utf8("booperbot")) > Int32(0) OR strpos(lower(prism.tenant.http_requests.path), Utf8("booperbot")) > Int32(0)
+              TableScan: prism.tenant.http_requests
+        "#]];
+        check(ctx, schema, r#"http_requests | search "BooperBot""#, e);
+    }
+
+    #[test]
+    fn search_in_columns() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "host" => DataType::Utf8,
+            "path" => DataType::Utf8
+        };
+
+        let e = expect![[r#"
+            Filter: strpos(lower(prism.tenant.http_requests.path), Utf8("booperbot")) > Int32(0)
+              TableScan: prism.tenant.http_requests
+        "#]];
+        check(
+            ctx,
+            schema,
+            r#"http_requests | search "booperbot" in (path)"#,
+            e,
+        );
+    }
+
+    #[test]
+    fn search_in_non_string_column() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "host" => DataType::Utf8,
+            "bytes" => DataType::Int64
+        };
+
+        let e = expect![[r#"
+            [
+                Diagnostic {
+                    severity: Error,
+                    code: Some(
+                        "E0032",
+                    ),
+                    message: "`search` can only look in string columns, but `bytes` has type `Int64`",
+                    labels: [
+                        Label {
+                            style: Primary,
+                            file_id: FileId(
+                                1,
+                            ),
+                            range: 45..50,
+                            message: "",
+                        },
+                    ],
+                    notes: [],
+                },
+            ]
+        "#]];
+        check_err(
+            ctx,
+            schema,
+            r#"http_requests | search "booperbot" in (host, bytes)"#,
+            e,
+        );
+    }
+
+    #[test]
+    fn case() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "status" => DataType::Int64
+        };
+
+        let e = expect![[r#"
+            Aggregate: groupBy=[[class]], aggr=[[COUNT(*)]]
+              Projection: prism.tenant.http_requests.status, CASE WHEN prism.tenant.http_requests.status < Int64(300) THEN Utf8("ok") WHEN prism.tenant.http_requests.status < Int64(500) THEN Utf8("client") ELSE Utf8("server") END AS class
+                TableScan: prism.tenant.http_requests
+        "#]];
+        check(
+            ctx,
+            schema,
+            r#"http_requests | extend class = case(status < 300, "ok", status < 500, "client", "server") | count by class"#,
+            e,
+        );
+    }
+
+    #[test]
+    fn iff_unifies_numbers() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "bytes" => DataType::Int64,
+            "ratio" => DataType::Float64
+        };
+
+        let e = expect![[r#"
+            Limit: skip=0, fetch=10
+              Projection: prism.tenant.http_requests.bytes, prism.tenant.http_requests.ratio, CASE WHEN prism.tenant.http_requests.bytes > Int64(100) THEN prism.tenant.http_requests.ratio ELSE CAST(Int64(0) AS Float64) END AS weight
+                TableScan: prism.tenant.http_requests
+        "#]];
+        check(
+            ctx,
+            schema,
+            "http_requests | extend weight = iff(bytes > 100, ratio, 0) | take 10",
+            e,
+        );
+    }
+
+    #[test]
+    fn incompatible_branches() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "status" => DataType::Int64
+        };
+
+        let e = expect![[r#"
+            [
+                Diagnostic {
+                    severity: Error,
+                    code: Some(
+                        "E0031",
+                    ),
+                    message: "`iff` has values of incompatible types `Utf8` and `Int64`",
+                    labels: [
+                        Label {
+                            style: Primary,
+                            file_id: FileId(
+                                1,
+                            ),
+                            range: 55..61,
+                            message: "this has type `Int64`",
+                        },
+                        Label {
+                            style: Secondary,
+                            file_id: FileId(
+                                1,
+                            ),
+                            range: 49..53,
+                            message: "this has type `Utf8`",
+                        },
+                    ],
+                    notes: [],
+                },
+            ]
+        "#]];
+        check_err(
+            ctx,
+            schema,
+            r#"http_requests | extend class = iff(status < 400, "ok", status) | take 10"#,
+            e,
+        );
+    }
+
+    #[test]
+    fn non_boolean_condition() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "status" => DataType::Int64
+        };
+
+        let e = expect![[r#"
+            [
+                Diagnostic {
+                    severity: Error,
+                    code: Some(
+                        "E0004",
+                    ),
+                    message: "argument to function `iff` must be a boolean condition",
+                    labels: [
+                        Label {
+                            style: Primary,
+                            file_id: FileId(
+                                1,
+                            ),
+                            range: 35..41,
+                            message: "",
+                        },
+                    ],
+                    notes: [],
+                },
+            ]
+        "#]];
+        check_err(
+            ctx,
+            schema,
+            r#"http_requests | extend class = iff(status, "ok", "error") | take 10"#,
+            e,
+        );
+    }
+
+    #[test]
+    fn case_argument_count() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "status" => DataType::Int64
+        };
+
+        let e = expect![[r#"
+            [
+                Diagnostic {
+                    severity: Error,
+                    code: Some(
+                        "E0003",
+                    ),
+                    message: "function `case` expects an odd number (at least 3) of argument(s), but 2 were given",
+                    labels: [
+                        Label {
+                            style: Primary,
+                            file_id: FileId(
+                                1,
+                            ),
+                            range: 31..55,
+                            message: "",
+                        },
+                    ],
+                    notes: [],
+                },
+            ]
+        "#]];
+        check_err(
+            ctx,
+            schema,
+            r#"http_requests | extend class = case(status < 300, "ok") | take 10"#,
+            e,
+        );
+    }
+
+    #[test]
+    fn coalesce_and_isempty() {
+        let ctx = TestQueryContext::new("tenant");
+        let schema = schema! {
+            "host" => DataType::Utf8,
+            "forwarded_host" => DataType::Utf8,
+            "bytes" => DataType::Int32
+        };
+
+        let e = expect![[r#"
+            Limit: skip=0, fetch=10
+              Projection: prism.tenant.http_requests.host, prism.tenant.http_requests.forwarded_host, prism.tenant.http_requests.bytes, coalesce(prism.tenant.http_requests.forwarded_host, prism.tenant.http_requests.host, Utf8("unknown")) AS origin, coalesce(CAST(prism.tenant.http_requests.bytes AS Int64), Int64(0)) AS size
+                Filter: NOT prism.tenant.http_requests.host IS NULL OR prism.tenant.http_requests.host = Utf8("") AND prism.tenant.http_requests.bytes IS NULL
+                  TableScan: prism.tenant.http_requests
+        "#]];
+        check(
+            ctx,
+            schema,
+            r#"http_requests | where isnotempty(host) and isnull(bytes) | extend origin = coalesce(forwarded_host, host, "unknown"), size = coalesce(bytes, 0) | take 10"#,
+            e,
+        );
     }
 }
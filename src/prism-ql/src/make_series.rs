@@ -0,0 +1,216 @@
+//! The aggregate that fills in the buckets of a `make-series` that have no rows.
+//!
+//! `make-series` first summarizes its input by bucket, which leaves out the buckets in which nothing happened. This
+//! aggregate then collects the summarized rows of each series into a list with one struct for every bucket of the
+//! range, in order, with null values where the summary has no row. Filling in the series this way reads the summary
+//! once, where joining it to a grid of every series and bucket would need the distinct series first and so summarize
+//! the input twice.
+
+use std::sync::Arc;
+
+use datafusion::{
+    arrow::{
+        array::ArrayRef,
+        compute::cast,
+        datatypes::{DataType, Field, Fields, TimeUnit},
+    },
+    common::{cast::as_int64_array, internal_err},
+    error::{DataFusionError, Result},
+    logical_expr::{
+        Accumulator, AccumulatorFactoryFunction, AggregateUDF, ReturnTypeFunction, Signature,
+        StateTypeFunction, TypeSignature, Volatility,
+    },
+    scalar::ScalarValue,
+};
+
+/// The name of the aggregate function.
+pub const MAKE_SERIES: &str = "make_series";
+
+/// The buckets of a series: `count` buckets of `step` milliseconds each, the first beginning at `from` milliseconds
+/// since the epoch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Buckets {
+    pub from: i64,
+    pub step: i64,
+    pub count: i64,
+}
+
+impl Buckets {
+    fn index(&self, bucket: i64) -> Option<usize> {
+        let offset = bucket - self.from;
+        (offset >= 0 && offset % self.step == 0 && offset / self.step < self.count)
+            .then(|| (offset / self.step) as usize)
+    }
+
+    fn start(&self, index: usize) -> ScalarValue {
+        ScalarValue::TimestampMillisecond(Some(self.from + index as i64 * self.step), None)
+    }
+}
+
+/// Returns an aggregate that takes the start of a bucket, as a datetime, followed by the values of that bucket. It
+/// returns a list with a struct for each of the given buckets, whose fields are named after `bucket` and `values`: the
+/// start of the bucket in milliseconds, then its values, or nulls if no row was in the bucket.
+pub fn make_series_udaf(buckets: Buckets, bucket: &str, values: &[String]) -> Arc<AggregateUDF> {
+    let names: Vec<String> = std::iter::once(bucket.to_string())
+        .chain(values.iter().cloned())
+        .collect();
+    let return_type: ReturnTypeFunction = Arc::new(move |args| {
+        let fields = std::iter::once(DataType::Timestamp(TimeUnit::Millisecond, None))
+            .chain(args.iter().skip(1).cloned())
+            .zip(&names)
+            .map(|(ty, name)| Field::new(name, ty, true))
+            .collect();
+        Ok(Arc::new(series_type(fields)))
+    });
+    let accumulator: AccumulatorFactoryFunction = Arc::new(move |ty| {
+        let Some(fields) = struct_fields(ty) else {
+            return internal_err!("expected a list of buckets, but got {ty}");
+        };
+
+        Ok(Box::new(SeriesAccumulator {
+            buckets,
+            fields,
+            rows: vec![None; buckets.count as usize],
+        }))
+    });
+    let state_type: StateTypeFunction = Arc::new(|ty| Ok(Arc::new(vec![ty.clone()])));
+    Arc::new(AggregateUDF::new(
+        MAKE_SERIES,
+        &Signature::new(TypeSignature::VariadicAny, Volatility::Immutable),
+        &return_type,
+        &accumulator,
+        &state_type,
+    ))
+}
+
+fn series_type(fields: Fields) -> DataType {
+    DataType::List(Arc::new(Field::new("item", DataType::Struct(fields), true)))
+}
+
+fn struct_fields(ty: &DataType) -> Option<Fields> {
+    match ty {
+        DataType::List(item) => match item.data_type() {
+            DataType::Struct(fields) => Some(fields.clone()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[derive(Debug)]
+struct SeriesAccumulator {
+    buckets: Buckets,
+    /// The fields of each bucket: its start, then its values.
+    fields: Fields,
+    /// The values of each bucket, if it has any.
+    rows: Vec<Option<Vec<ScalarValue>>>,
+}
+
+impl SeriesAccumulator {
+    fn row(&self, index: usize, values: Vec<ScalarValue>) -> ScalarValue {
+        let values = std::iter::once(self.buckets.start(index))
+            .chain(values)
+            .collect();
+        ScalarValue::Struct(Some(values), self.fields.clone())
+    }
+
+    /// The struct of every bucket that has values, in order.
+    fn present(&self) -> Vec<ScalarValue> {
+        self.rows
+            .iter()
+            .enumerate()
+            .filter_map(|(index, values)| Some(self.row(index, values.clone()?)))
+            .collect()
+    }
+}
+
+impl Accumulator for SeriesAccumulator {
+    /// Only the buckets that have values are kept in the state, so that merging never mistakes a missing bucket for a
+    /// bucket whose values are null.
+    fn state(&self) -> Result<Vec<ScalarValue>> {
+        Ok(vec![ScalarValue::new_list(
+            Some(self.present()),
+            DataType::Struct(self.fields.clone()),
+        )])
+    }
+
+    fn update_batch(&mut self, values: &[ArrayRef]) -> Result<()> {
+        let Some((starts, values)) = values.split_first() else {
+            return internal_err!("{MAKE_SERIES} expects the start of each bucket");
+        };
+
+        let starts = cast(starts, &DataType::Timestamp(TimeUnit::Millisecond, None))?;
+        let starts = cast(&starts, &DataType::Int64)?;
+        for (row, start) in as_int64_array(&starts)?.iter().enumerate() {
+            let Some(index) = start.and_then(|start| self.buckets.index(start)) else {
+                continue;
+            };
+
+            self.rows[index] = Some(
+                values
+                    .iter()
+                    .map(|value| ScalarValue::try_from_array(value, row))
+                    .collect::<Result<_>>()?,
+            );
+        }
+
+        Ok(())
+    }
+
+    fn merge_batch(&mut self, states: &[ArrayRef]) -> Result<()> {
+        for i in 0..states[0].len() {
+            let ScalarValue::List(Some(rows), _) = ScalarValue::try_from_array(&states[0], i)?
+            else {
+                continue;
+            };
+
+            for row in rows {
+                let ScalarValue::Struct(Some(mut values), _) = row else {
+                    return internal_err!("expected a bucket, but got {row:?}");
+                };
+
+                let ScalarValue::TimestampMillisecond(Some(start), _) = values.remove(0) else {
+                    return internal_err!("expected the start of a bucket, but got {values:?}");
+                };
+
+                if let Some(index) = self.buckets.index(start) {
+                    self.rows[index] = Some(values);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn evaluate(&self) -> Result<ScalarValue> {
+        let rows = self
+            .rows
+            .iter()
+            .enumerate()
+            .map(|(index, values)| {
+                let values = match values {
+                    Some(values) => values.clone(),
+                    None => self.fields[1..]
+                        .iter()
+                        .map(|field| ScalarValue::try_from(field.data_type()))
+                        .collect::<Result<_>>()?,
+                };
+                Ok(self.row(index, values))
+            })
+            .collect::<Result<_>>()?;
+        Ok(ScalarValue::new_list(
+            Some(rows),
+            DataType::Struct(self.fields.clone()),
+        ))
+    }
+
+    fn size(&self) -> usize {
+        std::mem::size_of_val(self)
+            + self
+                .rows
+                .iter()
+                .flatten()
+                .flat_map(|values| values.iter().map(ScalarValue::size))
+                .sum::<usize>()
+    }
+}
@@ -1,4 +1,5 @@
-use lalrpop_util::lalrpop_mod;
+use codespan::Span;
+use lalrpop_util::{lalrpop_mod, ParseError};
 
 use crate::ast;
 
@@ -11,6 +12,41 @@ pub fn parse(
     parser.parse(input)
 }
 
+/// Parses the body of a scalar function.
+pub fn parse_expression(
+    input: &str,
+) -> Result<ast::Expression, lalrpop_util::ParseError<usize, pql::Token<'_>, &'static str>> {
+    let parser = pql::ExpressionParser::new();
+    parser.parse(input)
+}
+
+/// Parses the body of a tabular function.
+pub fn parse_pipelines(
+    input: &str,
+) -> Result<Vec<ast::Pipeline>, lalrpop_util::ParseError<usize, pql::Token<'_>, &'static str>> {
+    let parser = pql::PipelinesParser::new();
+    parser.parse(input)
+}
+
+/// Returns the span of the input at which parsing failed, if the error is tied to one.
+pub fn error_span<T>(error: &ParseError<usize, T, &'static str>) -> Option<Span> {
+    let (start, end) = match error {
+        ParseError::InvalidToken { location } | ParseError::UnrecognizedEof { location, .. } => {
+            (*location, *location)
+        }
+        ParseError::UnrecognizedToken {
+            token: (start, _, end),
+            ..
+        }
+        | ParseError::ExtraToken {
+            token: (start, _, end),
+        } => (*start, *end),
+        ParseError::User { .. } => return None,
+    };
+
+    Some(Span::new(start as u32, end as u32))
+}
+
 #[cfg(test)]
 mod tests {
     use expect_test::{expect, Expect};
@@ -28,6 +64,9 @@ mod tests {
                     start: ByteIndex(0),
                     end: ByteIndex(21),
                 },
+                explain: false,
+                parameters: [],
+                lets: [],
                 table: Identifier {
                     span: Span {
                         start: ByteIndex(0),
@@ -46,6 +85,8 @@ mod tests {
                         },
                     ),
                 ],
+                facet: None,
+                render: None,
             }
         "#]];
         check("http_requests | count", e);
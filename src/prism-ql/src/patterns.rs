@@ -0,0 +1,489 @@
+//! The aggregate that `reduce by` lowers to.
+//!
+//! Values are clustered into patterns in the manner of Drain: each value is split into words and delimiters, and
+//! joins the most similar existing cluster with the same number of tokens, if it is similar enough. Words that differ
+//! within a cluster are replaced with a wildcard, so `GET /user/1 500` and `GET /user/2 500` become
+//! `GET /user/<*> 500`.
+//!
+//! As in Drain's prefix tree, a value is only compared with the clusters that have as many tokens and the same first
+//! token, and with those whose first token varies. A first word that contains a digit is taken to vary, so that values
+//! starting with numbers or timestamps can still cluster together.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, OnceLock},
+};
+
+use datafusion::{
+    arrow::{
+        array::ArrayRef,
+        datatypes::{DataType, Field, Fields},
+    },
+    common::{cast::as_string_array, internal_err},
+    error::{DataFusionError, Result},
+    logical_expr::{create_udaf, Accumulator, AggregateUDF, Volatility},
+    scalar::ScalarValue,
+};
+
+/// The name of the aggregate function.
+pub const PATTERNS: &str = "patterns";
+
+/// The fields of each cluster that the aggregate returns.
+pub const PATTERN_FIELD: &str = "pattern";
+pub const COUNT_FIELD: &str = "count";
+pub const EXAMPLE_FIELD: &str = "example";
+
+/// The text that stands for a word that varies within a cluster.
+const WILDCARD: &str = "<*>";
+
+/// The fraction of words that a value must share with a cluster to join it.
+const SIMILARITY_THRESHOLD: f64 = 0.5;
+
+/// The most clusters that are kept. Once there are this many, a value that is not similar enough to any of them joins
+/// the most similar one it can, or else a last cluster whose pattern is a lone wildcard.
+const MAX_CLUSTERS: usize = 1000;
+
+/// Returns the aggregate that clusters strings into patterns. It returns a list of structs with the fields
+/// [`PATTERN_FIELD`], [`COUNT_FIELD`] and [`EXAMPLE_FIELD`], one for each cluster.
+pub fn patterns_udaf() -> Arc<AggregateUDF> {
+    static UDAF: OnceLock<Arc<AggregateUDF>> = OnceLock::new();
+    UDAF.get_or_init(|| {
+        Arc::new(create_udaf(
+            PATTERNS,
+            vec![DataType::Utf8],
+            Arc::new(clusters_type()),
+            Volatility::Immutable,
+            Arc::new(|_| Ok(Box::<PatternAccumulator>::default())),
+            Arc::new(vec![clusters_type()]),
+        ))
+    })
+    .clone()
+}
+
+fn cluster_fields() -> Fields {
+    Fields::from(vec![
+        Field::new(PATTERN_FIELD, DataType::Utf8, false),
+        Field::new(COUNT_FIELD, DataType::Int64, false),
+        Field::new(EXAMPLE_FIELD, DataType::Utf8, false),
+    ])
+}
+
+fn clusters_type() -> DataType {
+    DataType::List(Arc::new(Field::new(
+        "item",
+        DataType::Struct(cluster_fields()),
+        true,
+    )))
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Token {
+    Word(String),
+    Delimiter(String),
+    Wildcard,
+}
+
+impl Token {
+    fn as_str(&self) -> &str {
+        match self {
+            Token::Word(text) | Token::Delimiter(text) => text,
+            Token::Wildcard => WILDCARD,
+        }
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '.' | '-')
+}
+
+/// Splits text into words, runs of whitespace and single punctuation characters. Text never contains a wildcard, even
+/// where it reads `<*>`.
+fn tokenize(text: &str) -> Vec<Token> {
+    let mut tokens = vec![];
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        let end = if is_word_char(c) {
+            rest.find(|c| !is_word_char(c))
+        } else if c.is_whitespace() {
+            rest.find(|c: char| !c.is_whitespace())
+        } else {
+            Some(c.len_utf8())
+        }
+        .unwrap_or(rest.len());
+        let (token, after) = rest.split_at(end);
+        tokens.push(if is_word_char(c) {
+            Token::Word(token.to_string())
+        } else {
+            Token::Delimiter(token.to_string())
+        });
+        rest = after;
+    }
+
+    tokens
+}
+
+/// Writes the tokens of a pattern so that [`tokenize_pattern`] can read them back. Text that reads `<*>` is written
+/// as `\<*>`, so that it is not mistaken for a wildcard, and backslashes are doubled.
+fn escape_pattern(tokens: &[Token]) -> String {
+    tokens
+        .split(|token| *token == Token::Wildcard)
+        .map(|run| {
+            let text: String = run.iter().map(Token::as_str).collect();
+            text.replace('\\', "\\\\")
+                .replace(WILDCARD, &format!("\\{WILDCARD}"))
+        })
+        .collect::<Vec<_>>()
+        .join(WILDCARD)
+}
+
+/// Reads back the tokens of a pattern written by [`escape_pattern`].
+fn tokenize_pattern(pattern: &str) -> Vec<Token> {
+    let mut tokens = vec![];
+    let mut text = String::new();
+    let mut rest = pattern;
+    while let Some(c) = rest.chars().next() {
+        if let Some(after) = rest.strip_prefix(WILDCARD) {
+            tokens.extend(tokenize(&std::mem::take(&mut text)));
+            tokens.push(Token::Wildcard);
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("\\<*>") {
+            text.push_str(WILDCARD);
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("\\\\") {
+            text.push('\\');
+            rest = after;
+        } else {
+            text.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+
+    tokens.extend(tokenize(&text));
+    tokens
+}
+
+/// Returns the token by which clusters starting with the given tokens are indexed: their first token, or a wildcard
+/// if it varies or is a word that contains a digit.
+fn first_token(tokens: &[Token]) -> Option<Token> {
+    match tokens.first()? {
+        Token::Word(word) if word.contains(|c: char| c.is_ascii_digit()) => Some(Token::Wildcard),
+        token => Some(token.clone()),
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Cluster {
+    tokens: Vec<Token>,
+    count: i64,
+    example: String,
+}
+
+impl Cluster {
+    /// Returns the fraction of words that the tokens share with this cluster, or `None` if they can never join it
+    /// because their delimiters differ. Positions where either side has a wildcard count as different.
+    fn similarity(&self, tokens: &[Token]) -> Option<f64> {
+        if self.tokens.len() != tokens.len() {
+            return None;
+        }
+
+        let mut words = 0;
+        let mut shared = 0;
+        for (mine, theirs) in self.tokens.iter().zip(tokens) {
+            match (mine, theirs) {
+                (Token::Delimiter(mine), Token::Delimiter(theirs)) if mine == theirs => {}
+                (Token::Delimiter(_), _) | (_, Token::Delimiter(_)) => return None,
+                (Token::Word(mine), Token::Word(theirs)) if mine == theirs => {
+                    words += 1;
+                    shared += 1;
+                }
+                _ => words += 1,
+            }
+        }
+
+        if words == 0 {
+            return Some(1.0);
+        }
+
+        Some(shared as f64 / words as f64)
+    }
+
+    fn pattern(&self) -> String {
+        self.tokens.iter().map(Token::as_str).collect()
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct PatternAccumulator {
+    clusters: Vec<Cluster>,
+    /// The clusters with each number of tokens and [`first_token`].
+    index: HashMap<(usize, Option<Token>), Vec<usize>>,
+    /// The cluster of values that fit none of the others once there are [`MAX_CLUSTERS`] of them.
+    rest: Option<usize>,
+}
+
+impl PatternAccumulator {
+    fn add(&mut self, tokens: Vec<Token>, count: i64, example: &str) {
+        let key = (tokens.len(), first_token(&tokens));
+        let varying = (tokens.len(), Some(Token::Wildcard));
+        let candidates = self
+            .index
+            .get(&key)
+            .into_iter()
+            .chain((key != varying).then(|| self.index.get(&varying)).flatten());
+
+        let full = self.clusters.len() >= MAX_CLUSTERS;
+        let mut best: Option<(usize, f64)> = None;
+        for &i in candidates.flatten() {
+            let Some(similarity) = self.clusters[i].similarity(&tokens) else {
+                continue;
+            };
+
+            if (full || similarity >= SIMILARITY_THRESHOLD)
+                && best.is_none_or(|(_, best)| similarity > best)
+            {
+                best = Some((i, similarity));
+            }
+        }
+
+        let i = match best {
+            Some((i, _)) => i,
+            None if full => *self.rest.get_or_insert_with(|| {
+                self.clusters.push(Cluster {
+                    tokens: vec![Token::Wildcard],
+                    count: 0,
+                    example: example.to_string(),
+                });
+                self.clusters.len() - 1
+            }),
+            None => {
+                self.index.entry(key).or_default().push(self.clusters.len());
+                self.clusters.push(Cluster {
+                    tokens,
+                    count,
+                    example: example.to_string(),
+                });
+                return;
+            }
+        };
+
+        let cluster = &mut self.clusters[i];
+        if Some(i) != self.rest {
+            for (mine, theirs) in cluster.tokens.iter_mut().zip(tokens) {
+                if *mine != theirs {
+                    *mine = Token::Wildcard;
+                }
+            }
+        }
+        cluster.count += count;
+    }
+
+    fn clusters(&self, pattern: impl Fn(&Cluster) -> String) -> ScalarValue {
+        let clusters = self
+            .clusters
+            .iter()
+            .map(|cluster| {
+                ScalarValue::Struct(
+                    Some(vec![
+                        ScalarValue::Utf8(Some(pattern(cluster))),
+                        ScalarValue::Int64(Some(cluster.count)),
+                        ScalarValue::from(cluster.example.as_str()),
+                    ]),
+                    cluster_fields(),
+                )
+            })
+            .collect();
+        ScalarValue::new_list(Some(clusters), DataType::Struct(cluster_fields()))
+    }
+}
+
+impl Accumulator for PatternAccumulator {
+    /// The state has the same type as the result, but its patterns are escaped, so that they can be clustered again
+    /// when partial results are merged.
+    fn state(&self) -> Result<Vec<ScalarValue>> {
+        Ok(vec![
+            self.clusters(|cluster| escape_pattern(&cluster.tokens))
+        ])
+    }
+
+    fn update_batch(&mut self, values: &[ArrayRef]) -> Result<()> {
+        for value in as_string_array(&values[0])?.iter().flatten() {
+            self.add(tokenize(value), 1, value);
+        }
+
+        Ok(())
+    }
+
+    fn merge_batch(&mut self, states: &[ArrayRef]) -> Result<()> {
+        for i in 0..states[0].len() {
+            let ScalarValue::List(Some(clusters), _) = ScalarValue::try_from_array(&states[0], i)?
+            else {
+                continue;
+            };
+
+            for cluster in clusters {
+                let ScalarValue::Struct(Some(fields), _) = &cluster else {
+                    return internal_err!("expected a pattern cluster, but got {cluster:?}");
+                };
+
+                match fields.as_slice() {
+                    [ScalarValue::Utf8(Some(pattern)), ScalarValue::Int64(Some(count)), ScalarValue::Utf8(Some(example))] => {
+                        self.add(tokenize_pattern(pattern), *count, example)
+                    }
+                    _ => return internal_err!("expected a pattern cluster, but got {cluster:?}"),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn evaluate(&self) -> Result<ScalarValue> {
+        Ok(self.clusters(Cluster::pattern))
+    }
+
+    fn size(&self) -> usize {
+        std::mem::size_of_val(self)
+            + self
+                .clusters
+                .iter()
+                .map(|cluster| {
+                    std::mem::size_of_val(cluster)
+                        + cluster.example.capacity()
+                        + cluster
+                            .tokens
+                            .iter()
+                            .map(|token| std::mem::size_of_val(token) + token.as_str().len())
+                            .sum::<usize>()
+                })
+                .sum::<usize>()
+            + self
+                .index
+                .values()
+                .map(|clusters| clusters.capacity() * std::mem::size_of::<usize>())
+                .sum::<usize>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use datafusion::{
+        arrow::array::{ArrayRef, StringArray},
+        logical_expr::Accumulator,
+        scalar::ScalarValue,
+    };
+    use expect_test::{expect, Expect};
+
+    use super::{PatternAccumulator, MAX_CLUSTERS};
+
+    fn clusters(accumulator: &PatternAccumulator) -> String {
+        let ScalarValue::List(Some(clusters), _) = accumulator.evaluate().unwrap() else {
+            panic!("expected a list of clusters");
+        };
+
+        clusters
+            .iter()
+            .map(|cluster| {
+                let ScalarValue::Struct(Some(fields), _) = cluster else {
+                    panic!("expected a cluster");
+                };
+                let fields: Vec<String> = fields.iter().map(ToString::to_string).collect();
+                format!("{}\n", fields.join(" | "))
+            })
+            .collect()
+    }
+
+    fn check(values: &[Option<&str>], expect: Expect) {
+        let mut accumulator = PatternAccumulator::default();
+        let values: ArrayRef = Arc::new(StringArray::from(values.to_vec()));
+        accumulator.update_batch(&[values]).unwrap();
+        expect.assert_eq(&clusters(&accumulator));
+    }
+
+    #[test]
+    fn clusters_similar_values() {
+        check(
+            &[
+                Some("GET /user/1 500"),
+                Some("GET /user/23 500"),
+                None,
+                Some("GET /user/23/avatar 404"),
+                Some("connection from 10.0.0.1 refused"),
+                Some("GET /user/456 500"),
+                Some("connection from 10.0.0.2 refused"),
+            ],
+            expect![[r#"
+                GET /user/<*> 500 | 3 | GET /user/1 500
+                GET /user/23/avatar 404 | 1 | GET /user/23/avatar 404
+                connection from <*> refused | 2 | connection from 10.0.0.1 refused
+            "#]],
+        );
+    }
+
+    #[test]
+    fn keeps_dissimilar_values_apart() {
+        check(
+            &[Some("GET /a"), Some("PUT /b"), Some("GET /a")],
+            expect![[r#"
+                GET /a | 2 | GET /a
+                PUT /b | 1 | PUT /b
+            "#]],
+        );
+    }
+
+    #[test]
+    fn merges_partial_results() {
+        let mut left = PatternAccumulator::default();
+        let values: ArrayRef = Arc::new(StringArray::from(vec![
+            "user 1 logged in",
+            "user 2 logged in",
+        ]));
+        left.update_batch(&[values]).unwrap();
+
+        let mut right = PatternAccumulator::default();
+        let values: ArrayRef = Arc::new(StringArray::from(vec![
+            "user 3 logged in",
+            "user 3 logged out",
+        ]));
+        right.update_batch(&[values]).unwrap();
+
+        let state = ScalarValue::iter_to_array(right.state().unwrap()).unwrap();
+        left.merge_batch(&[state]).unwrap();
+        let e = expect![[r#"
+            user <*> logged <*> | 4 | user 1 logged in
+        "#]];
+        e.assert_eq(&clusters(&left));
+    }
+
+    #[test]
+    fn keeps_text_that_reads_like_a_wildcard() {
+        let mut left = PatternAccumulator::default();
+        let values: ArrayRef = Arc::new(StringArray::from(vec!["retry <*> 1", "retry y 1"]));
+        left.update_batch(&[values]).unwrap();
+
+        let mut right = PatternAccumulator::default();
+        let values: ArrayRef = Arc::new(StringArray::from(vec!["retry <*> 1"]));
+        right.update_batch(&[values]).unwrap();
+
+        let state = ScalarValue::iter_to_array(right.state().unwrap()).unwrap();
+        left.merge_batch(&[state]).unwrap();
+        let e = expect![[r#"
+            retry <*> 1 | 2 | retry <*> 1
+            retry y 1 | 1 | retry y 1
+        "#]];
+        e.assert_eq(&clusters(&left));
+    }
+
+    #[test]
+    fn limits_the_number_of_clusters() {
+        let values: Vec<String> = (0..MAX_CLUSTERS + 2).map(|i| "a ".repeat(i + 1)).collect();
+        let mut accumulator = PatternAccumulator::default();
+        let values: ArrayRef = Arc::new(StringArray::from(values));
+        accumulator.update_batch(&[values]).unwrap();
+
+        let clusters = clusters(&accumulator);
+        assert_eq!(clusters.lines().count(), MAX_CLUSTERS + 1);
+        assert!(clusters.ends_with(&format!("<*> | 2 | {}\n", "a ".repeat(MAX_CLUSTERS + 1))));
+    }
+}
@@ -0,0 +1,94 @@
+use std::collections::HashMap;
+
+use datafusion::{
+    arrow::datatypes::DataType, error::DataFusionError, logical_expr::LogicalPlanBuilder,
+    scalar::ScalarValue,
+};
+
+/// A custom pipeline operator that an embedder provides through [`QueryContext::get_plugin`], invoked in PQL as
+/// `| evaluate name(arg, name = arg, ...)`.
+///
+/// [`QueryContext::get_plugin`]: crate::QueryContext::get_plugin
+pub trait EvaluatePlugin: Send + Sync {
+    /// The parameters that the operator accepts, in the order that positional arguments are bound to them.
+    fn parameters(&self) -> Vec<PluginParameter>;
+
+    /// Extends the plan built from the preceding pipeline. Every argument has been checked against
+    /// [`EvaluatePlugin::parameters`] before this is called.
+    fn evaluate(
+        &self,
+        builder: LogicalPlanBuilder,
+        args: &PluginArguments,
+    ) -> Result<LogicalPlanBuilder, PluginError>;
+}
+
+#[derive(Clone, Debug)]
+pub struct PluginParameter {
+    pub name: String,
+    pub ty: DataType,
+    /// The value used when no argument is given; the parameter is required if there is none.
+    pub default: Option<ScalarValue>,
+}
+
+impl PluginParameter {
+    pub fn required(name: impl Into<String>, ty: DataType) -> PluginParameter {
+        PluginParameter {
+            name: name.into(),
+            ty,
+            default: None,
+        }
+    }
+
+    pub fn optional(name: impl Into<String>, default: ScalarValue) -> PluginParameter {
+        PluginParameter {
+            name: name.into(),
+            ty: default.data_type(),
+            default: Some(default),
+        }
+    }
+}
+
+/// The arguments of an `evaluate` call, by parameter name. Each value has the type of its parameter.
+#[derive(Clone, Debug, Default)]
+pub struct PluginArguments {
+    values: HashMap<String, ScalarValue>,
+}
+
+impl PluginArguments {
+    pub(crate) fn new(values: HashMap<String, ScalarValue>) -> PluginArguments {
+        PluginArguments { values }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&ScalarValue> {
+        self.values.get(name)
+    }
+}
+
+/// A reason that a plugin could not evaluate its arguments, reported to the user as a diagnostic.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PluginError {
+    pub message: String,
+    /// The parameter whose argument is at fault, if any. The diagnostic points at that argument rather than at the
+    /// whole call.
+    pub argument: Option<String>,
+}
+
+impl PluginError {
+    pub fn new(message: impl Into<String>) -> PluginError {
+        PluginError {
+            message: message.into(),
+            argument: None,
+        }
+    }
+
+    pub fn with_argument(mut self, argument: impl Into<String>) -> PluginError {
+        self.argument = Some(argument.into());
+        self
+    }
+}
+
+impl From<DataFusionError> for PluginError {
+    fn from(error: DataFusionError) -> PluginError {
+        PluginError::new(error.to_string())
+    }
+}
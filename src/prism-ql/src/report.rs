@@ -0,0 +1,448 @@
+use codespan::{FileId, Files, Span};
+use codespan_reporting::{
+    diagnostic::{Diagnostic, Label, LabelStyle, Severity},
+    term::{self, termcolor::WriteColor},
+};
+use datafusion::{error::DataFusionError, logical_expr::LogicalPlan};
+use lsp_types::{
+    DiagnosticRelatedInformation, DiagnosticSeverity, Location, NumberOrString, Position, Range,
+    Url,
+};
+use serde::Serialize;
+
+use crate::source_map::{FallibleExpr, SourceMap};
+
+/// The source name reported in LSP diagnostics.
+const LSP_SOURCE: &str = "pql";
+
+/// Diagnostics reported while lowering a query, together with the sources they refer to. The query itself is the
+/// first file; the bodies of any stored functions it calls follow.
+#[derive(Clone, Debug)]
+pub struct Report {
+    files: Files<String>,
+    query: FileId,
+    diagnostics: Vec<Diagnostic<FileId>>,
+}
+
+/// A diagnostic in the JSON format returned to HTTP clients.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct JsonDiagnostic {
+    pub code: Option<String>,
+    pub severity: &'static str,
+    pub message: String,
+    pub labels: Vec<JsonLabel>,
+    pub notes: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct JsonLabel {
+    pub primary: bool,
+    pub message: String,
+    /// The name of the file the label points into: `query`, or the name of a stored function.
+    pub file: String,
+    /// Byte offsets into the file.
+    pub start: usize,
+    pub end: usize,
+    pub start_position: JsonPosition,
+    pub end_position: JsonPosition,
+}
+
+/// A 1-based line and column, counting columns in characters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct JsonPosition {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Report {
+    pub(crate) fn new(
+        files: Files<String>,
+        query: FileId,
+        diagnostics: Vec<Diagnostic<FileId>>,
+    ) -> Report {
+        Report {
+            files,
+            query,
+            diagnostics,
+        }
+    }
+
+    pub fn diagnostics(&self) -> &[Diagnostic<FileId>] {
+        &self.diagnostics
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.diagnostics.is_empty()
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics
+            .iter()
+            .any(|diagnostic| diagnostic.severity >= Severity::Error)
+    }
+
+    /// Returns a report of an error raised while executing a plan of this query, pointing at the expressions in the
+    /// query that may have caused it. The error does not say which expression failed, so these are labelled as
+    /// candidates, found by [`SourceMap::culprits`].
+    pub fn execution_error(
+        &self,
+        source_map: &SourceMap,
+        plan: &LogicalPlan,
+        error: &DataFusionError,
+    ) -> Report {
+        let culprits = source_map.culprits(error, plan);
+        let label = |culprit: &&FallibleExpr| match culprits.len() {
+            1 => Label::primary(culprit.file, culprit.span),
+            _ => Label::secondary(culprit.file, culprit.span),
+        };
+        let mut diagnostic = Diagnostic::error()
+            .with_message(format!("query failed: {}", error.find_root()))
+            .with_labels(
+                culprits
+                    .iter()
+                    .map(|culprit| label(culprit).with_message(culprit.kind.description()))
+                    .collect(),
+            );
+        match culprits.len() {
+            0 => {
+                diagnostic = diagnostic.with_notes(vec![
+                    "no expression in the query is known to fail this way".to_string(),
+                ]);
+            }
+            1 => {
+                diagnostic = diagnostic.with_notes(vec![
+                    "the error does not say which expression failed; this is the only candidate, as no other in the plan can fail this way".to_string(),
+                ]);
+            }
+            count => {
+                diagnostic = diagnostic.with_notes(vec![format!(
+                    "the error does not say which expression failed; any of these {count} candidates can fail this way"
+                )]);
+            }
+        }
+
+        Report::new(self.files.clone(), self.query, vec![diagnostic])
+    }
+
+    /// Renders every diagnostic for a terminal, using colors if the writer supports them.
+    pub fn emit(
+        &self,
+        writer: &mut dyn WriteColor,
+        config: &term::Config,
+    ) -> Result<(), codespan_reporting::files::Error> {
+        for diagnostic in &self.diagnostics {
+            term::emit(writer, config, &self.files, diagnostic)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn to_json(&self) -> Vec<JsonDiagnostic> {
+        self.diagnostics
+            .iter()
+            .map(|diagnostic| JsonDiagnostic {
+                code: diagnostic.code.clone(),
+                severity: severity_name(diagnostic.severity),
+                message: diagnostic.message.clone(),
+                labels: diagnostic
+                    .labels
+                    .iter()
+                    .map(|label| JsonLabel {
+                        primary: label.style == LabelStyle::Primary,
+                        message: label.message.clone(),
+                        file: self
+                            .files
+                            .name(label.file_id)
+                            .to_string_lossy()
+                            .into_owned(),
+                        start: label.range.start,
+                        end: label.range.end,
+                        start_position: self.json_position(label.file_id, label.range.start),
+                        end_position: self.json_position(label.file_id, label.range.end),
+                    })
+                    .collect(),
+                notes: diagnostic.notes.clone(),
+            })
+            .collect()
+    }
+
+    /// Converts the diagnostics into LSP diagnostics for the query document at `uri`. Labels in the bodies of stored
+    /// functions have no document to point into, so each diagnostic is placed at its first label in the query.
+    pub fn to_lsp(&self, uri: &Url) -> Vec<lsp_types::Diagnostic> {
+        self.diagnostics
+            .iter()
+            .map(|diagnostic| {
+                let mut labels = diagnostic
+                    .labels
+                    .iter()
+                    .filter(|label| label.file_id == self.query);
+                let range = labels
+                    .next()
+                    .map(|label| self.lsp_range(&label.range))
+                    .unwrap_or_default();
+                let related_information: Vec<_> = labels
+                    .filter(|label| !label.message.is_empty())
+                    .map(|label| DiagnosticRelatedInformation {
+                        location: Location::new(uri.clone(), self.lsp_range(&label.range)),
+                        message: label.message.clone(),
+                    })
+                    .collect();
+
+                let mut message = diagnostic.message.clone();
+                for note in &diagnostic.notes {
+                    message.push('\n');
+                    message.push_str(note);
+                }
+
+                lsp_types::Diagnostic::new(
+                    range,
+                    Some(lsp_severity(diagnostic.severity)),
+                    diagnostic.code.clone().map(NumberOrString::String),
+                    Some(LSP_SOURCE.to_string()),
+                    message,
+                    (!related_information.is_empty()).then_some(related_information),
+                    None,
+                )
+            })
+            .collect()
+    }
+
+    fn json_position(&self, file_id: FileId, byte_index: usize) -> JsonPosition {
+        let location = self
+            .files
+            .location(file_id, byte_index as u32)
+            .expect("diagnostic label is outside of its file");
+        JsonPosition {
+            line: location.line.to_usize() + 1,
+            column: location.column.to_usize() + 1,
+        }
+    }
+
+    fn lsp_range(&self, range: &std::ops::Range<usize>) -> Range {
+        Range::new(self.lsp_position(range.start), self.lsp_position(range.end))
+    }
+
+    /// LSP positions are 0-based and count columns in UTF-16 code units.
+    fn lsp_position(&self, byte_index: usize) -> Position {
+        let line = self.files.line_index(self.query, byte_index as u32);
+        let line_span = self
+            .files
+            .line_span(self.query, line)
+            .expect("line index is within the query");
+        let prefix = self
+            .files
+            .source_slice(self.query, Span::new(line_span.start(), byte_index as u32))
+            .expect("diagnostic label is outside of the query");
+        Position::new(line.0, prefix.encode_utf16().count() as u32)
+    }
+}
+
+fn severity_name(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Bug => "bug",
+        Severity::Error => "error",
+        Severity::Warning => "warning",
+        Severity::Note => "note",
+        Severity::Help => "help",
+    }
+}
+
+fn lsp_severity(severity: Severity) -> DiagnosticSeverity {
+    match severity {
+        Severity::Bug | Severity::Error => DiagnosticSeverity::ERROR,
+        Severity::Warning => DiagnosticSeverity::WARNING,
+        Severity::Note => DiagnosticSeverity::INFORMATION,
+        Severity::Help => DiagnosticSeverity::HINT,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use codespan::Files;
+    use codespan_reporting::{
+        diagnostic::{Diagnostic, Label},
+        term::{self, termcolor::NoColor},
+    };
+    use expect_test::expect;
+    use lsp_types::Url;
+
+    use super::Report;
+
+    fn report() -> Report {
+        let mut files: Files<String> = Files::new();
+        let query = files.add("query", "http_requests\n| where größe == 404".to_string());
+        let diagnostics = vec![
+            Diagnostic::warning()
+                .with_code("W0003")
+                .with_message("comparison of string column `größe` to integer literal")
+                .with_labels(vec![Label::primary(query, 22..36)]),
+            Diagnostic::error()
+                .with_code("W0002")
+                .with_message("query has no filter on `timestamp`")
+                .with_labels(vec![
+                    Label::primary(query, 0..13),
+                    Label::secondary(query, 14..36).with_message("this filter"),
+                ])
+                .with_notes(vec!["`W0002` is denied for this tenant".to_string()]),
+        ];
+        Report::new(files, query, diagnostics)
+    }
+
+    #[test]
+    fn emit() {
+        let report = report();
+        let mut writer = NoColor::new(Vec::new());
+        report.emit(&mut writer, &term::Config::default()).unwrap();
+        let e = expect![[r#"
+            warning[W0003]: comparison of string column `größe` to integer literal
+              ┌─ query:2:9
+              │
+            2 │ | where größe == 404
+              │         ^^^^^^^^^^^^
+
+            error[W0002]: query has no filter on `timestamp`
+              ┌─ query:1:1
+              │
+            1 │ http_requests
+              │ ^^^^^^^^^^^^^
+            2 │ | where größe == 404
+              │ -------------------- this filter
+              │
+              = `W0002` is denied for this tenant
+
+        "#]];
+        e.assert_eq(&String::from_utf8(writer.into_inner()).unwrap());
+    }
+
+    #[test]
+    fn json() {
+        let report = report();
+        let e = expect![[r#"
+            [
+              {
+                "code": "W0003",
+                "severity": "warning",
+                "message": "comparison of string column `größe` to integer literal",
+                "labels": [
+                  {
+                    "primary": true,
+                    "message": "",
+                    "file": "query",
+                    "start": 22,
+                    "end": 36,
+                    "start_position": {
+                      "line": 2,
+                      "column": 9
+                    },
+                    "end_position": {
+                      "line": 2,
+                      "column": 21
+                    }
+                  }
+                ],
+                "notes": []
+              },
+              {
+                "code": "W0002",
+                "severity": "error",
+                "message": "query has no filter on `timestamp`",
+                "labels": [
+                  {
+                    "primary": true,
+                    "message": "",
+                    "file": "query",
+                    "start": 0,
+                    "end": 13,
+                    "start_position": {
+                      "line": 1,
+                      "column": 1
+                    },
+                    "end_position": {
+                      "line": 1,
+                      "column": 14
+                    }
+                  },
+                  {
+                    "primary": false,
+                    "message": "this filter",
+                    "file": "query",
+                    "start": 14,
+                    "end": 36,
+                    "start_position": {
+                      "line": 2,
+                      "column": 1
+                    },
+                    "end_position": {
+                      "line": 2,
+                      "column": 21
+                    }
+                  }
+                ],
+                "notes": [
+                  "`W0002` is denied for this tenant"
+                ]
+              }
+            ]"#]];
+        e.assert_eq(&serde_json::to_string_pretty(&report.to_json()).unwrap());
+    }
+
+    #[test]
+    fn lsp() {
+        let report = report();
+        let uri = Url::parse("file:///queries/requests.pql").unwrap();
+        let e = expect![[r#"
+            [
+              {
+                "range": {
+                  "start": {
+                    "line": 1,
+                    "character": 8
+                  },
+                  "end": {
+                    "line": 1,
+                    "character": 20
+                  }
+                },
+                "severity": 2,
+                "code": "W0003",
+                "source": "pql",
+                "message": "comparison of string column `größe` to integer literal"
+              },
+              {
+                "range": {
+                  "start": {
+                    "line": 0,
+                    "character": 0
+                  },
+                  "end": {
+                    "line": 0,
+                    "character": 13
+                  }
+                },
+                "severity": 1,
+                "code": "W0002",
+                "source": "pql",
+                "message": "query has no filter on `timestamp`\n`W0002` is denied for this tenant",
+                "relatedInformation": [
+                  {
+                    "location": {
+                      "uri": "file:///queries/requests.pql",
+                      "range": {
+                        "start": {
+                          "line": 1,
+                          "character": 0
+                        },
+                        "end": {
+                          "line": 1,
+                          "character": 20
+                        }
+                      }
+                    },
+                    "message": "this filter"
+                  }
+                ]
+              }
+            ]"#]];
+        e.assert_eq(&serde_json::to_string_pretty(&report.to_lsp(&uri)).unwrap());
+    }
+}
@@ -0,0 +1,206 @@
+//! The filter that `sample P%` lowers to.
+//!
+//! A sample of a table is a filter on its scan. A table source that reports [`TableProviderFilterPushDown::Exact`]
+//! support for this filter may sample at a coarser grain than rows, such as whole files, instead of reading everything
+//! and discarding rows. The filter is a function of its own rather than `random() < P`, so that sources sample only the
+//! scans that `sample` applies to, and not those that a SQL query happens to filter at random.
+//!
+//! A sample of anything else is a [`SampleNode`]. DataFusion would push a filter down through operators such as
+//! aggregates and joins, which would sample their input rather than their results, so the node keeps the sample where
+//! it is in the plan. Plans that contain it must be run with the [`query_planner`].
+//!
+//! [`TableProviderFilterPushDown::Exact`]: datafusion::logical_expr::TableProviderFilterPushDown::Exact
+
+use std::{
+    fmt,
+    sync::{Arc, OnceLock},
+};
+
+use async_trait::async_trait;
+use datafusion::{
+    arrow::{array::BooleanArray, datatypes::DataType},
+    common::{cast::as_float64_array, exec_err, DFSchemaRef},
+    error::{DataFusionError, Result},
+    execution::context::{QueryPlanner, SessionState},
+    logical_expr::{
+        expr::ScalarUDF as ScalarUDFExpr, expr_fn, lit, ColumnarValue, Expr, LogicalPlan,
+        ReturnTypeFunction, ScalarFunctionImplementation, ScalarUDF, Signature, TypeSignature,
+        UserDefinedLogicalNode, UserDefinedLogicalNodeCore, Volatility,
+    },
+    physical_plan::{filter::FilterExec, ExecutionPlan},
+    physical_planner::{DefaultPhysicalPlanner, ExtensionPlanner, PhysicalPlanner},
+    scalar::ScalarValue,
+};
+
+/// The name of the function that a sample filters by.
+pub const SAMPLE: &str = "sample";
+
+/// Returns the function that a sample filters by. It takes a random number between 0 and 1 for each row, and the
+/// fraction of rows to keep, and keeps the rows whose number is below the fraction.
+pub fn sample_udf() -> Arc<ScalarUDF> {
+    static UDF: OnceLock<Arc<ScalarUDF>> = OnceLock::new();
+    UDF.get_or_init(|| {
+        let return_type: ReturnTypeFunction = Arc::new(|_| Ok(Arc::new(DataType::Boolean)));
+        let fun: ScalarFunctionImplementation = Arc::new(sample);
+        Arc::new(ScalarUDF::new(
+            SAMPLE,
+            &Signature::new(
+                TypeSignature::Exact(vec![DataType::Float64, DataType::Float64]),
+                Volatility::Volatile,
+            ),
+            &return_type,
+            &fun,
+        ))
+    })
+    .clone()
+}
+
+fn sample(args: &[ColumnarValue]) -> Result<ColumnarValue> {
+    let [ColumnarValue::Array(random), ColumnarValue::Scalar(ScalarValue::Float64(Some(fraction)))] =
+        args
+    else {
+        return exec_err!("{SAMPLE} expects a random number for each row and a constant fraction");
+    };
+
+    let keep: BooleanArray = as_float64_array(random)?
+        .iter()
+        .map(|random| random.map(|random| random < *fraction))
+        .collect();
+    Ok(ColumnarValue::Array(Arc::new(keep)))
+}
+
+/// Returns a filter that keeps each row with the given probability.
+pub fn sample_filter(fraction: f64) -> Expr {
+    sample_udf().call(vec![expr_fn::random(), lit(fraction)])
+}
+
+/// Returns the probability with which a filter produced by [`sample_filter`] keeps each row, or `None` if the filter
+/// is not a sample.
+pub fn sample_fraction(filter: &Expr) -> Option<f64> {
+    let Expr::ScalarUDF(ScalarUDFExpr { fun, args }) = filter else {
+        return None;
+    };
+
+    match args.as_slice() {
+        [_, Expr::Literal(ScalarValue::Float64(Some(fraction)))] if fun.name == SAMPLE => {
+            Some(*fraction)
+        }
+        _ => None,
+    }
+}
+
+/// Keeps each row of its input with a given probability, wherever it is in the plan.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SampleNode {
+    input: LogicalPlan,
+    /// A filter produced by [`sample_filter`].
+    filter: Expr,
+}
+
+impl SampleNode {
+    pub fn new(input: LogicalPlan, fraction: f64) -> SampleNode {
+        SampleNode {
+            input,
+            filter: sample_filter(fraction),
+        }
+    }
+
+    /// The filter that the sample keeps rows by.
+    pub fn filter(&self) -> &Expr {
+        &self.filter
+    }
+}
+
+impl UserDefinedLogicalNodeCore for SampleNode {
+    fn name(&self) -> &str {
+        "Sample"
+    }
+
+    fn inputs(&self) -> Vec<&LogicalPlan> {
+        vec![&self.input]
+    }
+
+    fn schema(&self) -> &DFSchemaRef {
+        self.input.schema()
+    }
+
+    fn expressions(&self) -> Vec<Expr> {
+        vec![self.filter.clone()]
+    }
+
+    fn fmt_for_explain(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Sample: {}", self.filter)
+    }
+
+    fn from_template(&self, exprs: &[Expr], inputs: &[LogicalPlan]) -> Self {
+        SampleNode {
+            input: inputs[0].clone(),
+            filter: exprs[0].clone(),
+        }
+    }
+}
+
+/// Plans a [`SampleNode`] as a filter on its input.
+struct SamplePlanner;
+
+#[async_trait]
+impl ExtensionPlanner for SamplePlanner {
+    async fn plan_extension(
+        &self,
+        planner: &dyn PhysicalPlanner,
+        node: &dyn UserDefinedLogicalNode,
+        logical_inputs: &[&LogicalPlan],
+        physical_inputs: &[Arc<dyn ExecutionPlan>],
+        session_state: &SessionState,
+    ) -> Result<Option<Arc<dyn ExecutionPlan>>> {
+        let Some(sample) = node.as_any().downcast_ref::<SampleNode>() else {
+            return Ok(None);
+        };
+
+        let input = physical_inputs[0].clone();
+        let filter = planner.create_physical_expr(
+            &sample.filter,
+            logical_inputs[0].schema(),
+            &input.schema(),
+            session_state,
+        )?;
+        Ok(Some(Arc::new(FilterExec::try_new(filter, input)?)))
+    }
+}
+
+/// Plans queries with the nodes that PQL adds to DataFusion's, such as [`SampleNode`].
+struct PqlQueryPlanner;
+
+#[async_trait]
+impl QueryPlanner for PqlQueryPlanner {
+    async fn create_physical_plan(
+        &self,
+        logical_plan: &LogicalPlan,
+        session_state: &SessionState,
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        DefaultPhysicalPlanner::with_extension_planners(vec![Arc::new(SamplePlanner)])
+            .create_physical_plan(logical_plan, session_state)
+            .await
+    }
+}
+
+/// Returns the planner that sessions running PQL queries must use.
+pub fn query_planner() -> Arc<dyn QueryPlanner + Send + Sync> {
+    Arc::new(PqlQueryPlanner)
+}
+
+#[cfg(test)]
+mod tests {
+    use datafusion::logical_expr::expr_fn;
+
+    use super::{sample_filter, sample_fraction};
+
+    #[test]
+    fn fraction_of_sample() {
+        assert_eq!(sample_fraction(&sample_filter(0.25)), Some(0.25));
+        assert_eq!(
+            sample_fraction(&expr_fn::random().lt(datafusion::logical_expr::lit(0.25))),
+            None
+        );
+    }
+}
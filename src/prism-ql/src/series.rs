@@ -0,0 +1,399 @@
+//! Time-series functions over ordered rows, such as the buckets produced by `make-series`.
+//!
+//! Each function is a window function that sees a whole series at once: every row of its partition, in order. In PQL
+//! they are called from `extend` after `make-series` or `sort`, and each series of a `make-series ... by` is analysed
+//! separately. In SQL they are called like any other window function, for example
+//! `series_moving_avg(requests, 5) OVER (PARTITION BY host ORDER BY timestamp)`.
+
+use std::sync::{Arc, OnceLock};
+
+use datafusion::{
+    arrow::{
+        array::{Array, ArrayRef, Float64Array, Int64Array},
+        compute,
+        datatypes::DataType,
+    },
+    common::{cast::as_float64_array, exec_err},
+    error::{DataFusionError, Result},
+    logical_expr::{
+        PartitionEvaluator, ReturnTypeFunction, Signature, TypeSignature, Volatility, WindowUDF,
+    },
+};
+
+/// Scales the median absolute deviation so that it estimates the standard deviation of normally distributed values.
+const MAD_SCALE: f64 = 0.6745;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SeriesFunction {
+    /// The mean of the last `n` values, including the current one.
+    MovingAvg,
+    /// Exponentially weighted moving average with smoothing factor `alpha`.
+    ExpSmooth,
+    /// The centered moving average over one seasonal period.
+    Trend,
+    /// The average deviation from the trend at the same point of each seasonal period.
+    Seasonal,
+    /// What remains after removing the trend and seasonal components.
+    Residual,
+    /// `1` or `-1` for values more than `threshold` standard deviations above or below the mean, and `0` otherwise.
+    ZscoreAnomalies,
+    /// Like [`SeriesFunction::ZscoreAnomalies`], but measured from the median in median absolute deviations, which
+    /// is not skewed by the anomalies themselves.
+    MadAnomalies,
+}
+
+const FUNCTIONS: [SeriesFunction; 7] = [
+    SeriesFunction::MovingAvg,
+    SeriesFunction::ExpSmooth,
+    SeriesFunction::Trend,
+    SeriesFunction::Seasonal,
+    SeriesFunction::Residual,
+    SeriesFunction::ZscoreAnomalies,
+    SeriesFunction::MadAnomalies,
+];
+
+/// The meaning of a series function's second argument.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Parameter {
+    Window,
+    Alpha,
+    Period,
+    Threshold,
+}
+
+impl SeriesFunction {
+    pub fn from_name(name: &str) -> Option<SeriesFunction> {
+        FUNCTIONS
+            .into_iter()
+            .find(|function| function.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SeriesFunction::MovingAvg => "series_moving_avg",
+            SeriesFunction::ExpSmooth => "series_exp_smooth",
+            SeriesFunction::Trend => "series_trend",
+            SeriesFunction::Seasonal => "series_seasonal",
+            SeriesFunction::Residual => "series_residual",
+            SeriesFunction::ZscoreAnomalies => "series_zscore_anomalies",
+            SeriesFunction::MadAnomalies => "series_mad_anomalies",
+        }
+    }
+
+    fn parameter(self) -> Parameter {
+        match self {
+            SeriesFunction::MovingAvg => Parameter::Window,
+            SeriesFunction::ExpSmooth => Parameter::Alpha,
+            SeriesFunction::Trend | SeriesFunction::Seasonal | SeriesFunction::Residual => {
+                Parameter::Period
+            }
+            SeriesFunction::ZscoreAnomalies | SeriesFunction::MadAnomalies => Parameter::Threshold,
+        }
+    }
+
+    /// Whether the second argument is acceptable.
+    pub fn accepts(self, parameter: f64) -> bool {
+        match self.parameter() {
+            Parameter::Window | Parameter::Period => parameter >= 1.0 && parameter.fract() == 0.0,
+            Parameter::Alpha => parameter > 0.0 && parameter <= 1.0,
+            Parameter::Threshold => parameter > 0.0,
+        }
+    }
+
+    /// Describes the values that [`SeriesFunction::accepts`] as its second argument.
+    pub fn expected(self) -> &'static str {
+        match self.parameter() {
+            Parameter::Window => "a positive integer window size",
+            Parameter::Alpha => "a smoothing factor greater than 0 and at most 1",
+            Parameter::Period => "a positive integer period",
+            Parameter::Threshold => "a positive threshold",
+        }
+    }
+
+    pub fn return_type(self) -> DataType {
+        match self {
+            SeriesFunction::ZscoreAnomalies | SeriesFunction::MadAnomalies => DataType::Int64,
+            _ => DataType::Float64,
+        }
+    }
+
+    pub fn udwf(self) -> Arc<WindowUDF> {
+        static UDWFS: OnceLock<Vec<Arc<WindowUDF>>> = OnceLock::new();
+        let udwfs = UDWFS.get_or_init(|| {
+            FUNCTIONS
+                .into_iter()
+                .map(|function| {
+                    let return_type = Arc::new(function.return_type());
+                    let return_type: ReturnTypeFunction =
+                        Arc::new(move |_| Ok(return_type.clone()));
+                    Arc::new(WindowUDF::new(
+                        function.name(),
+                        &Signature::new(TypeSignature::Any(2), Volatility::Immutable),
+                        &return_type,
+                        &(Arc::new(move || {
+                            Ok(Box::new(SeriesEvaluator { function })
+                                as Box<dyn PartitionEvaluator>)
+                        }) as _),
+                    ))
+                })
+                .collect()
+        });
+        let index = FUNCTIONS
+            .iter()
+            .position(|function| *function == self)
+            .expect("every function is listed");
+        udwfs[index].clone()
+    }
+
+    /// Computes the function over a whole series. Missing values are left out of averages and statistics, and have no
+    /// seasonal component, residual or anomaly flag of their own.
+    fn evaluate(self, values: &[Option<f64>], parameter: f64) -> ArrayRef {
+        match self {
+            SeriesFunction::MovingAvg => Arc::new(moving_avg(values, parameter as usize)),
+            SeriesFunction::ExpSmooth => {
+                let mut smoothed = None;
+                Arc::new(Float64Array::from_iter(values.iter().map(|value| {
+                    smoothed = match (smoothed, value) {
+                        (None, value) => *value,
+                        (Some(smoothed), None) => Some(smoothed),
+                        (Some(smoothed), Some(value)) => {
+                            Some(parameter * value + (1.0 - parameter) * smoothed)
+                        }
+                    };
+                    smoothed
+                })))
+            }
+            SeriesFunction::Trend => Arc::new(trend(values, parameter as usize)),
+            SeriesFunction::Seasonal => {
+                let trend = trend(values, parameter as usize);
+                Arc::new(seasonal(values, &trend, parameter as usize))
+            }
+            SeriesFunction::Residual => {
+                let trend = trend(values, parameter as usize);
+                let seasonal = seasonal(values, &trend, parameter as usize);
+                Arc::new(Float64Array::from_iter(
+                    values.iter().zip(trend.iter()).zip(seasonal.iter()).map(
+                        |((value, trend), seasonal)| Some(value.as_ref()? - trend? - seasonal?),
+                    ),
+                ))
+            }
+            SeriesFunction::ZscoreAnomalies => {
+                let present: Vec<f64> = values.iter().flatten().copied().collect();
+                let mean = present.iter().sum::<f64>() / present.len() as f64;
+                let variance = present
+                    .iter()
+                    .map(|value| (value - mean).powi(2))
+                    .sum::<f64>()
+                    / present.len() as f64;
+                Arc::new(anomalies(values, mean, variance.sqrt(), parameter))
+            }
+            SeriesFunction::MadAnomalies => {
+                let present: Vec<f64> = values.iter().flatten().copied().collect();
+                let center = median(present.clone());
+                let deviations = present.iter().map(|value| (value - center).abs()).collect();
+                let scale = median(deviations) / MAD_SCALE;
+                Arc::new(anomalies(values, center, scale, parameter))
+            }
+        }
+    }
+}
+
+/// Returns every series function as a window function, to register with a `SessionContext`.
+pub fn series_udwfs() -> Vec<Arc<WindowUDF>> {
+    FUNCTIONS.into_iter().map(SeriesFunction::udwf).collect()
+}
+
+#[derive(Debug)]
+struct SeriesEvaluator {
+    function: SeriesFunction,
+}
+
+impl PartitionEvaluator for SeriesEvaluator {
+    fn evaluate_all(&mut self, values: &[ArrayRef], num_rows: usize) -> Result<ArrayRef> {
+        let name = self.function.name();
+        let [series, parameter] = values else {
+            return exec_err!(
+                "`{name}` expects 2 arguments, but {} were given",
+                values.len()
+            );
+        };
+
+        let series = compute::cast(series, &DataType::Float64)?;
+        let series: Vec<Option<f64>> = as_float64_array(&series)?.iter().collect();
+        let parameter = compute::cast(parameter, &DataType::Float64)?;
+        let parameter = as_float64_array(&parameter)?;
+        if num_rows == 0 {
+            return Ok(self.function.evaluate(&[], 1.0));
+        }
+
+        if parameter.is_null(0) || !self.function.accepts(parameter.value(0)) {
+            let expected = self.function.expected();
+            return exec_err!("second argument to `{name}` must be {expected}");
+        }
+
+        Ok(self.function.evaluate(&series, parameter.value(0)))
+    }
+}
+
+fn moving_avg(values: &[Option<f64>], window: usize) -> Float64Array {
+    Float64Array::from_iter((0..values.len()).map(|i| {
+        let start = (i + 1).saturating_sub(window);
+        mean(&values[start..=i])
+    }))
+}
+
+/// The centered moving average over `period` values, narrowed at either end of the series.
+fn trend(values: &[Option<f64>], period: usize) -> Float64Array {
+    let before = (period - 1) / 2;
+    let after = period / 2;
+    Float64Array::from_iter((0..values.len()).map(|i| {
+        let start = i.saturating_sub(before);
+        let end = (i + after + 1).min(values.len());
+        mean(&values[start..end])
+    }))
+}
+
+fn seasonal(values: &[Option<f64>], trend: &Float64Array, period: usize) -> Float64Array {
+    let mut sums = vec![0.0; period];
+    let mut counts = vec![0; period];
+    for (i, (value, trend)) in values.iter().zip(trend.iter()).enumerate() {
+        if let (Some(value), Some(trend)) = (value, trend) {
+            sums[i % period] += value - trend;
+            counts[i % period] += 1;
+        }
+    }
+
+    let phases: Vec<Option<f64>> = sums
+        .iter()
+        .zip(&counts)
+        .map(|(sum, count)| (*count > 0).then(|| sum / *count as f64))
+        .collect();
+    // The seasonal component averages to zero over a period, leaving any constant offset in the trend.
+    let offset = mean(&phases).unwrap_or_default();
+    Float64Array::from_iter(
+        values
+            .iter()
+            .enumerate()
+            .map(|(i, value)| Some(phases[i % period]? - offset).filter(|_| value.is_some())),
+    )
+}
+
+fn anomalies(values: &[Option<f64>], center: f64, scale: f64, threshold: f64) -> Int64Array {
+    Int64Array::from_iter(values.iter().map(|value| {
+        let score = (value.as_ref()? - center) / scale;
+        Some(if score > threshold {
+            1
+        } else if score < -threshold {
+            -1
+        } else {
+            0
+        })
+    }))
+}
+
+fn mean(values: &[Option<f64>]) -> Option<f64> {
+    let present: Vec<f64> = values.iter().flatten().copied().collect();
+    if present.is_empty() {
+        return None;
+    }
+
+    Some(present.iter().sum::<f64>() / present.len() as f64)
+}
+
+fn median(mut values: Vec<f64>) -> f64 {
+    values.sort_by(f64::total_cmp);
+    let middle = values.len() / 2;
+    match values.len() {
+        0 => f64::NAN,
+        len if len.is_multiple_of(2) => (values[middle - 1] + values[middle]) / 2.0,
+        _ => values[middle],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use datafusion::arrow::{array::ArrayRef, util::display::array_value_to_string};
+    use expect_test::{expect, Expect};
+
+    use super::SeriesFunction;
+
+    fn check(function: SeriesFunction, values: &[Option<f64>], parameter: f64, expect: Expect) {
+        let result: ArrayRef = function.evaluate(values, parameter);
+        let result: Vec<String> = (0..result.len())
+            .map(|i| array_value_to_string(&result, i).unwrap())
+            .collect();
+        expect.assert_eq(&result.join(", "));
+    }
+
+    #[test]
+    fn moving_avg() {
+        check(
+            SeriesFunction::MovingAvg,
+            &[Some(1.0), Some(2.0), None, Some(6.0), Some(10.0)],
+            2.0,
+            expect!["1.0, 1.5, 2.0, 6.0, 8.0"],
+        );
+    }
+
+    #[test]
+    fn exp_smooth() {
+        check(
+            SeriesFunction::ExpSmooth,
+            &[Some(10.0), Some(20.0), None, Some(0.0)],
+            0.5,
+            expect!["10.0, 15.0, 15.0, 7.5"],
+        );
+    }
+
+    #[test]
+    fn decompose() {
+        let values: Vec<Option<f64>> = [1.0, 5.0, 2.0, 6.0, 3.0, 7.0, 4.0, 8.0]
+            .into_iter()
+            .map(Some)
+            .collect();
+        check(
+            SeriesFunction::Trend,
+            &values,
+            2.0,
+            expect!["3.0, 3.5, 4.0, 4.5, 5.0, 5.5, 6.0, 8.0"],
+        );
+        check(
+            SeriesFunction::Seasonal,
+            &values,
+            2.0,
+            expect!["-1.5625, 1.5625, -1.5625, 1.5625, -1.5625, 1.5625, -1.5625, 1.5625"],
+        );
+        check(
+            SeriesFunction::Residual,
+            &values,
+            2.0,
+            expect!["-0.4375, -0.0625, -0.4375, -0.0625, -0.4375, -0.0625, -0.4375, -1.5625"],
+        );
+    }
+
+    #[test]
+    fn anomalies() {
+        let values = [
+            Some(10.0),
+            Some(11.0),
+            Some(9.0),
+            Some(10.0),
+            None,
+            Some(50.0),
+            Some(10.0),
+            Some(-30.0),
+        ];
+        check(
+            SeriesFunction::ZscoreAnomalies,
+            &values,
+            1.5,
+            expect!["0, 0, 0, 0, , 1, 0, -1"],
+        );
+        check(
+            SeriesFunction::MadAnomalies,
+            &values,
+            3.5,
+            expect!["0, 0, 0, 0, , 1, 0, -1"],
+        );
+    }
+}
@@ -0,0 +1,211 @@
+//! Where in a query each part of its lowered plan came from.
+
+use codespan::{FileId, Span};
+use datafusion::{arrow::error::ArrowError, error::DataFusionError, logical_expr::LogicalPlan};
+
+/// The longest stretch of source shown next to an annotated plan node.
+const MAX_SNIPPET_LEN: usize = 40;
+
+/// Records the plan as it stood after each stage of a query (the table it reads from, each pipeline operator and each
+/// facet), so that the nodes of a plan can be traced back to the stage that produced them.
+///
+/// It also records the expressions that can fail once the query runs, so that an error raised while executing the plan
+/// can be traced back to the expressions that may have caused it.
+#[derive(Clone, Debug, Default)]
+pub struct SourceMap {
+    source: String,
+    stages: Vec<Stage>,
+    fallible: Vec<FallibleExpr>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Stage {
+    /// The span of the stage in the query.
+    pub span: Span,
+    /// The plan as it stood once the stage had been lowered.
+    pub plan: LogicalPlan,
+}
+
+impl SourceMap {
+    pub(crate) fn new(source: impl Into<String>) -> SourceMap {
+        SourceMap {
+            source: source.into(),
+            stages: vec![],
+            fallible: vec![],
+        }
+    }
+
+    pub(crate) fn push(&mut self, span: Span, plan: LogicalPlan) {
+        self.stages.push(Stage { span, plan });
+    }
+
+    /// Records an expression that can fail, as part of the stage being lowered, which is pushed once it is done.
+    pub(crate) fn push_fallible(&mut self, file: FileId, span: Span, kind: FailureKind) {
+        self.fallible.push(FallibleExpr {
+            file,
+            span,
+            kind,
+            stage: self.stages.len(),
+        });
+    }
+
+    pub fn stages(&self) -> &[Stage] {
+        &self.stages
+    }
+
+    /// Returns the candidates for the expression that raised an error while executing the given plan of the query.
+    /// DataFusion does not report which expression failed, so these are guesses: every expression that can fail in the
+    /// way the error seems to describe, going by its message, in a stage whose plan is part of the plan that failed.
+    pub fn culprits(&self, error: &DataFusionError, plan: &LogicalPlan) -> Vec<&FallibleExpr> {
+        let Some(kind) = FailureKind::of(error) else {
+            return vec![];
+        };
+
+        let ran: Vec<bool> = self
+            .stages
+            .iter()
+            .map(|stage| contains(plan, &|node| node == &stage.plan))
+            .collect();
+        self.fallible
+            .iter()
+            .filter(|expr| expr.kind == kind)
+            .filter(|expr| ran.get(expr.stage).copied().unwrap_or(true))
+            .collect()
+    }
+
+    /// Returns the first stage whose plan contains the given node. A node that has been rewritten by the optimizer no
+    /// longer appears in any stage; it is matched by its own description instead, ignoring its inputs, so that nodes
+    /// that the optimizer left intact can still be traced.
+    pub fn stage_of(&self, node: &LogicalPlan) -> Option<&Stage> {
+        self.stages
+            .iter()
+            .find(|stage| contains(&stage.plan, &|candidate| candidate == node))
+            .or_else(|| {
+                let description = node.display().to_string();
+                self.stages.iter().find(|stage| {
+                    contains(&stage.plan, &|candidate| {
+                        candidate.display().to_string() == description
+                    })
+                })
+            })
+    }
+
+    /// Returns the source of a stage on a single line, shortened if it is long.
+    pub fn snippet(&self, stage: &Stage) -> String {
+        let source = self
+            .source
+            .get(stage.span.start().to_usize()..stage.span.end().to_usize())
+            .unwrap_or_default();
+        let snippet = source.split_whitespace().collect::<Vec<_>>().join(" ");
+        if snippet.chars().count() <= MAX_SNIPPET_LEN {
+            return snippet;
+        }
+
+        let shortened: String = snippet.chars().take(MAX_SNIPPET_LEN - 1).collect();
+        format!("{}…", shortened)
+    }
+
+    /// Returns the 1-based line and column at which a stage begins.
+    pub fn position(&self, stage: &Stage) -> (usize, usize) {
+        let before = self
+            .source
+            .get(..stage.span.start().to_usize())
+            .unwrap_or_default();
+        let line = before.matches('\n').count() + 1;
+        let column = before
+            .rsplit('\n')
+            .next()
+            .map_or(0, |line| line.chars().count())
+            + 1;
+        (line, column)
+    }
+
+    /// Formats a plan with one node per line, like [`LogicalPlan::display_indent`], annotating each node with the stage
+    /// of the query that produced it.
+    pub fn display_annotated(&self, plan: &LogicalPlan) -> String {
+        let mut out = String::new();
+        self.write_annotated(&mut out, plan, 0);
+        out
+    }
+
+    fn write_annotated(&self, out: &mut String, plan: &LogicalPlan, depth: usize) {
+        out.push_str(&"  ".repeat(depth));
+        out.push_str(&plan.display().to_string());
+        if let Some(stage) = self.stage_of(plan) {
+            let (line, column) = self.position(stage);
+            out.push_str(&format!("  [{}:{} {}]", line, column, self.snippet(stage)));
+        }
+
+        out.push('\n');
+        for input in plan.inputs() {
+            self.write_annotated(out, input, depth + 1);
+        }
+    }
+}
+
+/// Whether any node of the plan satisfies the predicate.
+fn contains(plan: &LogicalPlan, predicate: &dyn Fn(&LogicalPlan) -> bool) -> bool {
+    predicate(plan)
+        || plan
+            .inputs()
+            .into_iter()
+            .any(|input| contains(input, predicate))
+}
+
+/// A way in which evaluating an expression can fail at run time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FailureKind {
+    /// Integer division or remainder by zero.
+    DivideByZero,
+    /// A conversion from one type to another, explicit or implied by comparing values of different types.
+    Cast,
+    /// A regular expression that does not compile.
+    Regex,
+}
+
+impl FailureKind {
+    /// Classifies an error raised while executing a plan, if it is one that an expression of the query can cause. Errors
+    /// that DataFusion raises only as text are classified by their message, so the kind is a guess.
+    pub fn of(error: &DataFusionError) -> Option<FailureKind> {
+        let root = error.find_root();
+        match root {
+            DataFusionError::ArrowError(ArrowError::DivideByZero) => {
+                return Some(FailureKind::DivideByZero)
+            }
+            DataFusionError::ArrowError(ArrowError::CastError(_) | ArrowError::ParseError(_)) => {
+                return Some(FailureKind::Cast)
+            }
+            _ => {}
+        }
+
+        let message = root.to_string().to_lowercase();
+        if message.contains("divide by zero") || message.contains("division by zero") {
+            Some(FailureKind::DivideByZero)
+        } else if message.contains("regular expression") || message.contains("regex") {
+            Some(FailureKind::Regex)
+        } else if message.contains("cast") {
+            Some(FailureKind::Cast)
+        } else {
+            None
+        }
+    }
+
+    /// A description of how an expression of this kind may have failed, for labelling it.
+    pub fn description(self) -> &'static str {
+        match self {
+            FailureKind::DivideByZero => "this may have divided by zero",
+            FailureKind::Cast => "this conversion may have failed",
+            FailureKind::Regex => "this regular expression may not compile",
+        }
+    }
+}
+
+/// An expression of the query that can fail at run time.
+#[derive(Clone, Debug)]
+pub struct FallibleExpr {
+    pub file: FileId,
+    pub span: Span,
+    pub kind: FailureKind,
+    /// The index of the stage that the expression is part of.
+    pub stage: usize,
+}
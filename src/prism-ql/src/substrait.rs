@@ -0,0 +1,269 @@
+//! Substrait plans of lowered queries, so that a query can be compiled once and then cached, or run by another engine.
+//!
+//! [`to_substrait`] first runs DataFusion's analyzer over the plan, as DataFusion does before running it, which among
+//! other things rewrites the wildcard of `count()` into something Substrait can express. DataFusion writes a table scan
+//! to Substrait without the filters and limit pushed into it, so those are then
+//! moved above the scan. A sample becomes a [`SampleNode`], which is written as an extension relation that the
+//! [`serializer_registry`] encodes, and any other filter becomes a `Filter`.
+//!
+//! Substrait plans cannot yet call the functions that PQL adds to DataFusion, such as `bin` in a time zone,
+//! `make-series`, `reduce` or the series functions, so plans that use them fail to serialize.
+
+use std::sync::Arc;
+
+use datafusion::{
+    common::{
+        internal_err, not_impl_err,
+        tree_node::{Transformed, TreeNode, VisitRecursion},
+        DFSchema,
+    },
+    config::ConfigOptions,
+    error::{DataFusionError, Result},
+    execution::{context::SessionState, registry::SerializerRegistry, runtime_env::RuntimeEnv},
+    logical_expr::{
+        expr::WindowFunction, EmptyRelation, Expr, Extension, LogicalPlan, LogicalPlanBuilder,
+        TableScan, UserDefinedLogicalNode, WindowFrameUnits,
+    },
+    optimizer::analyzer::Analyzer,
+    prelude::{SessionConfig, SessionContext},
+};
+use datafusion_substrait::{
+    logical_plan::{consumer, producer},
+    substrait::proto::{plan_rel::RelType, Plan, PlanRel},
+};
+use prost::Message;
+
+use crate::sample::{sample_fraction, SampleNode};
+
+/// The name under which a [`SampleNode`] is written.
+const SAMPLE_NODE: &str = "Sample";
+
+/// Writes and reads the nodes that PQL adds to DataFusion's plans, such as [`SampleNode`]. Sessions that read Substrait
+/// plans with [`from_substrait`] must use it.
+pub fn serializer_registry() -> Arc<dyn SerializerRegistry> {
+    Arc::new(PqlSerializerRegistry)
+}
+
+struct PqlSerializerRegistry;
+
+impl SerializerRegistry for PqlSerializerRegistry {
+    fn serialize_logical_plan(&self, node: &dyn UserDefinedLogicalNode) -> Result<Vec<u8>> {
+        let Some(sample) = node.as_any().downcast_ref::<SampleNode>() else {
+            return not_impl_err!("Substrait plans cannot contain `{}` nodes", node.name());
+        };
+
+        let Some(fraction) = sample_fraction(sample.filter()) else {
+            return internal_err!(
+                "sample node does not filter by a sample: {}",
+                sample.filter()
+            );
+        };
+
+        Ok(fraction.to_le_bytes().to_vec())
+    }
+
+    fn deserialize_logical_plan(
+        &self,
+        name: &str,
+        bytes: &[u8],
+    ) -> Result<Arc<dyn UserDefinedLogicalNode>> {
+        if name != SAMPLE_NODE {
+            return not_impl_err!("unknown extension node `{name}`");
+        }
+
+        let Ok(fraction) = bytes.try_into() else {
+            return Err(DataFusionError::Substrait(format!(
+                "a sample node holds its fraction in 8 bytes, not {}",
+                bytes.len()
+            )));
+        };
+
+        // The consumer replaces the input with the relation that the node was written with.
+        let input = LogicalPlan::EmptyRelation(EmptyRelation {
+            produce_one_row: false,
+            schema: Arc::new(DFSchema::empty()),
+        });
+        Ok(Arc::new(SampleNode::new(
+            input,
+            f64::from_le_bytes(fraction),
+        )))
+    }
+}
+
+/// Encodes a lowered plan as a Substrait plan.
+pub fn to_substrait(plan: &LogicalPlan) -> Result<Vec<u8>> {
+    let plan = Analyzer::new()
+        .execute_and_check(plan, &ConfigOptions::default(), |_, _| {})?
+        .transform_up(&lift_scan_pushdowns)?;
+    check_expressible(&plan)?;
+
+    let state =
+        SessionState::new_with_config_rt(SessionConfig::new(), Arc::new(RuntimeEnv::default()))
+            .with_serializer_registry(serializer_registry());
+    let ctx = SessionContext::new_with_state(state);
+    let mut substrait = producer::to_substrait_plan(&plan, &ctx)?;
+    // DataFusion names the columns of the plan by their qualified names, which it cannot read back.
+    if let Some(PlanRel {
+        rel_type: Some(RelType::Root(root)),
+    }) = substrait.relations.first_mut()
+    {
+        root.names = plan
+            .schema()
+            .fields()
+            .iter()
+            .map(|field| field.name().clone())
+            .collect();
+    }
+
+    let mut bytes = vec![];
+    substrait
+        .encode(&mut bytes)
+        .map_err(|e| DataFusionError::Substrait(format!("failed to encode Substrait plan: {e}")))?;
+    Ok(bytes)
+}
+
+/// Decodes a Substrait plan, such as one written by [`to_substrait`], resolving its tables in the session's catalogs.
+/// The session must use the [`serializer_registry`], and run the plan with the
+/// [`query_planner`](crate::query_planner).
+pub async fn from_substrait(ctx: &mut SessionContext, bytes: &[u8]) -> Result<LogicalPlan> {
+    let plan = Plan::decode(bytes)
+        .map_err(|e| DataFusionError::Substrait(format!("failed to decode Substrait plan: {e}")))?;
+    let names = match plan.relations.first() {
+        Some(PlanRel {
+            rel_type: Some(RelType::Root(root)),
+        }) => root.names.clone(),
+        _ => vec![],
+    };
+
+    // DataFusion reads back the columns under the names of their expressions rather than those of the plan, so
+    // aggregates and the like lose their aliases unless they are given them again.
+    let plan = consumer::from_substrait_plan(ctx, &plan).await?;
+    let fields = plan.schema().fields();
+    if fields.len() != names.len()
+        || fields
+            .iter()
+            .zip(&names)
+            .all(|(field, name)| field.name() == name)
+    {
+        return Ok(plan);
+    }
+
+    let columns: Vec<Expr> = fields
+        .iter()
+        .zip(names)
+        .map(|(field, name)| Expr::Column(field.qualified_column()).alias(name))
+        .collect();
+    LogicalPlanBuilder::from(plan).project(columns)?.build()
+}
+
+/// Moves the filters and limit pushed into a scan above it, where Substrait can express them.
+fn lift_scan_pushdowns(plan: LogicalPlan) -> Result<Transformed<LogicalPlan>> {
+    let LogicalPlan::TableScan(scan) = &plan else {
+        return Ok(Transformed::No(plan));
+    };
+
+    if scan.filters.is_empty() && scan.fetch.is_none() {
+        return Ok(Transformed::No(plan));
+    }
+
+    let TableScan {
+        table_name,
+        source,
+        projection,
+        projected_schema,
+        filters,
+        fetch,
+    } = scan.clone();
+    let mut builder = LogicalPlanBuilder::scan(table_name, source, None)?;
+    for filter in filters {
+        builder = match sample_fraction(&filter) {
+            Some(fraction) => LogicalPlanBuilder::from(LogicalPlan::Extension(Extension {
+                node: Arc::new(SampleNode::new(builder.build()?, fraction)),
+            })),
+            None => builder.filter(filter)?,
+        };
+    }
+
+    if projection.is_some() {
+        builder = builder.project(
+            projected_schema
+                .fields()
+                .iter()
+                .map(|field| Expr::Column(field.qualified_column())),
+        )?;
+    }
+
+    if fetch.is_some() {
+        builder = builder.limit(0, fetch)?;
+    }
+
+    Ok(Transformed::Yes(builder.build()?))
+}
+
+/// Returns an error if the plan calls a function that Substrait cannot express, or that DataFusion would read back as
+/// something else.
+fn check_expressible(plan: &LogicalPlan) -> Result<()> {
+    let mut result = Ok(());
+    plan.apply(&mut |plan| {
+        // Extension nodes are written by the serializer registry rather than from their expressions.
+        if let LogicalPlan::Extension(_) = plan {
+            return Ok(VisitRecursion::Continue);
+        }
+
+        for expr in plan.expressions() {
+            expr.apply(&mut |expr| {
+                result = check_expr(expr);
+                Ok(if result.is_ok() {
+                    VisitRecursion::Continue
+                } else {
+                    VisitRecursion::Stop
+                })
+            })?;
+            if result.is_err() {
+                return Ok(VisitRecursion::Stop);
+            }
+        }
+
+        Ok(VisitRecursion::Continue)
+    })?;
+    result
+}
+
+fn check_expr(expr: &Expr) -> Result<()> {
+    match expr {
+        Expr::ScalarUDF(udf) => {
+            not_impl_err!("Substrait plans cannot call `{}` yet", udf.fun.name)
+        }
+        Expr::AggregateUDF(udaf) => {
+            not_impl_err!("Substrait plans cannot call `{}` yet", udaf.fun.name)
+        }
+        Expr::WindowFunction(WindowFunction {
+            fun: datafusion::logical_expr::WindowFunction::WindowUDF(udwf),
+            ..
+        }) => not_impl_err!("Substrait plans cannot call `{}` yet", udwf.name),
+        // Substrait does not record what a frame counts, so DataFusion reads back a frame of rows when the window is
+        // unordered and of ranges when it is ordered.
+        Expr::WindowFunction(WindowFunction {
+            order_by,
+            window_frame,
+            ..
+        }) if window_frame.units != read_back_units(order_by) => not_impl_err!(
+            "Substrait plans cannot express a window frame in {} {}",
+            window_frame.units,
+            if order_by.is_empty() {
+                "without an order"
+            } else {
+                "with an order"
+            }
+        ),
+        _ => Ok(()),
+    }
+}
+
+fn read_back_units(order_by: &[Expr]) -> WindowFrameUnits {
+    if order_by.is_empty() {
+        WindowFrameUnits::Rows
+    } else {
+        WindowFrameUnits::Range
+    }
+}
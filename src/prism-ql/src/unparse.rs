@@ -0,0 +1,870 @@
+//! Converts lowered plans back into SQL, so that users can see what their PQL became in a language they already know.
+//!
+//! Each plan node is folded into the `SELECT` being built for its input where SQL's clause order allows it, and
+//! otherwise wraps that `SELECT` in a derived table. Expressions that are not plain column references are given their
+//! DataFusion display name as an alias, so that the nodes above them can refer to them by the same name they use in
+//! the plan.
+
+use chrono::{NaiveDate, TimeZone, Utc};
+use datafusion::{
+    arrow::datatypes::{DataType, IntervalMonthDayNanoType, TimeUnit},
+    common::{not_impl_err, Column, OwnedTableReference},
+    error::{DataFusionError, Result},
+    logical_expr::{
+        expr::{
+            AggregateFunction, AggregateUDF, Alias, Between, BinaryExpr, Case, Cast,
+            GetFieldAccess, GetIndexedField, InList, Like, ScalarFunction, ScalarUDF, Sort,
+            TryCast, WindowFunction,
+        },
+        Distinct, Expr, JoinType, LogicalPlan, WindowFrame, WindowFrameBound,
+    },
+    scalar::ScalarValue,
+};
+
+use crate::sample::SampleNode;
+
+/// Returns a SQL query that is equivalent to the given plan, or an error if the plan uses a feature that cannot be
+/// expressed in SQL.
+pub fn plan_to_sql(plan: &LogicalPlan) -> Result<String> {
+    let mut unparser = Unparser { derived_tables: 0 };
+    Ok(unparser.select(plan)?.to_string())
+}
+
+struct Unparser {
+    /// The number of derived tables given a generated name so far.
+    derived_tables: usize,
+}
+
+/// A `SELECT` statement under construction.
+#[derive(Default)]
+struct Select {
+    distinct: bool,
+    /// The select list; empty if it is still `*`.
+    projection: Vec<String>,
+    from: String,
+    /// Whether `from` joins several relations, in which case columns must be qualified by the relation they belong to.
+    joined: bool,
+    selection: Vec<String>,
+    group_by: Option<Vec<String>>,
+    order_by: Vec<String>,
+    limit: Option<usize>,
+    offset: usize,
+}
+
+impl Select {
+    fn from(from: String) -> Select {
+        Select {
+            from,
+            ..Default::default()
+        }
+    }
+
+    /// Whether nothing but filters has been applied to the rows of `from` yet.
+    fn is_plain(&self) -> bool {
+        !self.distinct
+            && self.projection.is_empty()
+            && self.group_by.is_none()
+            && self.order_by.is_empty()
+            && self.limit.is_none()
+            && self.offset == 0
+    }
+}
+
+impl std::fmt::Display for Select {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SELECT ")?;
+        if self.distinct {
+            f.write_str("DISTINCT ")?;
+        }
+
+        if self.projection.is_empty() {
+            f.write_str("*")?;
+        } else {
+            f.write_str(&self.projection.join(", "))?;
+        }
+
+        write!(f, " FROM {}", self.from)?;
+        if !self.selection.is_empty() {
+            write!(f, " WHERE {}", self.selection.join(" AND "))?;
+        }
+
+        if let Some(group_by) = &self.group_by {
+            if !group_by.is_empty() {
+                write!(f, " GROUP BY {}", group_by.join(", "))?;
+            }
+        }
+
+        if !self.order_by.is_empty() {
+            write!(f, " ORDER BY {}", self.order_by.join(", "))?;
+        }
+
+        if let Some(limit) = self.limit {
+            write!(f, " LIMIT {}", limit)?;
+        }
+
+        if self.offset > 0 {
+            write!(f, " OFFSET {}", self.offset)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Unparser {
+    fn select(&mut self, plan: &LogicalPlan) -> Result<Select> {
+        match plan {
+            LogicalPlan::TableScan(scan) => {
+                let mut select = Select::from(table_name(&scan.table_name));
+                if scan.projection.is_some() {
+                    select.projection = scan
+                        .projected_schema
+                        .fields()
+                        .iter()
+                        .map(|field| quote(field.name()))
+                        .collect();
+                }
+
+                for filter in &scan.filters {
+                    select.selection.push(expr_to_sql(filter, false)?);
+                }
+
+                select.limit = scan.fetch;
+                Ok(select)
+            }
+            LogicalPlan::Values(values) => {
+                let mut rows = vec![];
+                for row in &values.values {
+                    let row: Vec<String> = row
+                        .iter()
+                        .map(|value| expr_to_sql(value, false))
+                        .collect::<Result<_>>()?;
+                    rows.push(format!("({})", row.join(", ")));
+                }
+
+                Ok(Select::from(format!(
+                    "(VALUES {}) AS {}",
+                    rows.join(", "),
+                    self.derived_table_name()
+                )))
+            }
+            LogicalPlan::Filter(filter) => self.filter(&filter.input, &filter.predicate),
+            LogicalPlan::Extension(extension) => {
+                match extension.node.as_any().downcast_ref::<SampleNode>() {
+                    Some(sample) => self.filter(extension.node.inputs()[0], sample.filter()),
+                    None => not_impl_err!("{} cannot be expressed as SQL", node_name(plan)),
+                }
+            }
+            LogicalPlan::Projection(projection) => {
+                let mut select = self.select(&projection.input)?;
+                if !select.is_plain() {
+                    select = self.derive(select, &projection.input)?;
+                }
+
+                select.projection = projection
+                    .expr
+                    .iter()
+                    .map(|expr| select_item(expr, select.joined))
+                    .collect::<Result<_>>()?;
+                Ok(select)
+            }
+            LogicalPlan::Aggregate(aggregate) => {
+                let mut select = self.select(&aggregate.input)?;
+                if !select.is_plain() {
+                    select = self.derive(select, &aggregate.input)?;
+                }
+
+                let mut group_by = vec![];
+                let mut projection = vec![];
+                for expr in &aggregate.group_expr {
+                    group_by.push(expr_to_sql(unalias(expr), select.joined)?);
+                    projection.push(select_item(expr, select.joined)?);
+                }
+
+                for expr in &aggregate.aggr_expr {
+                    projection.push(select_item(expr, select.joined)?);
+                }
+
+                select.group_by = Some(group_by);
+                select.projection = projection;
+                Ok(select)
+            }
+            LogicalPlan::Window(window) => {
+                let mut select = self.select(&window.input)?;
+                if !select.is_plain() {
+                    select = self.derive(select, &window.input)?;
+                }
+
+                select.projection = std::iter::once(Ok("*".to_string()))
+                    .chain(
+                        window
+                            .window_expr
+                            .iter()
+                            .map(|expr| select_item(expr, select.joined)),
+                    )
+                    .collect::<Result<_>>()?;
+                Ok(select)
+            }
+            LogicalPlan::Sort(sort) => {
+                let mut select = self.select(&sort.input)?;
+                if !select.order_by.is_empty() || select.limit.is_some() || select.offset > 0 {
+                    select = self.derive(select, &sort.input)?;
+                }
+
+                select.order_by = sort
+                    .expr
+                    .iter()
+                    .map(|expr| expr_to_sql(expr, select.joined))
+                    .collect::<Result<_>>()?;
+                select.limit = sort.fetch;
+                Ok(select)
+            }
+            LogicalPlan::Limit(limit) => {
+                let mut select = self.select(&limit.input)?;
+                if select.limit.is_some() || select.offset > 0 {
+                    select = self.derive(select, &limit.input)?;
+                }
+
+                select.limit = limit.fetch;
+                select.offset = limit.skip;
+                Ok(select)
+            }
+            LogicalPlan::Distinct(Distinct { input }) => {
+                let mut select = self.select(input)?;
+                if select.distinct
+                    || select.group_by.is_some()
+                    || !select.order_by.is_empty()
+                    || select.limit.is_some()
+                    || select.offset > 0
+                {
+                    select = self.derive(select, input)?;
+                }
+
+                select.distinct = true;
+                Ok(select)
+            }
+            LogicalPlan::SubqueryAlias(alias) => {
+                let select = self.select(&alias.input)?;
+                Ok(Select::from(format!(
+                    "({}) AS {}",
+                    select,
+                    quote(alias.alias.table())
+                )))
+            }
+            LogicalPlan::Join(join) => {
+                let left = self.relation(&join.left)?;
+                let right = self.relation(&join.right)?;
+                let join_type = match join.join_type {
+                    JoinType::Inner => "JOIN",
+                    JoinType::Left => "LEFT JOIN",
+                    JoinType::Right => "RIGHT JOIN",
+                    JoinType::Full => "FULL JOIN",
+                    JoinType::LeftSemi => "LEFT SEMI JOIN",
+                    JoinType::RightSemi => "RIGHT SEMI JOIN",
+                    JoinType::LeftAnti => "LEFT ANTI JOIN",
+                    JoinType::RightAnti => "RIGHT ANTI JOIN",
+                };
+                let mut conditions = join
+                    .on
+                    .iter()
+                    .map(|(left, right)| {
+                        Ok(format!(
+                            "{} = {}",
+                            expr_to_sql(left, true)?,
+                            expr_to_sql(right, true)?
+                        ))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                if let Some(filter) = &join.filter {
+                    conditions.push(expr_to_sql(filter, true)?);
+                }
+
+                let mut from = format!("{} {} {}", left, join_type, right);
+                if !conditions.is_empty() {
+                    from.push_str(&format!(" ON {}", conditions.join(" AND ")));
+                }
+
+                Ok(Select {
+                    joined: true,
+                    ..Select::from(from)
+                })
+            }
+            LogicalPlan::CrossJoin(join) => {
+                let left = self.relation(&join.left)?;
+                let right = self.relation(&join.right)?;
+                Ok(Select {
+                    joined: true,
+                    ..Select::from(format!("{} CROSS JOIN {}", left, right))
+                })
+            }
+            LogicalPlan::Union(union) => {
+                let inputs: Vec<String> = union
+                    .inputs
+                    .iter()
+                    .map(|input| Ok(self.select(input)?.to_string()))
+                    .collect::<Result<_>>()?;
+                Ok(Select::from(format!(
+                    "({}) AS {}",
+                    inputs.join(" UNION ALL "),
+                    self.derived_table_name()
+                )))
+            }
+            _ => not_impl_err!("{} cannot be expressed as SQL", node_name(plan)),
+        }
+    }
+
+    fn filter(&mut self, input: &LogicalPlan, predicate: &Expr) -> Result<Select> {
+        let mut select = self.select(input)?;
+        if !select.is_plain() {
+            select = self.derive(select, input)?;
+        }
+
+        select
+            .selection
+            .push(expr_to_sql(predicate, select.joined)?);
+        Ok(select)
+    }
+
+    /// Returns a relation that can appear on either side of a join, naming it after the relation that the plan's
+    /// columns are qualified by so that the join condition can refer to them.
+    fn relation(&mut self, plan: &LogicalPlan) -> Result<String> {
+        match plan {
+            LogicalPlan::TableScan(scan)
+                if scan.projection.is_none() && scan.filters.is_empty() =>
+            {
+                Ok(table_name(&scan.table_name))
+            }
+            LogicalPlan::SubqueryAlias(_) => Ok(self.select(plan)?.from),
+            _ => {
+                let select = self.select(plan)?;
+                Ok(format!("({}) AS {}", select, self.relation_name(plan)))
+            }
+        }
+    }
+
+    /// Wraps a `SELECT` in a derived table, so that further clauses apply to its results.
+    fn derive(&mut self, select: Select, plan: &LogicalPlan) -> Result<Select> {
+        if select.joined {
+            return not_impl_err!("{} over a join cannot be expressed as SQL", node_name(plan));
+        }
+
+        Ok(Select::from(format!(
+            "({}) AS {}",
+            select,
+            self.relation_name(plan)
+        )))
+    }
+
+    /// The name of the relation that every qualified column of the plan belongs to, if there is exactly one, or else a
+    /// generated name.
+    fn relation_name(&mut self, plan: &LogicalPlan) -> String {
+        let mut qualifiers = plan.schema().fields().iter().filter_map(|field| {
+            field
+                .qualifier()
+                .map(|qualifier| qualifier.table().to_string())
+        });
+        match qualifiers.next() {
+            Some(first) if qualifiers.all(|qualifier| qualifier == first) => quote(&first),
+            _ => self.derived_table_name(),
+        }
+    }
+
+    fn derived_table_name(&mut self) -> String {
+        self.derived_tables += 1;
+        format!("t{}", self.derived_tables)
+    }
+}
+
+fn node_name(plan: &LogicalPlan) -> String {
+    let display = plan.display().to_string();
+    display
+        .split(':')
+        .next()
+        .unwrap_or(&display)
+        .trim()
+        .to_string()
+}
+
+fn quote(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+fn table_name(name: &OwnedTableReference) -> String {
+    match name {
+        OwnedTableReference::Bare { table } => quote(table),
+        OwnedTableReference::Partial { schema, table } => {
+            format!("{}.{}", quote(schema), quote(table))
+        }
+        OwnedTableReference::Full {
+            catalog,
+            schema,
+            table,
+        } => format!("{}.{}.{}", quote(catalog), quote(schema), quote(table)),
+    }
+}
+
+fn unalias(expr: &Expr) -> &Expr {
+    match expr {
+        Expr::Alias(Alias { expr, .. }) => expr,
+        _ => expr,
+    }
+}
+
+/// Renders an item of a select list, naming it the way the plan names its output column.
+fn select_item(expr: &Expr, qualify: bool) -> Result<String> {
+    match expr {
+        Expr::Column(_) | Expr::Alias(_) => expr_to_sql(expr, qualify),
+        _ => Ok(format!(
+            "{} AS {}",
+            expr_to_sql(expr, qualify)?,
+            quote(&expr.display_name()?)
+        )),
+    }
+}
+
+fn column_to_sql(column: &Column, qualify: bool) -> String {
+    match &column.relation {
+        Some(relation) if qualify => format!("{}.{}", quote(relation.table()), quote(&column.name)),
+        _ => quote(&column.name),
+    }
+}
+
+fn exprs_to_sql(exprs: &[Expr], qualify: bool) -> Result<String> {
+    let exprs: Vec<String> = exprs
+        .iter()
+        .map(|expr| expr_to_sql(expr, qualify))
+        .collect::<Result<_>>()?;
+    Ok(exprs.join(", "))
+}
+
+/// Renders an expression as SQL, qualifying column references by their relation if `qualify` is set.
+fn expr_to_sql(expr: &Expr, qualify: bool) -> Result<String> {
+    let sql = |expr: &Expr| expr_to_sql(expr, qualify);
+    Ok(match expr {
+        Expr::Alias(Alias { expr, name }) => format!("{} AS {}", sql(expr)?, quote(name)),
+        Expr::Column(column) => column_to_sql(column, qualify),
+        Expr::Literal(value) => scalar_to_sql(value)?,
+        Expr::BinaryExpr(BinaryExpr { left, op, right }) => {
+            format!("({} {} {})", sql(left)?, op, sql(right)?)
+        }
+        Expr::Like(Like {
+            negated,
+            expr,
+            pattern,
+            escape_char,
+            case_insensitive,
+        }) => {
+            let mut like = format!(
+                "{} {}{} {}",
+                sql(expr)?,
+                if *negated { "NOT " } else { "" },
+                if *case_insensitive { "ILIKE" } else { "LIKE" },
+                sql(pattern)?
+            );
+            if let Some(escape) = escape_char {
+                like.push_str(&format!(" ESCAPE '{}'", escape));
+            }
+
+            like
+        }
+        Expr::Not(expr) => format!("(NOT {})", sql(expr)?),
+        Expr::IsNull(expr) => format!("({} IS NULL)", sql(expr)?),
+        Expr::IsNotNull(expr) => format!("({} IS NOT NULL)", sql(expr)?),
+        Expr::IsTrue(expr) => format!("({} IS TRUE)", sql(expr)?),
+        Expr::IsFalse(expr) => format!("({} IS FALSE)", sql(expr)?),
+        Expr::IsNotTrue(expr) => format!("({} IS NOT TRUE)", sql(expr)?),
+        Expr::IsNotFalse(expr) => format!("({} IS NOT FALSE)", sql(expr)?),
+        Expr::Negative(expr) => format!("(- {})", sql(expr)?),
+        Expr::Between(Between {
+            expr,
+            negated,
+            low,
+            high,
+        }) => format!(
+            "({} {}BETWEEN {} AND {})",
+            sql(expr)?,
+            if *negated { "NOT " } else { "" },
+            sql(low)?,
+            sql(high)?
+        ),
+        Expr::InList(InList {
+            expr,
+            list,
+            negated,
+        }) => format!(
+            "({} {}IN ({}))",
+            sql(expr)?,
+            if *negated { "NOT " } else { "" },
+            exprs_to_sql(list, qualify)?
+        ),
+        Expr::Case(Case {
+            expr,
+            when_then_expr,
+            else_expr,
+        }) => {
+            let mut case = "CASE".to_string();
+            if let Some(expr) = expr {
+                case.push_str(&format!(" {}", sql(expr)?));
+            }
+
+            for (when, then) in when_then_expr {
+                case.push_str(&format!(" WHEN {} THEN {}", sql(when)?, sql(then)?));
+            }
+
+            if let Some(else_expr) = else_expr {
+                case.push_str(&format!(" ELSE {}", sql(else_expr)?));
+            }
+
+            case.push_str(" END");
+            case
+        }
+        Expr::Cast(Cast { expr, data_type }) => {
+            format!("CAST({} AS {})", sql(expr)?, type_to_sql(data_type)?)
+        }
+        Expr::TryCast(TryCast { expr, data_type }) => {
+            format!("TRY_CAST({} AS {})", sql(expr)?, type_to_sql(data_type)?)
+        }
+        Expr::Sort(Sort {
+            expr,
+            asc,
+            nulls_first,
+        }) => format!(
+            "{} {} {}",
+            sql(expr)?,
+            if *asc { "ASC" } else { "DESC" },
+            if *nulls_first {
+                "NULLS FIRST"
+            } else {
+                "NULLS LAST"
+            }
+        ),
+        Expr::ScalarFunction(ScalarFunction { fun, args }) => {
+            format!("{}({})", fun, exprs_to_sql(args, qualify)?)
+        }
+        Expr::ScalarUDF(ScalarUDF { fun, args }) => {
+            format!("{}({})", fun.name, exprs_to_sql(args, qualify)?)
+        }
+        Expr::AggregateFunction(AggregateFunction {
+            fun,
+            args,
+            distinct,
+            filter,
+            order_by,
+        }) => aggregate_to_sql(
+            &fun.to_string(),
+            args,
+            *distinct,
+            filter.as_deref(),
+            order_by.as_deref(),
+            qualify,
+        )?,
+        Expr::AggregateUDF(AggregateUDF {
+            fun,
+            args,
+            filter,
+            order_by,
+        }) => aggregate_to_sql(
+            &fun.name,
+            args,
+            false,
+            filter.as_deref(),
+            order_by.as_deref(),
+            qualify,
+        )?,
+        Expr::WindowFunction(WindowFunction {
+            fun,
+            args,
+            partition_by,
+            order_by,
+            window_frame,
+        }) => {
+            let mut over = vec![];
+            if !partition_by.is_empty() {
+                over.push(format!(
+                    "PARTITION BY {}",
+                    exprs_to_sql(partition_by, qualify)?
+                ));
+            }
+
+            if !order_by.is_empty() {
+                over.push(format!("ORDER BY {}", exprs_to_sql(order_by, qualify)?));
+            }
+
+            over.push(frame_to_sql(window_frame)?);
+            format!(
+                "{}({}) OVER ({})",
+                fun,
+                exprs_to_sql(args, qualify)?,
+                over.join(" ")
+            )
+        }
+        Expr::GetIndexedField(GetIndexedField { expr, field }) => match field {
+            GetFieldAccess::NamedStructField { name } => {
+                format!("{}[{}]", sql(expr)?, scalar_to_sql(name)?)
+            }
+            GetFieldAccess::ListIndex { key } => format!("{}[{}]", sql(expr)?, sql(key)?),
+            GetFieldAccess::ListRange { start, stop } => {
+                format!("{}[{}:{}]", sql(expr)?, sql(start)?, sql(stop)?)
+            }
+        },
+        Expr::Wildcard => "*".to_string(),
+        _ => return not_impl_err!("the expression {} cannot be expressed as SQL", expr),
+    })
+}
+
+fn aggregate_to_sql(
+    name: &str,
+    args: &[Expr],
+    distinct: bool,
+    filter: Option<&Expr>,
+    order_by: Option<&[Expr]>,
+    qualify: bool,
+) -> Result<String> {
+    let mut sql = format!(
+        "{}({}{}",
+        name,
+        if distinct { "DISTINCT " } else { "" },
+        exprs_to_sql(args, qualify)?
+    );
+    if let Some(order_by) = order_by {
+        sql.push_str(&format!(" ORDER BY {}", exprs_to_sql(order_by, qualify)?));
+    }
+
+    sql.push(')');
+    if let Some(filter) = filter {
+        sql.push_str(&format!(
+            " FILTER (WHERE {})",
+            expr_to_sql(filter, qualify)?
+        ));
+    }
+
+    Ok(sql)
+}
+
+fn frame_to_sql(frame: &WindowFrame) -> Result<String> {
+    let bound = |bound: &WindowFrameBound| -> Result<String> {
+        Ok(match bound {
+            WindowFrameBound::Preceding(value) if value.is_null() => {
+                "UNBOUNDED PRECEDING".to_string()
+            }
+            WindowFrameBound::Preceding(value) => format!("{} PRECEDING", scalar_to_sql(value)?),
+            WindowFrameBound::CurrentRow => "CURRENT ROW".to_string(),
+            WindowFrameBound::Following(value) if value.is_null() => {
+                "UNBOUNDED FOLLOWING".to_string()
+            }
+            WindowFrameBound::Following(value) => format!("{} FOLLOWING", scalar_to_sql(value)?),
+        })
+    };
+
+    Ok(format!(
+        "{} BETWEEN {} AND {}",
+        frame.units,
+        bound(&frame.start_bound)?,
+        bound(&frame.end_bound)?
+    ))
+}
+
+fn type_to_sql(ty: &DataType) -> Result<&'static str> {
+    Ok(match ty {
+        DataType::Boolean => "BOOLEAN",
+        DataType::Int8 => "TINYINT",
+        DataType::Int16 => "SMALLINT",
+        DataType::Int32 => "INT",
+        DataType::Int64 => "BIGINT",
+        DataType::UInt8 => "TINYINT UNSIGNED",
+        DataType::UInt16 => "SMALLINT UNSIGNED",
+        DataType::UInt32 => "INT UNSIGNED",
+        DataType::UInt64 => "BIGINT UNSIGNED",
+        DataType::Float32 => "REAL",
+        DataType::Float64 => "DOUBLE",
+        DataType::Utf8 | DataType::LargeUtf8 => "VARCHAR",
+        DataType::Binary | DataType::LargeBinary => "BYTEA",
+        DataType::Date32 => "DATE",
+        DataType::Timestamp(TimeUnit::Nanosecond, None) => "TIMESTAMP",
+        _ => return not_impl_err!("the type {} cannot be expressed as SQL", ty),
+    })
+}
+
+fn scalar_to_sql(value: &ScalarValue) -> Result<String> {
+    if value.is_null() {
+        return Ok(match type_to_sql(&value.data_type()) {
+            Ok(ty) => format!("CAST(NULL AS {})", ty),
+            Err(_) => "NULL".to_string(),
+        });
+    }
+
+    Ok(match value {
+        ScalarValue::Boolean(Some(value)) => value.to_string().to_uppercase(),
+        ScalarValue::Int8(Some(value)) => value.to_string(),
+        ScalarValue::Int16(Some(value)) => value.to_string(),
+        ScalarValue::Int32(Some(value)) => value.to_string(),
+        ScalarValue::Int64(Some(value)) => value.to_string(),
+        ScalarValue::UInt8(Some(value)) => value.to_string(),
+        ScalarValue::UInt16(Some(value)) => value.to_string(),
+        ScalarValue::UInt32(Some(value)) => value.to_string(),
+        ScalarValue::UInt64(Some(value)) => value.to_string(),
+        ScalarValue::Float32(Some(value)) if value.is_finite() => format!("{:?}", value),
+        ScalarValue::Float64(Some(value)) if value.is_finite() => format!("{:?}", value),
+        ScalarValue::Float32(Some(value)) => format!("CAST('{}' AS REAL)", value),
+        ScalarValue::Float64(Some(value)) => format!("CAST('{}' AS DOUBLE)", value),
+        ScalarValue::Utf8(Some(value)) | ScalarValue::LargeUtf8(Some(value)) => {
+            format!("'{}'", value.replace('\'', "''"))
+        }
+        ScalarValue::Date32(Some(days)) => {
+            let date = NaiveDate::default() + chrono::Duration::days(*days as i64);
+            format!("CAST('{}' AS DATE)", date.format("%Y-%m-%d"))
+        }
+        ScalarValue::TimestampSecond(Some(value), _) => format!("to_timestamp_seconds({})", value),
+        ScalarValue::TimestampMillisecond(Some(value), _) => {
+            format!("to_timestamp_millis({})", value)
+        }
+        ScalarValue::TimestampMicrosecond(Some(value), _) => {
+            format!("to_timestamp_micros({})", value)
+        }
+        ScalarValue::TimestampNanosecond(Some(value), _) => {
+            format!(
+                "CAST('{}' AS TIMESTAMP)",
+                Utc.timestamp_nanos(*value).format("%Y-%m-%dT%H:%M:%S%.9f")
+            )
+        }
+        ScalarValue::IntervalMonthDayNano(Some(value)) => {
+            let (months, days, nanos) = IntervalMonthDayNanoType::to_parts(*value);
+            format!(
+                "INTERVAL '{} months {} days {} nanoseconds'",
+                months, days, nanos
+            )
+        }
+        _ => return not_impl_err!("the value {:?} cannot be expressed as SQL", value),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use datafusion::{
+        arrow::datatypes::{DataType, Field, Schema, TimeUnit},
+        common::Column,
+        logical_expr::{
+            builder::LogicalTableSource, expr_fn, lit, Expr, Extension, JoinType, LogicalPlan,
+            LogicalPlanBuilder,
+        },
+        scalar::ScalarValue,
+    };
+    use expect_test::{expect, Expect};
+
+    use super::plan_to_sql;
+    use crate::sample::SampleNode;
+
+    fn scan() -> LogicalPlanBuilder {
+        let schema = Schema::new(vec![
+            Field::new(
+                "timestamp",
+                DataType::Timestamp(TimeUnit::Millisecond, None),
+                true,
+            ),
+            Field::new("status", DataType::Int64, true),
+            Field::new("host", DataType::Utf8, true),
+        ]);
+        LogicalPlanBuilder::scan(
+            "prism.tenant.http_requests",
+            Arc::new(LogicalTableSource::new(Arc::new(schema))),
+            None,
+        )
+        .unwrap()
+    }
+
+    fn check(plan: LogicalPlan, expect: Expect) {
+        expect.assert_eq(&plan_to_sql(&plan).unwrap());
+    }
+
+    #[test]
+    fn filter_and_aggregate() {
+        let plan = scan()
+            .filter(expr_fn::col("status").gt_eq(lit(500)))
+            .unwrap()
+            .aggregate(
+                vec![expr_fn::col("host")],
+                vec![expr_fn::count(expr_fn::col("status"))],
+            )
+            .unwrap()
+            .sort(vec![expr_fn::col("host").sort(true, false)])
+            .unwrap()
+            .limit(0, Some(10))
+            .unwrap()
+            .build()
+            .unwrap();
+        check(
+            plan,
+            expect![[
+                r#"SELECT "host", COUNT("status") AS "COUNT(prism.tenant.http_requests.status)" FROM "prism"."tenant"."http_requests" WHERE ("status" >= 500) GROUP BY "host" ORDER BY "host" ASC NULLS LAST LIMIT 10"#
+            ]],
+        );
+    }
+
+    #[test]
+    fn projection_over_aggregate() {
+        let plan = scan()
+            .filter(
+                expr_fn::col("timestamp").gt(lit(ScalarValue::TimestampMillisecond(
+                    Some(1698836400000),
+                    None,
+                ))),
+            )
+            .unwrap()
+            .aggregate(
+                Vec::<Expr>::new(),
+                vec![expr_fn::count(expr_fn::col("host"))],
+            )
+            .unwrap()
+            .project(vec![(Expr::Column(Column::from_name(
+                "COUNT(prism.tenant.http_requests.host)",
+            )) * lit(2))
+            .alias("twice")])
+            .unwrap()
+            .build()
+            .unwrap();
+        check(
+            plan,
+            expect![[
+                r#"SELECT ("COUNT(prism.tenant.http_requests.host)" * 2) AS "twice" FROM (SELECT COUNT("host") AS "COUNT(prism.tenant.http_requests.host)" FROM "prism"."tenant"."http_requests" WHERE ("timestamp" > to_timestamp_millis(1698836400000))) AS t1"#
+            ]],
+        );
+    }
+
+    #[test]
+    fn sample_over_aggregate() {
+        let summary = scan()
+            .aggregate(
+                vec![expr_fn::col("host")],
+                vec![expr_fn::count(expr_fn::col("status"))],
+            )
+            .unwrap()
+            .build()
+            .unwrap();
+        let plan = LogicalPlan::Extension(Extension {
+            node: Arc::new(SampleNode::new(summary, 0.5)),
+        });
+        check(
+            plan,
+            expect![[
+                r#"SELECT * FROM (SELECT "host", COUNT("status") AS "COUNT(prism.tenant.http_requests.status)" FROM "prism"."tenant"."http_requests" GROUP BY "host") AS "http_requests" WHERE sample(random(), 0.5)"#
+            ]],
+        );
+    }
+
+    #[test]
+    fn join() {
+        let lookup = scan().alias("other").unwrap().build().unwrap();
+        let plan = scan()
+            .join(lookup, JoinType::Left, (vec!["host"], vec!["host"]), None)
+            .unwrap()
+            .build()
+            .unwrap();
+        check(
+            plan,
+            expect![[
+                r#"SELECT * FROM "prism"."tenant"."http_requests" LEFT JOIN (SELECT * FROM "prism"."tenant"."http_requests") AS "other" ON "http_requests"."host" = "other"."host""#
+            ]],
+        );
+    }
+}
@@ -0,0 +1,42 @@
+use std::fmt;
+
+/// Presentation hints attached to a query by `render`. They do not change the results, only how a client shows them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Visualization {
+    pub kind: VisualizationKind,
+    pub title: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VisualizationKind {
+    Table,
+    /// A line chart of every numeric column against the first datetime column.
+    TimeChart,
+    /// A bar for each row, labelled by the first string column and sized by the first numeric column.
+    BarChart,
+}
+
+impl VisualizationKind {
+    pub fn from_name(name: &str) -> Option<VisualizationKind> {
+        match name {
+            "table" => Some(VisualizationKind::Table),
+            "timechart" => Some(VisualizationKind::TimeChart),
+            "barchart" => Some(VisualizationKind::BarChart),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            VisualizationKind::Table => "table",
+            VisualizationKind::TimeChart => "timechart",
+            VisualizationKind::BarChart => "barchart",
+        }
+    }
+}
+
+impl fmt::Display for VisualizationKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
@@ -0,0 +1,510 @@
+//! Runs the conformance corpus in `tests/pqltest`: files of PQL queries executed end to end against in-memory tables,
+//! in the spirit of sqllogictest.
+//!
+//! A `.pqltest` file is a sequence of records separated by blank lines. Lines starting with `#` between records are
+//! comments.
+//!
+//! A `table` record declares an in-memory table that the queries after it can read from. Its second line declares the
+//! columns as `name:type`, with the types of `datatable`, and each following line is a row. Values are separated by
+//! whitespace; a value containing whitespace is written in double quotes, and `NULL` is a null:
+//!
+//! ```text
+//! table http_requests
+//! timestamp:datetime host:string status:long
+//! 2023-11-01T00:00:00Z a.example 200
+//! 2023-11-01T00:01:00Z "b example" NULL
+//! ```
+//!
+//! A `query` record runs the query above the `----` line and compares each row of its results, with values separated by
+//! spaces, to the lines below it. With `query rowsort`, the rows are sorted before they are compared. A query that ends
+//! with `facet` prints a `name:` line before the rows of each of its result sets:
+//!
+//! ```text
+//! query rowsort
+//! http_requests | where status == 200 | distinct host
+//! ----
+//! a.example
+//! ```
+//!
+//! An `error` record expects the query below it to fail: `error parse` if it does not parse, `error execution` if it
+//! fails while running, or `error <code>` if lowering it raises a diagnostic with that code.
+//!
+//! Run with `UPDATE_EXPECT=1` to rewrite the expected rows of every `query` record to the actual ones.
+
+use std::{
+    collections::HashMap,
+    env, fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use chrono::{DateTime, TimeZone, Utc};
+use datafusion::{
+    arrow::{
+        array::Array,
+        datatypes::{DataType, Field, Schema, SchemaRef, TimeUnit},
+        record_batch::RecordBatch,
+        util::display::array_value_to_string,
+    },
+    datasource::{provider_as_source, MemTable},
+    execution::{context::SessionState, runtime_env::RuntimeEnv},
+    logical_expr::LogicalPlan,
+    prelude::{SessionConfig, SessionContext},
+    scalar::ScalarValue,
+};
+use prism_ql::QueryContext;
+
+/// The tenant that every query runs as.
+const TENANT: &str = "tenant";
+
+/// Separates a query from its expected rows.
+const SEPARATOR: &str = "----";
+
+struct PqlTestContext {
+    start_time: DateTime<Utc>,
+}
+
+impl QueryContext for PqlTestContext {
+    fn get_tenant_id(&self) -> &str {
+        TENANT
+    }
+
+    fn get_query_start_time(&self) -> DateTime<Utc> {
+        self.start_time
+    }
+}
+
+/// What a record expects of its query.
+enum Expected {
+    /// The rows of the results, and whether to sort them before comparing.
+    Rows { rows: Vec<String>, sort: bool },
+    /// The query does not parse.
+    ParseError,
+    /// The query fails while running.
+    ExecutionError,
+    /// Lowering the query raises a diagnostic with this code.
+    Diagnostic(String),
+}
+
+enum Record {
+    Table {
+        name: String,
+        schema: SchemaRef,
+        rows: Vec<Vec<String>>,
+    },
+    Query {
+        query: String,
+        expected: Expected,
+        /// The lines of the file holding the expected rows, if there are any, as a range of line indexes.
+        rows_at: (usize, usize),
+    },
+}
+
+/// A record along with the 1-based line of the file on which it begins.
+struct Located {
+    line: usize,
+    record: Record,
+}
+
+#[tokio::test]
+async fn pqltest() {
+    let update = env::var("UPDATE_EXPECT").is_ok();
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/pqltest");
+    let mut paths: Vec<PathBuf> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "pqltest"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty(), "no .pqltest files in {}", dir.display());
+
+    let mut failures = vec![];
+    for path in &paths {
+        failures.extend(run_file(path, update).await);
+    }
+
+    assert!(
+        failures.is_empty(),
+        "{} record(s) failed:\n\n{}",
+        failures.len(),
+        failures.join("\n\n")
+    );
+}
+
+/// Runs every record of a file, returning a message for each that failed. When updating, the expected rows of the file
+/// are rewritten instead of being compared.
+async fn run_file(path: &Path, update: bool) -> Vec<String> {
+    let name = path.file_name().unwrap().to_string_lossy().to_string();
+    let text = fs::read_to_string(path).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    let records = match parse_file(&lines) {
+        Ok(records) => records,
+        Err((line, message)) => return vec![format!("{}:{}: {}", name, line, message)],
+    };
+
+    let mut failures = vec![];
+    let mut tables: HashMap<String, Arc<MemTable>> = HashMap::new();
+    let mut updates: Vec<((usize, usize), Vec<String>)> = vec![];
+    for Located { line, record } in records {
+        match record {
+            Record::Table {
+                name: table,
+                schema,
+                rows,
+            } => match mem_table(schema, &rows) {
+                Ok(mem_table) => {
+                    tables.insert(table, Arc::new(mem_table));
+                }
+                Err(e) => failures.push(format!("{}:{}: {}", name, line, e)),
+            },
+            Record::Query {
+                query,
+                expected,
+                rows_at,
+            } => {
+                let result = run_query(&tables, &query).await;
+                if let (true, Expected::Rows { sort, .. }, Ok(rows)) = (update, &expected, &result)
+                {
+                    let mut rows = rows.clone();
+                    if *sort {
+                        rows.sort();
+                    }
+                    updates.push((rows_at, rows));
+                    continue;
+                }
+
+                if let Err(e) = check(&expected, result) {
+                    failures.push(format!("{}:{}: {}\n{}", name, line, e, query));
+                }
+            }
+        }
+    }
+
+    if update && !updates.is_empty() {
+        let mut lines: Vec<String> = lines.iter().map(|line| line.to_string()).collect();
+        for ((start, end), rows) in updates.into_iter().rev() {
+            lines.splice(start..end, rows);
+        }
+        fs::write(path, lines.join("\n") + "\n").unwrap();
+    }
+
+    failures
+}
+
+/// Splits a file into records, or returns the line and description of the first malformed one.
+fn parse_file(lines: &[&str]) -> Result<Vec<Located>, (usize, String)> {
+    let mut records = vec![];
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i].trim();
+        if line.is_empty() || line.starts_with('#') {
+            i += 1;
+            continue;
+        }
+
+        let start = i;
+        let mut end = i;
+        while end < lines.len() && !lines[end].trim().is_empty() {
+            end += 1;
+        }
+
+        let body = &lines[start + 1..end];
+        let record = parse_record(line, body, start, end).map_err(|e| (start + 1, e))?;
+        records.push(Located {
+            line: start + 1,
+            record,
+        });
+        i = end;
+    }
+
+    Ok(records)
+}
+
+/// Parses a record from its header, at line index `start`, and the lines below it, which end just before line index
+/// `end`.
+fn parse_record(header: &str, body: &[&str], start: usize, end: usize) -> Result<Record, String> {
+    let words: Vec<&str> = header.split_whitespace().collect();
+    match words.as_slice() {
+        ["table", name] => {
+            let Some((columns, rows)) = body.split_first() else {
+                return Err(format!("table `{}` declares no columns", name));
+            };
+
+            let schema = parse_schema(columns)?;
+            let rows = rows
+                .iter()
+                .map(|row| split_values(row))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(Record::Table {
+                name: name.to_string(),
+                schema,
+                rows,
+            })
+        }
+        ["query", mode @ ..] => {
+            let sort = match mode {
+                [] => false,
+                ["rowsort"] => true,
+                _ => return Err(format!("unknown query mode `{}`", mode.join(" "))),
+            };
+            let Some(separator) = body.iter().position(|line| line.trim() == SEPARATOR) else {
+                return Err(format!("query has no `{}` line", SEPARATOR));
+            };
+
+            // The expected rows follow the header, the query and the separator.
+            let rows_start = start + 1 + separator + 1;
+            Ok(Record::Query {
+                query: body[..separator].join("\n"),
+                expected: Expected::Rows {
+                    rows: body[separator + 1..]
+                        .iter()
+                        .map(|row| row.trim().to_string())
+                        .collect(),
+                    sort,
+                },
+                rows_at: (rows_start, end),
+            })
+        }
+        ["error", kind] => {
+            let expected = match *kind {
+                "parse" => Expected::ParseError,
+                "execution" => Expected::ExecutionError,
+                code => Expected::Diagnostic(code.to_string()),
+            };
+            Ok(Record::Query {
+                query: body.join("\n"),
+                expected,
+                rows_at: (end, end),
+            })
+        }
+        _ => Err(format!("unknown record `{}`", header)),
+    }
+}
+
+fn parse_schema(columns: &str) -> Result<SchemaRef, String> {
+    let fields = columns
+        .split_whitespace()
+        .map(|column| {
+            let (name, ty) = column
+                .split_once(':')
+                .ok_or_else(|| format!("expected `name:type`, got `{}`", column))?;
+            let ty = match ty {
+                "bool" => DataType::Boolean,
+                "int" => DataType::Int32,
+                "long" => DataType::Int64,
+                "real" | "double" => DataType::Float64,
+                "string" => DataType::Utf8,
+                "datetime" => DataType::Timestamp(TimeUnit::Millisecond, None),
+                _ => return Err(format!("unknown type `{}`", ty)),
+            };
+            Ok(Field::new(name, ty, true))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    Ok(Arc::new(Schema::new(fields)))
+}
+
+/// Splits a row into its values, keeping together a value in double quotes. A quoted `"NULL"` is the string itself.
+fn split_values(row: &str) -> Result<Vec<String>, String> {
+    let mut values = vec![];
+    let mut chars = row.trim().chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+
+        if c != '"' {
+            let mut value = String::new();
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                value.push(c);
+            }
+            values.push(value);
+            continue;
+        }
+
+        chars.next();
+        let mut value = String::from('"');
+        loop {
+            match chars.next() {
+                Some('"') => break,
+                Some(c) => value.push(c),
+                None => return Err(format!("unterminated string in row `{}`", row)),
+            }
+        }
+        values.push(value);
+    }
+
+    Ok(values)
+}
+
+fn mem_table(schema: SchemaRef, rows: &[Vec<String>]) -> Result<MemTable, String> {
+    let mut columns = vec![];
+    for (i, field) in schema.fields().iter().enumerate() {
+        let values = rows
+            .iter()
+            .map(|row| {
+                let value = row.get(i).ok_or_else(|| {
+                    format!(
+                        "row has {} value(s), expected {}",
+                        row.len(),
+                        schema.fields().len()
+                    )
+                })?;
+                parse_value(value, field.data_type())
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        let column = if values.is_empty() {
+            datafusion::arrow::array::new_empty_array(field.data_type())
+        } else {
+            ScalarValue::iter_to_array(values).map_err(|e| e.to_string())?
+        };
+        columns.push(column);
+    }
+
+    let batch = RecordBatch::try_new(schema.clone(), columns).map_err(|e| e.to_string())?;
+    MemTable::try_new(schema, vec![vec![batch]]).map_err(|e| e.to_string())
+}
+
+/// Parses a value of a row as the type of its column. Quoted values keep their leading quote, to tell them from `NULL`.
+fn parse_value(value: &str, ty: &DataType) -> Result<ScalarValue, String> {
+    if value == "NULL" {
+        return ScalarValue::try_from(ty).map_err(|e| e.to_string());
+    }
+
+    let value = value.strip_prefix('"').unwrap_or(value);
+    ScalarValue::try_from_string(value.to_string(), ty)
+        .map_err(|e| format!("invalid {} value `{}`: {}", ty, value, e))
+}
+
+/// The ways in which running a query can fail.
+enum Failure {
+    Parse(String),
+    /// The query reads from a table that the file does not declare.
+    UndeclaredTable(String),
+    Diagnostics(Vec<String>),
+    Execution(String),
+}
+
+/// Parses, lowers and executes a query, returning its rows formatted as lines.
+async fn run_query(
+    tables: &HashMap<String, Arc<MemTable>>,
+    input: &str,
+) -> Result<Vec<String>, Failure> {
+    let query = prism_ql::parse(input).map_err(|e| Failure::Parse(e.to_string()))?;
+    let Some(table) = tables.get(&query.table.name) else {
+        return Err(Failure::UndeclaredTable(query.table.name));
+    };
+
+    let ctx = Arc::new(PqlTestContext {
+        start_time: Utc.with_ymd_and_hms(2023, 11, 1, 12, 0, 0).unwrap(),
+    });
+    let lowered = prism_ql::lower(
+        query,
+        ctx,
+        provider_as_source(table.clone()),
+        input,
+        &HashMap::new(),
+    );
+    let Some(plan) = lowered.plan else {
+        let codes = lowered
+            .report
+            .diagnostics()
+            .iter()
+            .map(|diagnostic| diagnostic.code.clone().unwrap_or_default())
+            .collect();
+        return Err(Failure::Diagnostics(codes));
+    };
+
+    let state =
+        SessionState::new_with_config_rt(SessionConfig::new(), Arc::new(RuntimeEnv::default()))
+            .with_query_planner(prism_ql::query_planner());
+    let session = SessionContext::new_with_state(state);
+    session.register_udf(prism_ql::sample_udf().as_ref().clone());
+    for udwf in prism_ql::series_udwfs() {
+        session.register_udwf(udwf.as_ref().clone());
+    }
+
+    if lowered.facets.is_empty() {
+        return execute(&session, plan).await;
+    }
+
+    let mut rows = vec![];
+    for facet in lowered.facets {
+        rows.push(format!("{}:", facet.name));
+        rows.extend(execute(&session, facet.plan).await?);
+    }
+
+    Ok(rows)
+}
+
+async fn execute(session: &SessionContext, plan: LogicalPlan) -> Result<Vec<String>, Failure> {
+    let batches = async { session.execute_logical_plan(plan).await?.collect().await }
+        .await
+        .map_err(|e| Failure::Execution(e.to_string()))?;
+
+    let mut rows = vec![];
+    for batch in &batches {
+        for row in 0..batch.num_rows() {
+            let values = batch
+                .columns()
+                .iter()
+                .map(|column| format_value(column.as_ref(), row))
+                .collect::<Vec<_>>();
+            rows.push(values.join(" "));
+        }
+    }
+
+    Ok(rows)
+}
+
+fn format_value(column: &dyn Array, row: usize) -> String {
+    if column.is_null(row) {
+        return "NULL".to_string();
+    }
+
+    match array_value_to_string(column, row) {
+        Ok(value) if value.is_empty() => "(empty)".to_string(),
+        Ok(value) => value,
+        Err(e) => format!("<{}>", e),
+    }
+}
+
+/// Compares the outcome of a query with what its record expects.
+fn check(expected: &Expected, result: Result<Vec<String>, Failure>) -> Result<(), String> {
+    match (expected, result) {
+        (Expected::Rows { rows, sort }, Ok(mut actual)) => {
+            let mut expected = rows.clone();
+            if *sort {
+                expected.sort();
+                actual.sort();
+            }
+
+            if expected == actual {
+                return Ok(());
+            }
+
+            Err(format!(
+                "unexpected rows\nexpected:\n{}\nactual:\n{}",
+                expected.join("\n"),
+                actual.join("\n")
+            ))
+        }
+        (Expected::ParseError, Err(Failure::Parse(_)))
+        | (Expected::ExecutionError, Err(Failure::Execution(_))) => Ok(()),
+        (Expected::Diagnostic(code), Err(Failure::Diagnostics(codes))) if codes.contains(code) => {
+            Ok(())
+        }
+        (_, Ok(rows)) => Err(format!(
+            "expected the query to fail, but it returned {} row(s)",
+            rows.len()
+        )),
+        (_, Err(Failure::Parse(e))) => Err(format!("query does not parse: {}", e)),
+        (_, Err(Failure::UndeclaredTable(table))) => {
+            Err(format!("table `{}` is not declared", table))
+        }
+        (_, Err(Failure::Diagnostics(codes))) => {
+            Err(format!("query raised diagnostics [{}]", codes.join(", ")))
+        }
+        (_, Err(Failure::Execution(e))) => Err(format!("query failed: {}", e)),
+    }
+}
@@ -0,0 +1,166 @@
+//! Round trips lowered plans through Substrait and checks that the plans read back return the same rows.
+
+use std::{collections::HashMap, sync::Arc};
+
+use chrono::{DateTime, TimeZone, Utc};
+use datafusion::{
+    arrow::{
+        array::{Int64Array, StringArray, TimestampMillisecondArray},
+        datatypes::{DataType, Field, Schema, TimeUnit},
+        record_batch::RecordBatch,
+        util::pretty::pretty_format_batches,
+    },
+    datasource::{provider_as_source, MemTable, TableProvider},
+    execution::{context::SessionState, runtime_env::RuntimeEnv},
+    logical_expr::{LogicalPlan, LogicalPlanBuilder},
+    prelude::{SessionConfig, SessionContext},
+};
+use prism_ql::QueryContext;
+
+const TENANT: &str = "tenant";
+
+struct TestQueryContext;
+
+impl QueryContext for TestQueryContext {
+    fn get_tenant_id(&self) -> &str {
+        TENANT
+    }
+
+    fn get_query_start_time(&self) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2023, 11, 1, 12, 0, 0).unwrap()
+    }
+}
+
+fn http_requests() -> Arc<MemTable> {
+    let schema = Arc::new(Schema::new(vec![
+        Field::new(
+            "timestamp",
+            DataType::Timestamp(TimeUnit::Millisecond, None),
+            true,
+        ),
+        Field::new("host", DataType::Utf8, true),
+        Field::new("status", DataType::Int64, true),
+    ]));
+    let start = Utc
+        .with_ymd_and_hms(2023, 11, 1, 11, 0, 0)
+        .unwrap()
+        .timestamp_millis();
+    let batch = RecordBatch::try_new(
+        schema.clone(),
+        vec![
+            Arc::new(TimestampMillisecondArray::from(vec![
+                start,
+                start + 60_000,
+                start + 120_000,
+                start + 180_000,
+            ])),
+            Arc::new(StringArray::from(vec!["a", "b", "a", "c"])),
+            Arc::new(Int64Array::from(vec![200, 500, 503, 404])),
+        ],
+    )
+    .unwrap();
+    Arc::new(MemTable::try_new(schema, vec![vec![batch]]).unwrap())
+}
+
+/// A session that resolves `prism.tenant.http_requests` and can run and read back PQL plans.
+fn session() -> SessionContext {
+    let config = SessionConfig::new().with_default_catalog_and_schema("prism", TENANT);
+    let state = SessionState::new_with_config_rt(config, Arc::new(RuntimeEnv::default()))
+        .with_query_planner(prism_ql::query_planner())
+        .with_serializer_registry(prism_ql::serializer_registry());
+    let ctx = SessionContext::new_with_state(state);
+    ctx.register_table("http_requests", http_requests())
+        .unwrap();
+    ctx.register_udf(prism_ql::sample_udf().as_ref().clone());
+    ctx
+}
+
+fn lower(input: &str) -> LogicalPlan {
+    let query = prism_ql::parse(input).unwrap();
+    let lowered = prism_ql::lower(
+        query,
+        Arc::new(TestQueryContext),
+        provider_as_source(http_requests()),
+        input,
+        &HashMap::new(),
+    );
+    lowered.plan.unwrap()
+}
+
+async fn run(ctx: &SessionContext, plan: LogicalPlan) -> String {
+    let batches = ctx
+        .execute_logical_plan(plan)
+        .await
+        .unwrap()
+        .collect()
+        .await
+        .unwrap();
+    pretty_format_batches(&batches).unwrap().to_string()
+}
+
+async fn round_trip(plan: LogicalPlan) -> LogicalPlan {
+    let bytes = prism_ql::to_substrait(&plan).unwrap();
+    prism_ql::from_substrait(&mut session(), &bytes)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn round_trips_queries() {
+    let queries = [
+        "http_requests | where status >= 500 | sort by status | take 10",
+        "http_requests | summarize requests = count() by host | sort by host",
+        "http_requests | sort by timestamp desc | take 2",
+        "http_requests | extend failed = status >= 400 | where failed | count",
+    ];
+    let ctx = session();
+    for query in queries {
+        let plan = lower(query);
+        let expected = run(&ctx, plan.clone()).await;
+        let actual = run(&ctx, round_trip(plan).await).await;
+        assert_eq!(actual, expected, "{}", query);
+    }
+}
+
+#[tokio::test]
+async fn round_trips_samples() {
+    let ctx = session();
+    let plan = lower("http_requests | summarize count() by host | sample 100% | count");
+    let imported = round_trip(plan).await;
+    assert!(
+        format!("{}", imported.display_indent()).contains("Sample: sample(random(), Float64(1))"),
+        "{}",
+        imported.display_indent()
+    );
+    assert!(run(&ctx, imported).await.contains("| 3 "));
+
+    // A sample pushed into a scan is written above it, as a scan cannot hold it in Substrait.
+    let source = provider_as_source(http_requests() as Arc<dyn TableProvider>);
+    let scan = LogicalPlanBuilder::scan_with_filters(
+        "prism.tenant.http_requests",
+        source,
+        None,
+        vec![prism_ql::sample_filter(1.0)],
+    )
+    .unwrap()
+    .build()
+    .unwrap();
+    let imported = round_trip(scan).await;
+    let LogicalPlan::Extension(extension) = &imported else {
+        panic!("expected a sample, got {}", imported.display_indent());
+    };
+    assert_eq!(extension.node.name(), "Sample");
+    assert!(run(&ctx, imported).await.contains("| c    |"));
+}
+
+#[tokio::test]
+async fn rejects_pql_functions() {
+    let plan = lower("http_requests | reduce by host");
+    let e = prism_ql::to_substrait(&plan).unwrap_err();
+    assert!(
+        e.to_string()
+            .contains("Substrait plans cannot call `patterns` yet"),
+        "{}",
+        e
+    );
+}
@@ -0,0 +1,348 @@
+//! Terminal renderings of the visualizations that a PQL query asks for with `render`.
+
+use std::{collections::BTreeMap, fmt::Write};
+
+use anyhow::anyhow;
+use chrono::{TimeZone, Utc};
+use datafusion::arrow::{
+    array::{as_primitive_array, Array, ArrayRef},
+    compute::cast,
+    datatypes::{DataType, Float64Type, TimeUnit, TimestampNanosecondType},
+    record_batch::RecordBatch,
+    util::{display::array_value_to_string, pretty::pretty_format_batches},
+};
+use prism_ql::{Visualization, VisualizationKind};
+
+/// The number of characters that the plotting area of a chart spans horizontally.
+const CHART_WIDTH: usize = 60;
+
+/// The number of lines that the plotting area of a time chart spans vertically.
+const CHART_HEIGHT: usize = 12;
+
+/// The characters that mark the points of successive series in a time chart.
+const MARKERS: [char; 6] = ['●', '○', '◆', '◇', '▲', '△'];
+
+/// Bar characters filled in eighths, from one eighth to full.
+const BAR_EIGHTHS: [char; 8] = ['▏', '▎', '▍', '▌', '▋', '▊', '▉', '█'];
+
+/// Renders query results as the given visualization.
+pub fn render(batches: &[RecordBatch], visualization: &Visualization) -> anyhow::Result<String> {
+    let mut out = String::new();
+    if let Some(title) = &visualization.title {
+        writeln!(out, "{}", title)?;
+    }
+
+    match visualization.kind {
+        VisualizationKind::Table => writeln!(out, "{}", pretty_format_batches(batches)?)?,
+        VisualizationKind::TimeChart => time_chart(&mut out, batches)?,
+        VisualizationKind::BarChart => bar_chart(&mut out, batches)?,
+    }
+
+    Ok(out)
+}
+
+fn is_timestamp(ty: &DataType) -> bool {
+    matches!(ty, DataType::Timestamp(_, _))
+}
+
+fn as_f64s(array: &ArrayRef) -> anyhow::Result<Vec<Option<f64>>> {
+    let array = cast(array, &DataType::Float64)?;
+    Ok(as_primitive_array::<Float64Type>(&array).iter().collect())
+}
+
+/// Returns the instants of a timestamp column in nanoseconds since the epoch, regardless of its unit and time zone.
+fn as_nanos(array: &ArrayRef) -> anyhow::Result<Vec<Option<i64>>> {
+    let DataType::Timestamp(_, tz) = array.data_type() else {
+        return Err(anyhow!(
+            "expected a datetime column, got {}",
+            array.data_type()
+        ));
+    };
+
+    let array = cast(
+        array,
+        &DataType::Timestamp(TimeUnit::Nanosecond, tz.clone()),
+    )?;
+    Ok(as_primitive_array::<TimestampNanosecondType>(&array)
+        .iter()
+        .collect())
+}
+
+/// Formats a value for an axis or bar label, without a fractional part if it has none.
+fn format_value(value: f64) -> String {
+    if value.fract() == 0.0 && value.abs() < 1e15 {
+        format!("{}", value as i64)
+    } else {
+        format!("{:.2}", value)
+    }
+}
+
+fn format_instant(nanos: i64) -> String {
+    Utc.timestamp_nanos(nanos)
+        .format("%Y-%m-%d %H:%M:%S")
+        .to_string()
+}
+
+/// Draws every numeric column against the first datetime column. Rows are split into a separate series for each
+/// distinct combination of the remaining columns, such as the hosts of `count() by bin(timestamp, 1h), host`.
+fn time_chart(out: &mut String, batches: &[RecordBatch]) -> anyhow::Result<()> {
+    let mut series: BTreeMap<String, Vec<(i64, f64)>> = BTreeMap::new();
+    for batch in batches {
+        let schema = batch.schema();
+        let Some(x) = schema
+            .fields()
+            .iter()
+            .position(|field| is_timestamp(field.data_type()))
+        else {
+            return Err(anyhow!("a time chart needs a datetime column"));
+        };
+
+        let xs = as_nanos(batch.column(x))?;
+        let keys: Vec<usize> = (0..batch.num_columns())
+            .filter(|&i| i != x && !schema.field(i).data_type().is_numeric())
+            .collect();
+        for (i, field) in schema.fields().iter().enumerate() {
+            if !field.data_type().is_numeric() {
+                continue;
+            }
+
+            let ys = as_f64s(batch.column(i))?;
+            for row in 0..batch.num_rows() {
+                let (Some(x), Some(y)) = (xs[row], ys[row]) else {
+                    continue;
+                };
+
+                let mut name = field.name().clone();
+                for &key in &keys {
+                    write!(name, " {}", array_value_to_string(batch.column(key), row)?)?;
+                }
+
+                series.entry(name).or_default().push((x, y));
+            }
+        }
+    }
+
+    let points = || series.values().flatten();
+    let Some(x_min) = points().map(|(x, _)| *x).min() else {
+        writeln!(out, "(no data)")?;
+        return Ok(());
+    };
+
+    let x_max = points().map(|(x, _)| *x).max().unwrap_or(x_min);
+    let y_min = points().map(|(_, y)| *y).fold(f64::INFINITY, f64::min);
+    let y_max = points().map(|(_, y)| *y).fold(f64::NEG_INFINITY, f64::max);
+    let column = |x: i64| {
+        if x_max == x_min {
+            return 0;
+        }
+
+        ((x - x_min) as f64 / (x_max - x_min) as f64 * (CHART_WIDTH - 1) as f64).round() as usize
+    };
+    let line = |y: f64| {
+        if y_max == y_min {
+            return CHART_HEIGHT / 2;
+        }
+
+        ((y_max - y) / (y_max - y_min) * (CHART_HEIGHT - 1) as f64).round() as usize
+    };
+
+    let mut grid = vec![vec![' '; CHART_WIDTH]; CHART_HEIGHT];
+    for (points, marker) in series.values().zip(MARKERS.iter().cycle()) {
+        for (x, y) in points {
+            grid[line(*y)][column(*x)] = *marker;
+        }
+    }
+
+    let (top, bottom) = (format_value(y_max), format_value(y_min));
+    let label_width = top.len().max(bottom.len());
+    for (i, cells) in grid.iter().enumerate() {
+        let (label, axis) = if i == 0 {
+            (top.as_str(), '┤')
+        } else if i == CHART_HEIGHT - 1 {
+            (bottom.as_str(), '┤')
+        } else {
+            ("", '│')
+        };
+        let cells: String = cells.iter().collect();
+        writeln!(
+            out,
+            "{:>width$} {}{}",
+            label,
+            axis,
+            cells.trim_end(),
+            width = label_width
+        )?;
+    }
+
+    writeln!(
+        out,
+        "{:>width$} └{}",
+        "",
+        "─".repeat(CHART_WIDTH),
+        width = label_width
+    )?;
+    let (start, end) = (format_instant(x_min), format_instant(x_max));
+    if x_max == x_min {
+        writeln!(out, "{:>width$}  {}", "", start, width = label_width)?;
+    } else {
+        writeln!(
+            out,
+            "{:>width$}  {}{:>gap$}",
+            "",
+            start,
+            end,
+            width = label_width,
+            gap = CHART_WIDTH.saturating_sub(start.len()).max(end.len() + 1)
+        )?;
+    }
+
+    for (name, marker) in series.keys().zip(MARKERS.iter().cycle()) {
+        writeln!(out, "{} {}", marker, name)?;
+    }
+
+    Ok(())
+}
+
+/// Draws a bar for each row, labelled by the first string column (or the row number, if there is none) and sized by
+/// the first numeric column.
+fn bar_chart(out: &mut String, batches: &[RecordBatch]) -> anyhow::Result<()> {
+    let mut bars: Vec<(String, f64)> = vec![];
+    for batch in batches {
+        let schema = batch.schema();
+        let Some(value) = schema
+            .fields()
+            .iter()
+            .position(|field| field.data_type().is_numeric())
+        else {
+            return Err(anyhow!("a bar chart needs a numeric column"));
+        };
+
+        let label = schema
+            .fields()
+            .iter()
+            .position(|field| matches!(field.data_type(), DataType::Utf8 | DataType::LargeUtf8));
+        let values = as_f64s(batch.column(value))?;
+        for (row, value) in values.into_iter().enumerate() {
+            let label = match label {
+                Some(label) => array_value_to_string(batch.column(label), row)?,
+                None => (bars.len() + 1).to_string(),
+            };
+            bars.push((label, value.unwrap_or(0.0)));
+        }
+    }
+
+    if bars.is_empty() {
+        writeln!(out, "(no data)")?;
+        return Ok(());
+    }
+
+    let label_width = bars
+        .iter()
+        .map(|(label, _)| label.chars().count())
+        .max()
+        .unwrap_or(0);
+    let max = bars.iter().map(|(_, value)| *value).fold(0.0, f64::max);
+    for (label, value) in &bars {
+        let eighths = if max > 0.0 {
+            (value.max(0.0) / max * (CHART_WIDTH * 8) as f64).round() as usize
+        } else {
+            0
+        };
+        let mut bar = BAR_EIGHTHS[7].to_string().repeat(eighths / 8);
+        if eighths % 8 > 0 {
+            bar.push(BAR_EIGHTHS[eighths % 8 - 1]);
+        }
+
+        writeln!(
+            out,
+            "{:>width$} │{} {}",
+            label,
+            bar,
+            format_value(*value),
+            width = label_width
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use datafusion::arrow::{
+        array::{ArrayRef, Int64Array, StringArray, TimestampMillisecondArray},
+        record_batch::RecordBatch,
+    };
+    use expect_test::{expect, Expect};
+    use prism_ql::{Visualization, VisualizationKind};
+
+    use super::render;
+
+    fn check(kind: VisualizationKind, columns: Vec<(&str, ArrayRef)>, expect: Expect) {
+        let batch = RecordBatch::try_from_iter(columns).unwrap();
+        let visualization = Visualization { kind, title: None };
+        expect.assert_eq(&render(&[batch], &visualization).unwrap());
+    }
+
+    #[test]
+    fn bar_chart() {
+        check(
+            VisualizationKind::BarChart,
+            vec![
+                (
+                    "host",
+                    Arc::new(StringArray::from(vec!["a.example", "b.example", "c"])),
+                ),
+                ("count", Arc::new(Int64Array::from(vec![40, 7, 0]))),
+            ],
+            expect![[r#"
+                a.example │████████████████████████████████████████████████████████████ 40
+                b.example │██████████▌ 7
+                        c │ 0
+            "#]],
+        );
+    }
+
+    #[test]
+    fn time_chart() {
+        check(
+            VisualizationKind::TimeChart,
+            vec![
+                (
+                    "timestamp",
+                    Arc::new(TimestampMillisecondArray::from(vec![
+                        1698796800000,
+                        1698800400000,
+                        1698804000000,
+                        1698796800000,
+                        1698804000000,
+                    ])),
+                ),
+                (
+                    "host",
+                    Arc::new(StringArray::from(vec!["a", "a", "a", "b", "b"])),
+                ),
+                ("count", Arc::new(Int64Array::from(vec![10, 30, 20, 0, 5]))),
+            ],
+            expect![[r#"
+                30 ┤                              ●
+                   │
+                   │
+                   │
+                   │                                                           ●
+                   │
+                   │
+                   │●
+                   │
+                   │                                                           ○
+                   │
+                 0 ┤○
+                   └────────────────────────────────────────────────────────────
+                    2023-11-01 00:00:00                      2023-11-01 02:00:00
+                ● count a
+                ○ count b
+            "#]],
+        );
+    }
+}
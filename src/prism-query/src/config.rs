@@ -3,6 +3,8 @@
 //! The querier component needs to be aware of configuration for the meta service that it talks to and the S3 buckets
 //! that contain the data that the querier intends to reference.
 
+use std::collections::HashMap;
+
 use config::{Config, File};
 use serde::{Deserialize, Serialize};
 
@@ -28,6 +30,33 @@ pub struct S3Config {
     pub bucket_name: String,
 }
 
+/// The most that a single query may scan. A query whose estimated scan exceeds either limit is rejected before it runs.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ScanBudget {
+    /// The most bytes of partitions that a query may read, if limited.
+    pub max_bytes: Option<u64>,
+    /// The most partitions that a query may read, if limited.
+    pub max_partitions: Option<u64>,
+}
+
+/// Configuration pertaining to scan budgets.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BudgetConfig {
+    /// The budget of tenants that have none of their own. Queries are unlimited if this is absent.
+    #[serde(default)]
+    pub default: Option<ScanBudget>,
+    /// Budgets for particular tenants, by tenant ID.
+    #[serde(default)]
+    pub tenants: HashMap<String, ScanBudget>,
+}
+
+impl BudgetConfig {
+    /// Returns the budget that applies to the given tenant, if any.
+    pub fn for_tenant(&self, tenant: &str) -> Option<&ScanBudget> {
+        self.tenants.get(tenant).or(self.default.as_ref())
+    }
+}
+
 /// Configuration for the querier.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct QueryConfig {
@@ -35,6 +64,9 @@ pub struct QueryConfig {
     pub meta: MetaConfig,
     /// S3 configuration.
     pub s3: S3Config,
+    /// Scan budget configuration.
+    #[serde(default)]
+    pub budget: BudgetConfig,
 }
 
 /// Reads configuration from the given path.
@@ -0,0 +1,381 @@
+//! Estimates of how much data a query will scan, made before it runs from the partitions that Meta lists for each table
+//! that its plan reads.
+
+use std::{collections::BTreeMap, fmt};
+
+use anyhow::anyhow;
+use datafusion::{
+    datasource::source_as_provider,
+    logical_expr::{LogicalPlan, TableScan},
+    prelude::SessionContext,
+};
+
+use crate::{
+    config::{BudgetConfig, ScanBudget},
+    providers::table_provider::{sampled_fraction, time_range, PrismTableProvider},
+};
+
+/// The units in which sizes are shown, each 1024 times the last.
+const BYTE_UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+/// How much of a table a single scan in a plan is expected to read.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TableEstimate {
+    pub tenant: String,
+    pub table: String,
+    pub partitions: u64,
+    pub bytes: u64,
+}
+
+/// How much data a plan is expected to read, table by table.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ScanEstimate {
+    pub tables: Vec<TableEstimate>,
+}
+
+impl ScanEstimate {
+    /// The partitions and bytes that the plan is expected to read from the tables of each tenant.
+    fn by_tenant(&self) -> BTreeMap<&str, (u64, u64)> {
+        let mut totals: BTreeMap<&str, (u64, u64)> = BTreeMap::new();
+        for table in &self.tables {
+            let total = totals.entry(&table.tenant).or_default();
+            total.0 += table.partitions;
+            total.1 += table.bytes;
+        }
+
+        totals
+    }
+
+    /// Returns an error if the plan is expected to read more from the tables of any tenant than that tenant's budget
+    /// allows.
+    pub fn check(&self, budgets: &BudgetConfig) -> anyhow::Result<()> {
+        for (tenant, (partitions, bytes)) in self.by_tenant() {
+            let Some(budget) = budgets.for_tenant(tenant) else {
+                continue;
+            };
+
+            if exceeds(budget, partitions, bytes) {
+                return Err(anyhow!(
+                    "query would scan an estimated {} in {} partitions of tenant `{}`, over its budget of {}; narrow \
+                     it down with `where` or `sample` and try again",
+                    format_bytes(bytes),
+                    partitions,
+                    tenant,
+                    format_budget(budget)
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Describes the estimate, along with the budget of each tenant that it reads from and whether it fits within it.
+    pub fn explain(&self, budgets: &BudgetConfig) -> String {
+        let mut out = self.to_string();
+        for (tenant, (partitions, bytes)) in self.by_tenant() {
+            let Some(budget) = budgets.for_tenant(tenant) else {
+                out.push_str(&format!("Budget of tenant `{}`: unlimited\n", tenant));
+                continue;
+            };
+
+            let verdict = if exceeds(budget, partitions, bytes) {
+                "exceeded"
+            } else {
+                "within budget"
+            };
+            out.push_str(&format!(
+                "Budget of tenant `{}`: {} ({})\n",
+                tenant,
+                format_budget(budget),
+                verdict
+            ));
+        }
+
+        out
+    }
+}
+
+impl fmt::Display for ScanEstimate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Estimated scan:")?;
+        for table in &self.tables {
+            writeln!(
+                f,
+                "  prism.{}.{}: {} partitions, {}",
+                table.tenant,
+                table.table,
+                table.partitions,
+                format_bytes(table.bytes)
+            )?;
+        }
+
+        let partitions: u64 = self.tables.iter().map(|table| table.partitions).sum();
+        let bytes: u64 = self.tables.iter().map(|table| table.bytes).sum();
+        writeln!(
+            f,
+            "Total: {} partitions, {}",
+            partitions,
+            format_bytes(bytes)
+        )
+    }
+}
+
+fn exceeds(budget: &ScanBudget, partitions: u64, bytes: u64) -> bool {
+    budget.max_bytes.is_some_and(|max| bytes > max)
+        || budget.max_partitions.is_some_and(|max| partitions > max)
+}
+
+fn format_budget(budget: &ScanBudget) -> String {
+    let bytes = budget
+        .max_bytes
+        .map_or("unlimited bytes".to_string(), format_bytes);
+    let partitions = budget
+        .max_partitions
+        .map_or("unlimited partitions".to_string(), |max| {
+            format!("{} partitions", max)
+        });
+    format!("{} and {}", bytes, partitions)
+}
+
+/// Formats a size in the largest unit in which it is at least one, such as `1.5 GiB`.
+fn format_bytes(bytes: u64) -> String {
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < BYTE_UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} {}", bytes, BYTE_UNITS[0])
+    } else {
+        format!("{:.1} {}", size, BYTE_UNITS[unit])
+    }
+}
+
+/// Estimates how much data a plan will scan. The plan is optimized first, so that samples and filters on time are pushed
+/// down to the scans that they thin out, as they are when the plan runs. Only Prism tables are counted; tables held in
+/// memory, such as those of a `datatable`, read nothing from storage.
+pub async fn estimate(ctx: &SessionContext, plan: &LogicalPlan) -> anyhow::Result<ScanEstimate> {
+    let plan = ctx.state().optimize(plan)?;
+    let mut scans = vec![];
+    collect_scans(&plan, &mut scans);
+
+    let mut estimate = ScanEstimate::default();
+    for scan in scans {
+        let provider = source_as_provider(&scan.source)?;
+        let Some(provider) = provider.as_any().downcast_ref::<PrismTableProvider>() else {
+            continue;
+        };
+
+        let partitions = provider.partitions(time_range(&scan.filters)).await?;
+        let fraction = sampled_fraction(&scan.filters);
+        let bytes: i64 = partitions.iter().map(|partition| partition.size).sum();
+        estimate.tables.push(TableEstimate {
+            tenant: provider.tenant().to_string(),
+            table: provider.table().to_string(),
+            partitions: (partitions.len() as f64 * fraction).round() as u64,
+            bytes: (bytes.max(0) as f64 * fraction).round() as u64,
+        });
+    }
+
+    Ok(estimate)
+}
+
+/// Returns the query that follows `EXPLAIN ESTIMATE`, in any case, if the input begins with it.
+pub fn explain_estimate(input: &str) -> Option<&str> {
+    let rest = strip_keyword(input.trim_start(), "explain")?;
+    strip_keyword(rest, "estimate")
+}
+
+fn strip_keyword<'a>(input: &'a str, keyword: &str) -> Option<&'a str> {
+    let head = input.get(..keyword.len())?;
+    let rest = &input[keyword.len()..];
+    (head.eq_ignore_ascii_case(keyword) && rest.starts_with(char::is_whitespace))
+        .then(|| rest.trim_start())
+}
+
+fn collect_scans<'a>(plan: &'a LogicalPlan, scans: &mut Vec<&'a TableScan>) {
+    if let LogicalPlan::TableScan(scan) = plan {
+        scans.push(scan);
+    }
+
+    for input in plan.inputs() {
+        collect_scans(input, scans);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use datafusion::{
+        arrow::datatypes::{DataType, Field, Schema, TimeUnit},
+        datasource::provider_as_source,
+        logical_expr::{expr_fn, lit, LogicalPlan, LogicalPlanBuilder},
+        prelude::SessionContext,
+        scalar::ScalarValue,
+    };
+    use expect_test::{expect, Expect};
+    use prism_common_v1::{Partition, TimeRange};
+
+    use super::{estimate, explain_estimate, ScanEstimate};
+    use crate::{
+        config::{BudgetConfig, S3Config, ScanBudget},
+        meta::fake::FakeMeta,
+        providers::table_provider::{FooterCache, PrismTableProvider},
+    };
+
+    /// The start of the first partition of a table, in milliseconds.
+    const START: i64 = 1698796800000;
+
+    const HOUR: i64 = 3_600_000;
+
+    /// Plans a scan of a table of the given tenant with 40 partitions of 512 MiB each, one for each hour from
+    /// [`START`], passing it through `build`.
+    async fn estimate_of(
+        tenant: &str,
+        build: impl FnOnce(LogicalPlanBuilder) -> LogicalPlanBuilder,
+    ) -> ScanEstimate {
+        let meta = FakeMeta {
+            partitions: (0..40)
+                .map(|i| Partition {
+                    name: format!("{}/http_requests/{}.parquet", tenant, i),
+                    size: 512 << 20,
+                    time_range: Some(TimeRange {
+                        start_time: START + i * HOUR,
+                        end_time: START + (i + 1) * HOUR - 1,
+                    }),
+                })
+                .collect(),
+        };
+        let schema = Schema::new(vec![
+            Field::new(
+                "timestamp",
+                DataType::Timestamp(TimeUnit::Millisecond, None),
+                true,
+            ),
+            Field::new("referer", DataType::Utf8, true),
+        ]);
+        let provider = PrismTableProvider::new(
+            Arc::new(schema),
+            tenant,
+            "http_requests",
+            Arc::new(meta),
+            S3Config {
+                endpoint: None,
+                bucket_name: "query".to_string(),
+            },
+            FooterCache::default(),
+        );
+        let scan = LogicalPlanBuilder::scan(
+            format!("prism.{}.http_requests", tenant),
+            provider_as_source(Arc::new(provider)),
+            None,
+        )
+        .unwrap();
+        let plan: LogicalPlan = build(scan).build().unwrap();
+        estimate(&SessionContext::new(), &plan).await.unwrap()
+    }
+
+    fn budgets() -> BudgetConfig {
+        BudgetConfig {
+            default: Some(ScanBudget {
+                max_bytes: Some(16 << 30),
+                max_partitions: None,
+            }),
+            tenants: [(
+                "big".to_string(),
+                ScanBudget {
+                    max_bytes: None,
+                    max_partitions: Some(1000),
+                },
+            )]
+            .into(),
+        }
+    }
+
+    fn check(estimate: &ScanEstimate, expect: Expect) {
+        let mut out = estimate.explain(&budgets());
+        if let Err(e) = estimate.check(&budgets()) {
+            out.push_str(&format!("error: {}\n", e));
+        }
+
+        expect.assert_eq(&out);
+    }
+
+    #[tokio::test]
+    async fn over_budget() {
+        check(
+            &estimate_of("acme", |scan| scan).await,
+            expect![[r#"
+                Estimated scan:
+                  prism.acme.http_requests: 40 partitions, 20.0 GiB
+                Total: 40 partitions, 20.0 GiB
+                Budget of tenant `acme`: 16.0 GiB and unlimited partitions (exceeded)
+                error: query would scan an estimated 20.0 GiB in 40 partitions of tenant `acme`, over its budget of 16.0 GiB and unlimited partitions; narrow it down with `where` or `sample` and try again
+            "#]],
+        );
+    }
+
+    #[tokio::test]
+    async fn tenant_budget() {
+        check(
+            &estimate_of("big", |scan| scan).await,
+            expect![[r#"
+                Estimated scan:
+                  prism.big.http_requests: 40 partitions, 20.0 GiB
+                Total: 40 partitions, 20.0 GiB
+                Budget of tenant `big`: unlimited bytes and 1000 partitions (within budget)
+            "#]],
+        );
+    }
+
+    #[tokio::test]
+    async fn sampled() {
+        check(
+            &estimate_of("acme", |scan| {
+                scan.filter(prism_ql::sample_filter(0.25)).unwrap()
+            })
+            .await,
+            expect![[r#"
+                Estimated scan:
+                  prism.acme.http_requests: 10 partitions, 5.0 GiB
+                Total: 10 partitions, 5.0 GiB
+                Budget of tenant `acme`: 16.0 GiB and unlimited partitions (within budget)
+            "#]],
+        );
+    }
+
+    #[tokio::test]
+    async fn filtered_by_time() {
+        check(
+            &estimate_of("acme", |scan| {
+                let since = ScalarValue::TimestampMillisecond(Some(START + 30 * HOUR + 1), None);
+                scan.filter(expr_fn::col("timestamp").gt(lit(since)))
+                    .unwrap()
+            })
+            .await,
+            expect![[r#"
+                Estimated scan:
+                  prism.acme.http_requests: 10 partitions, 5.0 GiB
+                Total: 10 partitions, 5.0 GiB
+                Budget of tenant `acme`: 16.0 GiB and unlimited partitions (within budget)
+            "#]],
+        );
+    }
+
+    #[test]
+    fn explain_estimate_prefix() {
+        assert_eq!(
+            explain_estimate("EXPLAIN ESTIMATE SELECT 1"),
+            Some("SELECT 1")
+        );
+        assert_eq!(
+            explain_estimate("explain  estimate\nhttp_requests | count"),
+            Some("http_requests | count")
+        );
+        assert_eq!(explain_estimate("explain http_requests | count"), None);
+        assert_eq!(explain_estimate("explain estimates | count"), None);
+    }
+}
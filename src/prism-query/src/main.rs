@@ -1,12 +1,19 @@
 use std::{
+    fs,
     io::{self, BufRead, Write},
+    path::{Path, PathBuf},
     sync::Arc,
     time::Instant,
 };
 
-use anyhow::Context;
-use clap::Parser;
-use datafusion::prelude::SessionContext;
+use anyhow::{anyhow, Context};
+use clap::{Parser, ValueEnum};
+use datafusion::{
+    arrow::{record_batch::RecordBatch, util::pretty::pretty_format_batches},
+    execution::{context::SessionState, runtime_env::RuntimeEnv},
+    logical_expr::LogicalPlan,
+    prelude::{SessionConfig, SessionContext},
+};
 use envconfig::Envconfig;
 use meta::provider::DirectMetaClientProvider;
 use object_store::aws::AmazonS3Builder;
@@ -14,10 +21,13 @@ use tracing::Level;
 use tracing_subscriber::prelude::*;
 use url::Url;
 
-use crate::providers::catalog_provider::PrismCatalogProvider;
+use crate::{config::BudgetConfig, providers::catalog_provider::PrismCatalogProvider};
 
+mod chart;
 mod config;
+mod estimate;
 mod meta;
+mod pql;
 mod providers;
 
 #[derive(Debug, Parser)]
@@ -25,6 +35,37 @@ struct Args {
     /// If present, run the given SQL query and exit.
     #[arg(short, long)]
     sql: Option<String>,
+
+    /// If present, run the given PQL query and exit.
+    #[arg(long)]
+    pql: Option<String>,
+
+    /// If present, write the plan of the PQL query given with `--pql` to this file as Substrait instead of running it.
+    #[arg(long, value_name = "PATH", requires = "pql")]
+    emit_substrait: Option<PathBuf>,
+
+    /// If present, run the Substrait plan in this file, such as one written by `--emit-substrait`, and exit. The plan
+    /// may read only the tables of the tenant given with `--tenant`.
+    #[arg(long, value_name = "PATH")]
+    substrait: Option<PathBuf>,
+
+    /// The language of the queries entered at the prompt.
+    #[arg(long, value_enum, default_value_t = Language::Sql)]
+    language: Language,
+
+    /// The tenant whose tables PQL queries read from.
+    #[arg(long)]
+    tenant: Option<String>,
+
+    /// If present, print the explanation of the given PQL diagnostic code, such as `E0001`, and exit.
+    #[arg(long, value_name = "CODE")]
+    explain: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Language {
+    Sql,
+    Pql,
 }
 
 #[derive(Envconfig)]
@@ -60,6 +101,13 @@ async fn main() {
 
 async fn repl() -> anyhow::Result<()> {
     let args = Args::parse();
+    if let Some(code) = args.explain {
+        let explanation = prism_ql::explain(&code)
+            .ok_or_else(|| anyhow::anyhow!("no explanation for code `{}`", code))?;
+        print!("{}", explanation);
+        return Ok(());
+    }
+
     let env_config = Config::init_from_env()?;
     let config = config::get_config(&env_config.config_path).context("reading config from file")?;
     let store = {
@@ -76,23 +124,53 @@ async fn repl() -> anyhow::Result<()> {
 
         builder.build()?
     };
+    let budgets = config.budget.clone();
     let client_provider = Arc::new(DirectMetaClientProvider::new(config.meta.clone()));
     let s3_url = Url::parse(&format!("s3://{}", &config.s3.bucket_name))?;
     let catalog = PrismCatalogProvider::new(client_provider, config.s3.clone());
-    let ctx = SessionContext::new();
+    let state =
+        SessionState::new_with_config_rt(SessionConfig::new(), Arc::new(RuntimeEnv::default()))
+            .with_query_planner(prism_ql::query_planner())
+            .with_serializer_registry(prism_ql::serializer_registry());
+    let ctx = SessionContext::new_with_state(state);
     ctx.register_catalog("prism", Arc::new(catalog));
+    for udwf in prism_ql::series_udwfs() {
+        ctx.register_udwf(udwf.as_ref().clone());
+    }
+    ctx.register_udf(prism_ql::local_bin_udf().as_ref().clone());
+    ctx.register_udf(prism_ql::sample_udf().as_ref().clone());
     ctx.runtime_env()
         .register_object_store(&s3_url, Arc::new(store));
     let mut stdin = io::stdin().lock();
     let mut stdout = io::stdout().lock();
     if let Some(sql) = args.sql {
-        let df = ctx.sql(&sql).await?;
-        df.show().await?;
+        run_sql(&ctx, &budgets, &sql).await?;
+        return Ok(());
+    }
+
+    if let Some(pql) = args.pql {
+        match &args.emit_substrait {
+            Some(path) => emit_substrait(&ctx, tenant(&args.tenant)?, &pql, path).await?,
+            None => run_pql(&ctx, &budgets, tenant(&args.tenant)?, &pql).await?,
+        }
+        return Ok(());
+    }
+
+    if let Some(path) = args.substrait {
+        run_substrait(&ctx, &budgets, tenant(&args.tenant)?, &path).await?;
         return Ok(());
     }
 
+    // Every PQL query entered at the prompt runs as the same tenant, so a missing one is reported before the first.
+    let pql_tenant = match args.language {
+        Language::Sql => None,
+        Language::Pql => Some(tenant(&args.tenant)?),
+    };
     loop {
-        write!(&mut stdout, "prism> ")?;
+        match args.language {
+            Language::Sql => write!(&mut stdout, "prism> ")?,
+            Language::Pql => write!(&mut stdout, "pql> ")?,
+        }
         stdout.flush()?;
         let mut buf = String::new();
         stdin.read_line(&mut buf)?;
@@ -102,17 +180,19 @@ async fn repl() -> anyhow::Result<()> {
         }
 
         let start = Instant::now();
-        let df = match ctx.sql(buf).await {
-            Ok(df) => df,
-            Err(e) => {
-                writeln!(&mut stdout, "sql error: {}", e)?;
-                continue;
+        match pql_tenant {
+            None => {
+                if let Err(e) = run_sql(&ctx, &budgets, buf).await {
+                    writeln!(&mut stdout, "sql error: {}", e)?;
+                    continue;
+                }
+            }
+            Some(tenant) => {
+                if let Err(e) = run_pql(&ctx, &budgets, tenant, buf).await {
+                    writeln!(&mut stdout, "pql error: {}", e)?;
+                    continue;
+                }
             }
-        };
-
-        if let Err(e) = df.show().await {
-            writeln!(&mut stdout, "show error: {}", e)?;
-            continue;
         }
 
         let end = Instant::now();
@@ -121,3 +201,105 @@ async fn repl() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+fn tenant(tenant: &Option<String>) -> anyhow::Result<&str> {
+    tenant
+        .as_deref()
+        .ok_or_else(|| anyhow::anyhow!("PQL queries need a tenant; pass one with --tenant"))
+}
+
+async fn execute(
+    ctx: &SessionContext,
+    plan: LogicalPlan,
+) -> datafusion::error::Result<Vec<RecordBatch>> {
+    ctx.execute_logical_plan(plan).await?.collect().await
+}
+
+/// Runs a SQL query and prints its results, unless it scans more than the budget of a tenant whose tables it reads
+/// allows. A query that begins with `EXPLAIN ESTIMATE` prints how much it would scan instead.
+async fn run_sql(ctx: &SessionContext, budgets: &BudgetConfig, input: &str) -> anyhow::Result<()> {
+    let explain = estimate::explain_estimate(input);
+    let df = ctx.sql(explain.unwrap_or(input)).await?;
+    let estimate = estimate::estimate(ctx, df.logical_plan()).await?;
+    if explain.is_some() {
+        print!("{}", estimate.explain(budgets));
+        return Ok(());
+    }
+
+    estimate.check(budgets)?;
+    df.show().await?;
+    Ok(())
+}
+
+/// Runs a PQL query and prints each of its result sets in turn, as a chart if the query asks for one with `render` and
+/// as a table otherwise. A query that begins with `explain` prints its plans instead, and one that begins with
+/// `explain estimate` prints how much it would scan. Like SQL queries, PQL queries are rejected if they would scan more
+/// than the tenant's budget allows.
+async fn run_pql(
+    ctx: &SessionContext,
+    budgets: &BudgetConfig,
+    tenant: &str,
+    input: &str,
+) -> anyhow::Result<()> {
+    let explain = estimate::explain_estimate(input);
+    let query = pql::compile(ctx, tenant, explain.unwrap_or(input)).await?;
+    if query.explain && explain.is_none() {
+        print!("{}", pql::explain(ctx, query).await?);
+        return Ok(());
+    }
+
+    let estimate = pql::estimate(ctx, &query).await?;
+    if explain.is_some() {
+        print!("{}", estimate.explain(budgets));
+        return Ok(());
+    }
+
+    estimate.check(budgets)?;
+    for (name, plan) in query.result_sets() {
+        if let Some(name) = name {
+            println!("{}:", name);
+        }
+
+        let batches = match execute(ctx, plan.clone()).await {
+            Ok(batches) => batches,
+            Err(e) => {
+                query.report_execution_error(&plan, &e)?;
+                return Err(anyhow!("query failed"));
+            }
+        };
+
+        match &query.visualization {
+            Some(visualization) => print!("{}", chart::render(&batches, visualization)?),
+            None => println!("{}", pretty_format_batches(&batches)?),
+        }
+    }
+
+    Ok(())
+}
+
+/// Compiles a PQL query and writes its plan to a file as Substrait.
+async fn emit_substrait(
+    ctx: &SessionContext,
+    tenant: &str,
+    input: &str,
+    path: &Path,
+) -> anyhow::Result<()> {
+    let query = pql::compile(ctx, tenant, input).await?;
+    fs::write(path, pql::export(&query)?)
+        .with_context(|| format!("writing plan to {}", path.display()))?;
+    Ok(())
+}
+
+/// Runs a Substrait plan read from a file and prints its results, unless it scans more than the tenant's budget allows.
+async fn run_substrait(
+    ctx: &SessionContext,
+    budgets: &BudgetConfig,
+    tenant: &str,
+    path: &Path,
+) -> anyhow::Result<()> {
+    let bytes = fs::read(path).with_context(|| format!("reading plan from {}", path.display()))?;
+    let plan = pql::import(ctx, tenant, &bytes).await?;
+    estimate::estimate(ctx, &plan).await?.check(budgets)?;
+    println!("{}", pretty_format_batches(&execute(ctx, plan).await?)?);
+    Ok(())
+}
@@ -1,2 +1,4 @@
 pub mod client;
+#[cfg(test)]
+pub mod fake;
 pub mod provider;
@@ -0,0 +1,64 @@
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+
+use crate::meta::{client::MetaClient, provider::MetaClientProvider};
+use prism_common_v1::Partition;
+use prism_rpc_meta_v1::{
+    GetTablePartitionsRequest, GetTablePartitionsResponse, GetTableSchemaRequest,
+    GetTableSchemaResponse,
+};
+
+/// A Meta for tests that lists the same partitions for every table. Partitions without a time range are listed for
+/// every time range.
+pub struct FakeMeta {
+    pub partitions: Vec<Partition>,
+}
+
+#[async_trait]
+impl MetaClient for FakeMeta {
+    async fn get_table_schema(
+        &self,
+        request: GetTableSchemaRequest,
+    ) -> anyhow::Result<GetTableSchemaResponse> {
+        Err(anyhow!(
+            "the fake Meta does not know the schema of table `{}`",
+            request.table_name
+        ))
+    }
+
+    async fn get_table_partitions(
+        &self,
+        request: GetTablePartitionsRequest,
+    ) -> anyhow::Result<GetTablePartitionsResponse> {
+        let partitions = self
+            .partitions
+            .iter()
+            .filter(
+                |partition| match (&request.time_range, &partition.time_range) {
+                    (Some(requested), Some(range)) => {
+                        range.start_time <= requested.end_time
+                            && range.end_time >= requested.start_time
+                    }
+                    _ => true,
+                },
+            )
+            .cloned()
+            .collect();
+        Ok(GetTablePartitionsResponse {
+            tenant_id: request.tenant_id,
+            table_name: request.table_name,
+            partitions,
+        })
+    }
+}
+
+#[async_trait]
+impl MetaClientProvider for FakeMeta {
+    async fn get_client(&self) -> anyhow::Result<Arc<dyn MetaClient>> {
+        Ok(Arc::new(FakeMeta {
+            partitions: self.partitions.clone(),
+        }))
+    }
+}
@@ -0,0 +1,287 @@
+//! Compiles PQL queries entered at the REPL into plans that the session can execute.
+
+use std::{collections::HashMap, sync::Arc};
+
+use anyhow::anyhow;
+use chrono::{DateTime, Utc};
+use codespan_reporting::term::{
+    self,
+    termcolor::{ColorChoice, StandardStream},
+};
+use datafusion::{
+    catalog::{CatalogList, CatalogProvider, MemoryCatalogList, MemoryCatalogProvider},
+    datasource::provider_as_source,
+    error::DataFusionError,
+    execution::context::SessionState,
+    logical_expr::LogicalPlan,
+    physical_plan::displayable,
+    prelude::{SessionConfig, SessionContext},
+    sql::TableReference,
+};
+use prism_ql::{QueryContext, Report, ResultSet, SourceMap, Visualization};
+
+use crate::estimate::{self, ScanEstimate};
+
+/// The context of a query issued from the REPL: the tenant given on the command line, with every other setting left at
+/// its default.
+pub struct ReplQueryContext {
+    tenant_id: String,
+    start_time: DateTime<Utc>,
+}
+
+impl ReplQueryContext {
+    pub fn new(tenant_id: impl Into<String>) -> ReplQueryContext {
+        ReplQueryContext {
+            tenant_id: tenant_id.into(),
+            start_time: Utc::now(),
+        }
+    }
+}
+
+impl QueryContext for ReplQueryContext {
+    fn get_tenant_id(&self) -> &str {
+        &self.tenant_id
+    }
+
+    fn get_query_start_time(&self) -> DateTime<Utc> {
+        self.start_time
+    }
+}
+
+/// A PQL query lowered to plans, along with how it asks for its results to be shown.
+pub struct CompiledQuery {
+    /// The plan of the query, or the input shared by its facets if it ends with `facet`.
+    pub plan: LogicalPlan,
+    pub facets: Vec<ResultSet>,
+    pub visualization: Option<Visualization>,
+    /// Whether the query asks for its plans to be explained rather than for its results.
+    pub explain: bool,
+    pub source_map: SourceMap,
+    /// The diagnostics of the query, which errors raised while executing it are reported against.
+    pub report: Report,
+}
+
+impl CompiledQuery {
+    /// The plans whose results should be shown, in order: each facet under its name, or else the plan of the query.
+    pub fn result_sets(&self) -> Vec<(Option<String>, LogicalPlan)> {
+        if self.facets.is_empty() {
+            return vec![(None, self.plan.clone())];
+        }
+
+        self.facets
+            .iter()
+            .map(|facet| (Some(facet.name.clone()), facet.plan.clone()))
+            .collect()
+    }
+
+    /// Writes a diagnostic for an error raised while executing one of the plans of the query to stderr, pointing at the
+    /// expressions that may have caused it.
+    pub fn report_execution_error(
+        &self,
+        plan: &LogicalPlan,
+        error: &DataFusionError,
+    ) -> anyhow::Result<()> {
+        let mut stderr = StandardStream::stderr(ColorChoice::Auto);
+        self.report
+            .execution_error(&self.source_map, plan, error)
+            .emit(&mut stderr, &term::Config::default())?;
+        Ok(())
+    }
+}
+
+/// Parses and lowers a PQL query against the tenant's tables. Any diagnostics are written to stderr; an error is
+/// returned if the query cannot be lowered.
+pub async fn compile(
+    ctx: &SessionContext,
+    tenant_id: &str,
+    input: &str,
+) -> anyhow::Result<CompiledQuery> {
+    let query = prism_ql::parse(input)?;
+    let table = TableReference::full("prism", tenant_id, query.table.name.as_str());
+    let provider = ctx
+        .table_provider(table)
+        .await
+        .map_err(|_| anyhow!("table `{}` not found", query.table.name))?;
+    let lowered = prism_ql::lower(
+        query,
+        Arc::new(ReplQueryContext::new(tenant_id)),
+        provider_as_source(provider),
+        input,
+        &HashMap::new(),
+    );
+
+    let mut stderr = StandardStream::stderr(ColorChoice::Auto);
+    lowered.report.emit(&mut stderr, &term::Config::default())?;
+    let plan = lowered.plan.ok_or_else(|| anyhow!("query has errors"))?;
+    Ok(CompiledQuery {
+        plan,
+        facets: lowered.facets,
+        visualization: lowered.visualization,
+        explain: lowered.explain,
+        source_map: lowered.source_map,
+        report: lowered.report,
+    })
+}
+
+/// Describes what each result set of a query became: the plan lowered from PQL and the plan that DataFusion optimized
+/// it into, each annotated with the stages of the query that produced their nodes, then the physical plan and an
+/// equivalent SQL query. The physical plan is not annotated, as its nodes do not keep the logical nodes they were
+/// planned from, and the output says so.
+pub async fn explain(ctx: &SessionContext, query: CompiledQuery) -> anyhow::Result<String> {
+    let source_map = &query.source_map;
+    let state = ctx.state();
+    let mut out = String::new();
+    for (name, plan) in query.result_sets() {
+        if let Some(name) = name {
+            out.push_str(&format!("{}:\n", name));
+        }
+
+        out.push_str("PQL plan:\n");
+        out.push_str(&source_map.display_annotated(&plan));
+        out.push_str("\nOptimized plan:\n");
+        out.push_str(&source_map.display_annotated(&state.optimize(&plan)?));
+        let physical = state.create_physical_plan(&plan).await?;
+        out.push_str("\nPhysical plan (not annotated with stages):\n");
+        out.push_str(&displayable(physical.as_ref()).indent(true).to_string());
+        out.push_str("\nSQL:\n");
+        match prism_ql::plan_to_sql(&plan) {
+            Ok(sql) => out.push_str(&format!("{}\n", sql)),
+            Err(e) => out.push_str(&format!("(unavailable: {})\n", e)),
+        }
+    }
+
+    Ok(out)
+}
+
+/// Encodes the plan of a query as Substrait, so that it can be cached or run without compiling it again.
+pub fn export(query: &CompiledQuery) -> anyhow::Result<Vec<u8>> {
+    if !query.facets.is_empty() {
+        return Err(anyhow!(
+            "queries that end with `facet` have several plans and cannot be exported"
+        ));
+    }
+
+    Ok(prism_ql::to_substrait(&query.plan)?)
+}
+
+/// Decodes a Substrait plan, such as one written by [`export`], into a plan that the session can execute. The plan may
+/// read only the tenant's tables.
+pub async fn import(
+    ctx: &SessionContext,
+    tenant_id: &str,
+    bytes: &[u8],
+) -> anyhow::Result<LogicalPlan> {
+    let schema = ctx
+        .catalog("prism")
+        .and_then(|catalog| catalog.schema(tenant_id))
+        .ok_or_else(|| anyhow!("tenant `{}` not found", tenant_id))?;
+    let catalog = MemoryCatalogProvider::new();
+    catalog.register_schema(tenant_id, schema)?;
+    let catalogs = MemoryCatalogList::new();
+    catalogs.register_catalog("prism".to_string(), Arc::new(catalog));
+    let state = SessionState::new_with_config_rt_and_catalog_list(
+        SessionConfig::new(),
+        ctx.runtime_env(),
+        Arc::new(catalogs),
+    )
+    .with_serializer_registry(prism_ql::serializer_registry());
+    let mut tenant_ctx = SessionContext::new_with_state(state);
+    Ok(prism_ql::from_substrait(&mut tenant_ctx, bytes).await?)
+}
+
+/// Estimates how much data the result sets of a query will scan, together.
+pub async fn estimate(ctx: &SessionContext, query: &CompiledQuery) -> anyhow::Result<ScanEstimate> {
+    let mut total = ScanEstimate::default();
+    for (_, plan) in query.result_sets() {
+        total
+            .tables
+            .extend(estimate::estimate(ctx, &plan).await?.tables);
+    }
+
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use datafusion::{
+        arrow::{
+            array::Int64Array,
+            datatypes::{DataType, Field, Schema},
+            record_batch::RecordBatch,
+            util::pretty::pretty_format_batches,
+        },
+        catalog::{
+            schema::{MemorySchemaProvider, SchemaProvider},
+            CatalogProvider, MemoryCatalogProvider,
+        },
+        datasource::MemTable,
+        execution::{context::SessionState, runtime_env::RuntimeEnv},
+        prelude::{SessionConfig, SessionContext},
+    };
+    use expect_test::expect;
+
+    use super::{compile, export, import};
+
+    /// A session with two tenants, `acme` and `globex`, whose `http_requests` tables have 3 and 5 rows.
+    fn context() -> SessionContext {
+        let state =
+            SessionState::new_with_config_rt(SessionConfig::new(), Arc::new(RuntimeEnv::default()))
+                .with_query_planner(prism_ql::query_planner())
+                .with_serializer_registry(prism_ql::serializer_registry());
+        let ctx = SessionContext::new_with_state(state);
+        let catalog = MemoryCatalogProvider::new();
+        for (tenant, rows) in [("acme", 3), ("globex", 5)] {
+            let schema = Arc::new(Schema::new(vec![Field::new(
+                "status",
+                DataType::Int64,
+                true,
+            )]));
+            let batch = RecordBatch::try_new(
+                schema.clone(),
+                vec![Arc::new(Int64Array::from_iter_values(0..rows))],
+            )
+            .unwrap();
+            let tables = MemorySchemaProvider::new();
+            tables
+                .register_table(
+                    "http_requests".to_string(),
+                    Arc::new(MemTable::try_new(schema, vec![vec![batch]]).unwrap()),
+                )
+                .unwrap();
+            catalog.register_schema(tenant, Arc::new(tables)).unwrap();
+        }
+        ctx.register_catalog("prism", Arc::new(catalog));
+        ctx
+    }
+
+    #[tokio::test]
+    async fn imports_plans_of_the_tenant() {
+        let ctx = context();
+        let query = compile(&ctx, "globex", "http_requests | count")
+            .await
+            .unwrap();
+        let bytes = export(&query).unwrap();
+
+        let plan = import(&ctx, "globex", &bytes).await.unwrap();
+        let batches = ctx
+            .execute_logical_plan(plan)
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+        expect![[r#"
+            +----------+
+            | COUNT(*) |
+            +----------+
+            | 5        |
+            +----------+"#]]
+        .assert_eq(&pretty_format_batches(&batches).unwrap().to_string());
+
+        let e = import(&ctx, "acme", &bytes).await.unwrap_err();
+        expect!["Error during planning: failed to resolve schema: globex"]
+            .assert_eq(&e.to_string());
+    }
+}
@@ -3,13 +3,15 @@ use std::{any::Any, sync::Arc};
 use datafusion::catalog::{schema::SchemaProvider, CatalogProvider};
 
 use crate::{
-    config::S3Config, meta::provider::MetaClientProvider,
-    providers::schema_provider::PrismSchemaProvider,
+    config::S3Config,
+    meta::provider::MetaClientProvider,
+    providers::{schema_provider::PrismSchemaProvider, table_provider::FooterCache},
 };
 
 pub struct PrismCatalogProvider {
     client_provider: Arc<dyn MetaClientProvider>,
     s3_config: S3Config,
+    footers: FooterCache,
 }
 
 impl PrismCatalogProvider {
@@ -20,6 +22,7 @@ impl PrismCatalogProvider {
         PrismCatalogProvider {
             client_provider,
             s3_config,
+            footers: FooterCache::default(),
         }
     }
 }
@@ -38,6 +41,7 @@ impl CatalogProvider for PrismCatalogProvider {
             name.to_string(),
             self.client_provider.clone(),
             self.s3_config.clone(),
+            self.footers.clone(),
         )))
     }
 }
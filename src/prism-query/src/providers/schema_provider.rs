@@ -8,8 +8,9 @@ use datafusion::{
 };
 
 use crate::{
-    config::S3Config, meta::provider::MetaClientProvider,
-    providers::table_provider::PrismTableProvider,
+    config::S3Config,
+    meta::provider::MetaClientProvider,
+    providers::table_provider::{FooterCache, PrismTableProvider},
 };
 use prism_common_v1::ColumnType;
 use prism_rpc_meta_v1::GetTableSchemaRequest;
@@ -18,6 +19,7 @@ pub struct PrismSchemaProvider {
     tenant: String,
     client_provider: Arc<dyn MetaClientProvider>,
     s3_config: S3Config,
+    footers: FooterCache,
 }
 
 impl PrismSchemaProvider {
@@ -25,11 +27,13 @@ impl PrismSchemaProvider {
         tenant: String,
         client_provider: Arc<dyn MetaClientProvider>,
         s3_config: S3Config,
+        footers: FooterCache,
     ) -> PrismSchemaProvider {
         PrismSchemaProvider {
             tenant,
             client_provider,
             s3_config,
+            footers,
         }
     }
 }
@@ -85,6 +89,7 @@ impl SchemaProvider for PrismSchemaProvider {
             name,
             self.client_provider.clone(),
             self.s3_config.clone(),
+            self.footers.clone(),
         )))
     }
 
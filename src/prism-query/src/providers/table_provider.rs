@@ -1,29 +1,81 @@
-use std::{any::Any, sync::Arc};
+use std::{
+    any::Any,
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
 
 use async_trait::async_trait;
 use chrono::Utc;
 use datafusion::{
-    arrow::datatypes::{Schema, SchemaRef},
-    common::{Constraints, Statistics, ToDFSchema},
+    arrow::datatypes::{DataType, Schema, SchemaRef, TimeUnit},
+    common::{ColumnStatistics, Constraints, Statistics, ToDFSchema},
     datasource::{
+        file_format::parquet::fetch_parquet_metadata,
+        get_statistics_with_limit,
         listing::PartitionedFile,
         physical_plan::{FileScanConfig, ParquetExec},
         TableProvider,
     },
     error::DataFusionError,
     execution::{context::SessionState, object_store::ObjectStoreUrl},
-    logical_expr::{expr_fn, TableType},
+    logical_expr::{
+        expr::Between, expr_fn, BinaryExpr, Operator, TableProviderFilterPushDown, TableType,
+    },
+    parquet::{
+        arrow::parquet_to_arrow_schema,
+        file::{
+            metadata::{ParquetMetaData, RowGroupMetaData},
+            statistics::Statistics as ParquetStatistics,
+        },
+        schema::types::SchemaDescriptor,
+    },
     physical_expr::{create_physical_expr, execution_props::ExecutionProps},
     physical_plan::ExecutionPlan,
     prelude::Expr,
     scalar::ScalarValue,
 };
-use object_store::{path::Path, ObjectMeta};
+use futures::{stream, StreamExt};
+use object_store::{path::Path, ObjectMeta, ObjectStore};
+use rand::Rng;
 
+use prism_common_v1::{Partition, TimeRange};
 use prism_rpc_meta_v1::GetTablePartitionsRequest;
 
 use crate::{config::S3Config, meta::provider::MetaClientProvider};
 
+/// The column whose earliest and latest values Meta records for each partition.
+const TIMESTAMP_COLUMN: &str = "timestamp";
+
+/// The most footers that a [`FooterCache`] holds before it starts over.
+const MAX_CACHED_FOOTERS: usize = 4096;
+
+/// The Parquet footers of partitions, shared by the scans of every query. A partition is never rewritten once Meta
+/// lists it, so its footer never goes stale.
+#[derive(Clone, Default)]
+pub struct FooterCache {
+    footers: Arc<Mutex<HashMap<Path, Arc<ParquetMetaData>>>>,
+}
+
+impl FooterCache {
+    async fn get(
+        &self,
+        store: &dyn ObjectStore,
+        object: &ObjectMeta,
+    ) -> datafusion::common::Result<Arc<ParquetMetaData>> {
+        if let Some(footer) = self.footers.lock().unwrap().get(&object.location) {
+            return Ok(footer.clone());
+        }
+
+        let footer = Arc::new(fetch_parquet_metadata(store, object, None).await?);
+        let mut footers = self.footers.lock().unwrap();
+        if footers.len() >= MAX_CACHED_FOOTERS {
+            footers.clear();
+        }
+        footers.insert(object.location.clone(), footer.clone());
+        Ok(footer)
+    }
+}
+
 pub struct PrismTableProvider {
     schema: Arc<Schema>,
     constraints: Constraints,
@@ -31,6 +83,7 @@ pub struct PrismTableProvider {
     table: String,
     client_provider: Arc<dyn MetaClientProvider>,
     s3_config: S3Config,
+    footers: FooterCache,
 }
 
 impl PrismTableProvider {
@@ -40,6 +93,7 @@ impl PrismTableProvider {
         table: impl AsRef<str>,
         client_provider: Arc<dyn MetaClientProvider>,
         s3_config: S3Config,
+        footers: FooterCache,
     ) -> Self {
         Self {
             schema,
@@ -48,8 +102,281 @@ impl PrismTableProvider {
             table: table.as_ref().to_string(),
             client_provider,
             s3_config,
+            footers,
+        }
+    }
+
+    pub fn tenant(&self) -> &str {
+        &self.tenant
+    }
+
+    pub fn table(&self) -> &str {
+        &self.table
+    }
+
+    /// Asks Meta for the partitions of the table, or only for those that may hold rows in the given time range.
+    pub async fn partitions(
+        &self,
+        time_range: Option<TimeRange>,
+    ) -> datafusion::common::Result<Vec<Partition>> {
+        let client = self
+            .client_provider
+            .get_client()
+            .await
+            .map_err(|e| DataFusionError::Internal(format!("{}", e)))?;
+        let partitions = client
+            .get_table_partitions(GetTablePartitionsRequest {
+                tenant_id: self.tenant.clone(),
+                table_name: self.table.clone(),
+                time_range,
+            })
+            .await
+            .map_err(|e| DataFusionError::Execution(e.to_string()))?
+            .partitions;
+        Ok(partitions)
+    }
+}
+
+/// Reads the statistics of a partition from its Parquet footer: its exact row count, the null count of each column and
+/// the earliest and latest value of each datetime column. DataFusion's own Parquet statistics leave out the bounds of
+/// datetime columns, and Meta only records the time range of a partition to the second, so both are read here.
+async fn file_statistics(
+    store: &dyn ObjectStore,
+    footers: &FooterCache,
+    schema: &Schema,
+    object: &ObjectMeta,
+) -> datafusion::common::Result<Statistics> {
+    let metadata = footers.get(store, object).await?;
+    let file_metadata = metadata.file_metadata();
+    let file_schema = parquet_to_arrow_schema(
+        file_metadata.schema_descr(),
+        file_metadata.key_value_metadata(),
+    )?;
+
+    let row_groups = metadata.row_groups();
+    let column_statistics = schema
+        .fields()
+        .iter()
+        .map(|field| {
+            let Ok(file_field) = file_schema.field_with_name(field.name()) else {
+                // A column that the file lacks is null in every row.
+                return ColumnStatistics {
+                    null_count: Some(file_metadata.num_rows() as usize),
+                    ..Default::default()
+                };
+            };
+
+            let Some(leaf) = leaf_index(file_metadata.schema_descr(), field.name()) else {
+                return ColumnStatistics::default();
+            };
+
+            let chunks: Option<Vec<&ParquetStatistics>> = row_groups
+                .iter()
+                .map(|row_group| row_group.column(leaf).statistics())
+                .collect();
+            let Some(chunks) = chunks else {
+                return ColumnStatistics::default();
+            };
+
+            let null_count = chunks.iter().map(|chunk| chunk.null_count() as usize).sum();
+            let (min_value, max_value) = match field.data_type() {
+                DataType::Timestamp(unit, tz) if file_field.data_type() == field.data_type() => {
+                    // A column without values has null bounds, which leave those of other files untouched.
+                    match timestamp_bounds(row_groups, &chunks) {
+                        Some(bounds) => (
+                            Some(timestamp(unit, tz.clone(), bounds.map(|(min, _)| min))),
+                            Some(timestamp(unit, tz.clone(), bounds.map(|(_, max)| max))),
+                        ),
+                        None => (None, None),
+                    }
+                }
+                _ => (None, None),
+            };
+
+            ColumnStatistics {
+                null_count: Some(null_count),
+                max_value,
+                min_value,
+                distinct_count: None,
+            }
+        })
+        .collect();
+
+    Ok(Statistics {
+        num_rows: Some(file_metadata.num_rows() as usize),
+        total_byte_size: Some(
+            row_groups
+                .iter()
+                .map(|row_group| row_group.total_byte_size() as usize)
+                .sum(),
+        ),
+        column_statistics: Some(column_statistics),
+        is_exact: true,
+    })
+}
+
+/// Returns the index of the Parquet leaf column that holds the top-level column with the given name, or `None` if the
+/// column is nested, as its values are then spread over several leaves.
+fn leaf_index(schema: &SchemaDescriptor, name: &str) -> Option<usize> {
+    let root = schema
+        .root_schema()
+        .get_fields()
+        .iter()
+        .position(|field| field.name() == name)?;
+    if !schema.root_schema().get_fields()[root].is_primitive() {
+        return None;
+    }
+
+    (0..schema.num_columns()).find(|&leaf| schema.get_column_root_idx(leaf) == root)
+}
+
+/// Returns the earliest and latest value of a datetime column from the statistics of its chunk in each row group, or
+/// `Some(None)` if the column has no values. Returns `None` if a chunk that holds values lacks statistics.
+fn timestamp_bounds(
+    row_groups: &[RowGroupMetaData],
+    chunks: &[&ParquetStatistics],
+) -> Option<Option<(i64, i64)>> {
+    let mut bounds: Option<(i64, i64)> = None;
+    for (row_group, chunk) in row_groups.iter().zip(chunks) {
+        match chunk {
+            ParquetStatistics::Int64(chunk) if chunk.has_min_max_set() => {
+                bounds = Some(match bounds {
+                    Some((min, max)) => (min.min(*chunk.min()), max.max(*chunk.max())),
+                    None => (*chunk.min(), *chunk.max()),
+                });
+            }
+            // A chunk whose values are all null has no bounds, and does not affect those of the file.
+            _ if chunk.null_count() == row_group.num_rows() as u64 => {}
+            _ => return None,
         }
     }
+
+    Some(bounds)
+}
+
+fn timestamp(unit: &TimeUnit, tz: Option<Arc<str>>, value: Option<i64>) -> ScalarValue {
+    match unit {
+        TimeUnit::Second => ScalarValue::TimestampSecond(value, tz),
+        TimeUnit::Millisecond => ScalarValue::TimestampMillisecond(value, tz),
+        TimeUnit::Microsecond => ScalarValue::TimestampMicrosecond(value, tz),
+        TimeUnit::Nanosecond => ScalarValue::TimestampNanosecond(value, tz),
+    }
+}
+
+/// The fraction of partitions that a scan reads, given the filters pushed down to it: the product of the fractions of
+/// its samples, or every partition if there are none.
+pub fn sampled_fraction(filters: &[Expr]) -> f64 {
+    filters
+        .iter()
+        .filter_map(prism_ql::sample_fraction)
+        .product::<f64>()
+        .clamp(0.0, 1.0)
+}
+
+/// The earliest and latest instant, in milliseconds, that the filters pushed down to a scan allow in its timestamp
+/// column, or `None` if they do not bound it. A side that the filters leave open is `i64::MIN` or `i64::MAX`.
+pub fn time_range(filters: &[Expr]) -> Option<TimeRange> {
+    let bounds = filters
+        .iter()
+        .filter_map(time_bounds)
+        .reduce(TimeBounds::intersect)?;
+    Some(TimeRange {
+        start_time: bounds.start.unwrap_or(i64::MIN),
+        end_time: bounds.end.unwrap_or(i64::MAX),
+    })
+}
+
+/// The bounds, in milliseconds and inclusive, that a filter puts on the timestamp column.
+#[derive(Clone, Copy, Debug, Default)]
+struct TimeBounds {
+    start: Option<i64>,
+    end: Option<i64>,
+}
+
+impl TimeBounds {
+    fn intersect(self, other: TimeBounds) -> TimeBounds {
+        TimeBounds {
+            start: self.start.max(other.start),
+            end: match (self.end, other.end) {
+                (Some(end), Some(other)) => Some(end.min(other)),
+                (end, other) => end.or(other),
+            },
+        }
+    }
+}
+
+/// Returns the bounds that a filter puts on the timestamp column, if it compares it with constants. Bounds are widened
+/// to whole milliseconds, and strict comparisons are treated as inclusive, so they never leave out a matching row.
+fn time_bounds(filter: &Expr) -> Option<TimeBounds> {
+    let is_timestamp =
+        |expr: &Expr| matches!(expr, Expr::Column(column) if column.name == TIMESTAMP_COLUMN);
+    match filter {
+        Expr::BinaryExpr(BinaryExpr {
+            left,
+            op: Operator::And,
+            right,
+        }) => match (time_bounds(left), time_bounds(right)) {
+            (Some(left), Some(right)) => Some(left.intersect(right)),
+            (left, right) => left.or(right),
+        },
+        Expr::BinaryExpr(BinaryExpr { left, op, right }) => {
+            let (op, value) = match (left.as_ref(), right.as_ref()) {
+                (column, Expr::Literal(value)) if is_timestamp(column) => (*op, value),
+                (Expr::Literal(value), column) if is_timestamp(column) => (op.swap()?, value),
+                _ => return None,
+            };
+
+            let (floor, ceil) = millis(value)?;
+            match op {
+                Operator::Eq => Some(TimeBounds {
+                    start: Some(floor),
+                    end: Some(ceil),
+                }),
+                Operator::Gt | Operator::GtEq => Some(TimeBounds {
+                    start: Some(floor),
+                    end: None,
+                }),
+                Operator::Lt | Operator::LtEq => Some(TimeBounds {
+                    start: None,
+                    end: Some(ceil),
+                }),
+                _ => None,
+            }
+        }
+        Expr::Between(Between {
+            expr,
+            negated: false,
+            low,
+            high,
+        }) if is_timestamp(expr) => {
+            let (Expr::Literal(low), Expr::Literal(high)) = (low.as_ref(), high.as_ref()) else {
+                return None;
+            };
+
+            Some(TimeBounds {
+                start: Some(millis(low)?.0),
+                end: Some(millis(high)?.1),
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Returns a datetime in milliseconds, rounded down and up.
+fn millis(value: &ScalarValue) -> Option<(i64, i64)> {
+    let (value, per_milli) = match value {
+        ScalarValue::TimestampSecond(Some(value), _) => {
+            let millis = value.checked_mul(1000)?;
+            return Some((millis, millis));
+        }
+        ScalarValue::TimestampMillisecond(Some(value), _) => return Some((*value, *value)),
+        ScalarValue::TimestampMicrosecond(Some(value), _) => (*value, 1000),
+        ScalarValue::TimestampNanosecond(Some(value), _) => (*value, 1_000_000),
+        _ => return None,
+    };
+
+    let floor = value.div_euclid(per_milli);
+    Some((floor, floor + i64::from(value.rem_euclid(per_milli) != 0)))
 }
 
 #[async_trait]
@@ -70,48 +397,89 @@ impl TableProvider for PrismTableProvider {
         TableType::Base
     }
 
+    fn supports_filters_pushdown(
+        &self,
+        filters: &[&Expr],
+    ) -> datafusion::common::Result<Vec<TableProviderFilterPushDown>> {
+        // Samples are taken by reading a random subset of partitions, rather than by evaluating the filter on rows.
+        // Filters on the timestamp column narrow down the partitions that Meta lists, but the rows of those partitions
+        // still need filtering.
+        Ok(filters
+            .iter()
+            .map(|filter| {
+                if prism_ql::sample_fraction(filter).is_some() {
+                    TableProviderFilterPushDown::Exact
+                } else if time_bounds(filter).is_some() {
+                    TableProviderFilterPushDown::Inexact
+                } else {
+                    TableProviderFilterPushDown::Unsupported
+                }
+            })
+            .collect())
+    }
+
     async fn scan(
         &self,
-        _state: &SessionState,
+        state: &SessionState,
         projection: Option<&Vec<usize>>,
         filters: &[Expr],
         limit: Option<usize>,
     ) -> datafusion::common::Result<Arc<dyn ExecutionPlan>> {
-        let url_path = format!("s3://{}", self.s3_config.bucket_name);
-        let client = self
-            .client_provider
-            .get_client()
-            .await
-            .map_err(|e| DataFusionError::Internal(format!("{}", e)))?;
-        let partitions = client
-            .get_table_partitions(GetTablePartitionsRequest {
-                tenant_id: self.tenant.clone(),
-                table_name: self.table.clone(),
-                time_range: None,
-            })
-            .await
-            .map_err(|e| DataFusionError::Execution(e.to_string()))?
-            .partitions
+        let object_store_url =
+            ObjectStoreUrl::parse(format!("s3://{}", self.s3_config.bucket_name))?;
+        let fraction = sampled_fraction(filters);
+        let time_range = time_range(filters);
+        let filters: Vec<&Expr> = filters
+            .iter()
+            .filter(|filter| prism_ql::sample_fraction(filter).is_none())
+            .collect();
+        let partitions = self.partitions(time_range).await?;
+        let files = partitions
             .into_iter()
-            .map(|p| {
-                vec![PartitionedFile {
-                    object_meta: ObjectMeta {
-                        size: p.size as usize,
-                        last_modified: Utc::now(),
-                        location: Path::from(p.name),
-                        e_tag: None,
-                    },
-                    partition_values: vec![],
-                    range: None,
-                    extensions: None,
-                }]
+            .filter(|_| rand::thread_rng().gen_bool(fraction))
+            .map(|p| PartitionedFile {
+                object_meta: ObjectMeta {
+                    size: p.size as usize,
+                    last_modified: Utc::now(),
+                    location: Path::from(p.name),
+                    e_tag: None,
+                },
+                partition_values: vec![],
+                range: None,
+                extensions: None,
             })
             .collect::<Vec<_>>();
+
+        // The statistics are exact, so DataFusion can answer queries such as `count(*)` or `max(timestamp)` from them
+        // without reading the files. It cannot once rows are filtered, so footers are only read when nothing but a
+        // sample was pushed down.
+        let (files, statistics) = if filters.is_empty() {
+            let store = state.runtime_env().object_store(&object_store_url)?;
+            let files = stream::iter(files)
+                .map(|file| {
+                    let store = store.clone();
+                    let schema = self.schema.clone();
+                    async move {
+                        let statistics = file_statistics(
+                            store.as_ref(),
+                            &self.footers,
+                            &schema,
+                            &file.object_meta,
+                        )
+                        .await?;
+                        Ok((file, statistics))
+                    }
+                })
+                .buffered(state.config_options().execution.meta_fetch_concurrency);
+            get_statistics_with_limit(files, self.schema.clone(), limit).await?
+        } else {
+            (files, Statistics::default())
+        };
         let config = FileScanConfig {
-            object_store_url: ObjectStoreUrl::parse(url_path)?,
+            object_store_url,
             file_schema: self.schema.clone(),
-            file_groups: partitions,
-            statistics: Statistics::default(),
+            file_groups: files.into_iter().map(|file| vec![file]).collect(),
+            statistics,
             projection: projection.cloned(),
             limit,
             table_partition_cols: vec![],
@@ -122,7 +490,7 @@ impl TableProvider for PrismTableProvider {
         let df_schema = self.schema.clone().to_dfschema()?;
         let props = ExecutionProps::new();
         let and_expr = filters
-            .iter()
+            .into_iter()
             .fold(Expr::Literal(ScalarValue::Boolean(Some(true))), |acc, f| {
                 expr_fn::and(acc, f.clone())
             });
@@ -131,3 +499,194 @@ impl TableProvider for PrismTableProvider {
         Ok(Arc::new(plan))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use bytes::Bytes;
+    use datafusion::{
+        arrow::{
+            array::{ArrayRef, Int64Array, StringArray, StructArray, TimestampMillisecondArray},
+            datatypes::{DataType, Field, Schema, TimeUnit},
+            record_batch::RecordBatch,
+            util::pretty::pretty_format_batches,
+        },
+        logical_expr::{expr_fn, lit},
+        parquet::arrow::ArrowWriter,
+        physical_plan::displayable,
+        prelude::SessionContext,
+        scalar::ScalarValue,
+    };
+    use expect_test::{expect, Expect};
+    use object_store::{memory::InMemory, path::Path, ObjectStore};
+    use prism_common_v1::{Partition, TimeRange};
+    use url::Url;
+
+    use super::{time_range, FooterCache, PrismTableProvider};
+    use crate::{config::S3Config, meta::fake::FakeMeta};
+
+    /// Registers `http_requests` as a Prism table whose partitions are Parquet files in an in-memory bucket, one for
+    /// each of the given lists of timestamps, in milliseconds. Each file begins with a nested column that the table
+    /// does not list, so that the leaf columns of the file are numbered differently from its top-level ones.
+    async fn context(partitions: Vec<Vec<Option<i64>>>) -> SessionContext {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new(
+                "timestamp",
+                DataType::Timestamp(TimeUnit::Millisecond, None),
+                true,
+            ),
+            Field::new("host", DataType::Utf8, true),
+        ]));
+        let store = InMemory::new();
+        let mut listed = vec![];
+        for (i, timestamps) in partitions.into_iter().enumerate() {
+            let hosts = vec![Some("a.example"); timestamps.len()];
+            let sizes: ArrayRef = Arc::new(Int64Array::from(vec![7; timestamps.len()]));
+            let request = StructArray::from(vec![
+                (
+                    Arc::new(Field::new("bytes", DataType::Int64, true)),
+                    sizes.clone(),
+                ),
+                (Arc::new(Field::new("status", DataType::Int64, true)), sizes),
+            ]);
+            let batch = RecordBatch::try_from_iter(vec![
+                ("request", Arc::new(request) as ArrayRef),
+                (
+                    "timestamp",
+                    Arc::new(TimestampMillisecondArray::from(timestamps)),
+                ),
+                ("host", Arc::new(StringArray::from(hosts))),
+            ])
+            .unwrap();
+            let mut buf = vec![];
+            let mut writer = ArrowWriter::try_new(&mut buf, batch.schema(), None).unwrap();
+            writer.write(&batch).unwrap();
+            writer.close().unwrap();
+
+            let name = format!("acme/http_requests/{}.parquet", i);
+            listed.push(Partition {
+                name: name.clone(),
+                size: buf.len() as i64,
+                time_range: None,
+            });
+            store
+                .put(&Path::from(name), Bytes::from(buf))
+                .await
+                .unwrap();
+        }
+
+        let ctx = SessionContext::new();
+        ctx.runtime_env()
+            .register_object_store(&Url::parse("s3://query").unwrap(), Arc::new(store));
+        let provider = PrismTableProvider::new(
+            schema,
+            "acme",
+            "http_requests",
+            Arc::new(FakeMeta { partitions: listed }),
+            S3Config {
+                endpoint: None,
+                bucket_name: "query".to_string(),
+            },
+            FooterCache::default(),
+        );
+        ctx.register_table("http_requests", Arc::new(provider))
+            .unwrap();
+        ctx
+    }
+
+    /// Runs a query, showing its physical plan and then its results.
+    async fn check(ctx: &SessionContext, sql: &str, expect: Expect) {
+        let df = ctx.sql(sql).await.unwrap();
+        let plan = df.clone().create_physical_plan().await.unwrap();
+        let results = df.collect().await.unwrap();
+        expect.assert_eq(&format!(
+            "{}\n{}",
+            displayable(plan.as_ref()).indent(false),
+            pretty_format_batches(&results).unwrap()
+        ));
+    }
+
+    #[tokio::test]
+    async fn answers_from_statistics() {
+        let ctx = context(vec![
+            vec![Some(1698796800000), None, Some(1698800400000)],
+            vec![Some(1698804000000), Some(1698790000000)],
+            vec![None],
+        ])
+        .await;
+        check(
+            &ctx,
+            "SELECT count(*), min(timestamp), max(timestamp) FROM http_requests",
+            expect![[r#"
+                ProjectionExec: expr=[6 as COUNT(*), 1698790000000 as MIN(http_requests.timestamp), 1698804000000 as MAX(http_requests.timestamp)]
+                  EmptyExec: produce_one_row=true
+
+                +----------+------------------------------+------------------------------+
+                | COUNT(*) | MIN(http_requests.timestamp) | MAX(http_requests.timestamp) |
+                +----------+------------------------------+------------------------------+
+                | 6        | 2023-10-31T22:06:40          | 2023-11-01T02:00:00          |
+                +----------+------------------------------+------------------------------+"#]],
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn scans_when_filtered() {
+        let ctx = context(vec![vec![Some(1698796800000)], vec![None]]).await;
+        check(
+            &ctx,
+            "SELECT count(*), min(timestamp), max(timestamp) FROM http_requests WHERE host = 'a.example'",
+            expect![[r#"
+                AggregateExec: mode=Final, gby=[], aggr=[COUNT(*), MIN(http_requests.timestamp), MAX(http_requests.timestamp)]
+                  CoalescePartitionsExec
+                    AggregateExec: mode=Partial, gby=[], aggr=[COUNT(*), MIN(http_requests.timestamp), MAX(http_requests.timestamp)]
+                      ProjectionExec: expr=[timestamp@0 as timestamp]
+                        CoalesceBatchesExec: target_batch_size=8192
+                          FilterExec: host@1 = a.example
+                            ParquetExec: file_groups={2 groups: [[acme/http_requests/0.parquet], [acme/http_requests/1.parquet]]}, projection=[timestamp, host], predicate=true
+
+                +----------+------------------------------+------------------------------+
+                | COUNT(*) | MIN(http_requests.timestamp) | MAX(http_requests.timestamp) |
+                +----------+------------------------------+------------------------------+
+                | 2        | 2023-11-01T00:00:00          | 2023-11-01T00:00:00          |
+                +----------+------------------------------+------------------------------+"#]],
+        )
+        .await;
+    }
+
+    #[test]
+    fn time_range_of_filters() {
+        let nanos = |value| lit(ScalarValue::TimestampNanosecond(Some(value), None));
+        let timestamp = || expr_fn::col("timestamp");
+        assert_eq!(
+            time_range(&[
+                timestamp().gt_eq(nanos(1_500_000)),
+                nanos(9_000_000).gt(timestamp()),
+                expr_fn::col("host").eq(lit("a.example")),
+            ]),
+            Some(TimeRange {
+                start_time: 1,
+                end_time: 9,
+            })
+        );
+        assert_eq!(
+            time_range(&[timestamp().between(nanos(2_000_000), nanos(4_000_001))]),
+            Some(TimeRange {
+                start_time: 2,
+                end_time: 5,
+            })
+        );
+        assert_eq!(
+            time_range(&[timestamp().lt(nanos(0))]),
+            Some(TimeRange {
+                start_time: i64::MIN,
+                end_time: 0,
+            })
+        );
+        assert_eq!(
+            time_range(&[expr_fn::col("host").eq(lit("a.example"))]),
+            None
+        );
+    }
+}